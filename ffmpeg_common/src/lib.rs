@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate log;
 
 extern crate iron;
 extern crate router;
@@ -16,6 +18,8 @@ extern crate websocket;
 extern crate liquid;
 extern crate base64;
 extern crate rand;
+extern crate openssl;
 
 pub mod unsafe_code;
-pub mod networking;
\ No newline at end of file
+pub mod networking;
+pub mod logging;
\ No newline at end of file
@@ -0,0 +1,34 @@
+use std::cell::RefCell;
+
+/// The client a line is about, as its configured identity.
+pub const CLIENT_FIELD: &'static str = "client";
+/// The play a line is about.
+pub const PLAY_FIELD: &'static str = "play";
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<(&'static str, String)>> = RefCell::new(Vec::new());
+}
+
+/// Adds a field to every line this thread logs from now on, replacing any earlier value of it.
+/// Each client is handled on threads of its own, so setting the client once covers all of them.
+pub fn set_log_field<T: ToString>(key: &'static str, value: T) {
+    let value = value.to_string();
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        match context.iter().position(|&(ref item, _)| *item == key) {
+            Some(index) => context[index].1 = value,
+            None => context.push((key, value)),
+        }
+    });
+}
+
+pub fn clear_log_field(key: &'static str) {
+    CONTEXT.with(|context| context.borrow_mut().retain(|&(ref item, _)| *item != key));
+}
+
+/// The fields of the calling thread as `key=value` pairs.
+pub fn format_log_fields() -> String {
+    CONTEXT.with(|context| {
+        context.borrow().iter().map(|&(ref key, ref value)| format!("{}={}", key, value)).collect::<Vec<String>>().join(" ")
+    })
+}
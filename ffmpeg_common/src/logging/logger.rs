@@ -0,0 +1,103 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Write, stderr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use log;
+use log::{Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord};
+use time;
+
+use logging::format_log_fields;
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
+
+/// Targets logged at the configured level. Everything else, like the web framework, only logs warnings and errors.
+const OWN_TARGETS: &'static [&'static str] = &["ffmpeg_common", "sports_record_server", "record_client"];
+
+/// Shared by the server and client configurations, e.g.
+///
+/// ```toml
+/// [logging]
+/// level = "debug"
+/// file = "sr_server.log"
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LogConfiguration {
+    /// One of `off`, `error`, `warn`, `info`, `debug` or `trace`. Per-packet lines are logged at `trace`.
+    #[serde(default = "default_log_level")]
+    level: String,
+    /// Lines are appended here instead of going to stderr.
+    #[serde(default)]
+    file: Option<PathBuf>,
+}
+
+fn default_log_level() -> String {
+    String::from("info")
+}
+
+impl LogConfiguration {
+    pub fn get_level(&self) -> &str {
+        &self.level
+    }
+
+    pub fn get_file(&self) -> Option<&Path> {
+        self.file.as_ref().map(|x| x.as_path())
+    }
+}
+
+impl Default for LogConfiguration {
+    fn default() -> Self {
+        LogConfiguration {
+            level: default_log_level(),
+            file: None,
+        }
+    }
+}
+
+/// Writes one line per record: `<utc time> <level> <target> <thread fields> <message>`.
+struct Logger {
+    level: LogLevelFilter,
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        let own_target = OWN_TARGETS.iter().any(|target| metadata.target().starts_with(target));
+        if own_target {
+            metadata.level() <= self.level
+        } else {
+            metadata.level() <= LogLevel::Warn && metadata.level() <= self.level
+        }
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let fields = format_log_fields();
+        let line = format!("{} {:<5} {}{}{} {}\n", time::now_utc().rfc3339(), record.level(), record.target(), if fields.is_empty() { "" } else { " " }, fields, record.args());
+        match self.file {
+            Some(ref file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_all(line.as_bytes());
+                }
+            },
+            None => {
+                let _ = stderr().write_all(line.as_bytes());
+            },
+        }
+    }
+}
+
+/// Installs the logger for the whole process. Only the first call has any effect.
+pub fn init_logging(conf: &LogConfiguration) -> Result<(), UnsafeError> {
+    let level = LogLevelFilter::from_str(conf.get_level()).map_err(|_| UnsafeError::new(UnsafeErrorKind::LoggingSetup(format!("unknown log level {}", conf.get_level()))))?;
+    let file = match conf.get_file() {
+        Some(path) => Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => None,
+    };
+    log::set_logger(|max_log_level| {
+        max_log_level.set(level);
+        Box::new(Logger { level: level, file: file })
+    }).map_err(|e| UnsafeError::new(UnsafeErrorKind::LoggingSetup(e.to_string())))
+}
@@ -0,0 +1,5 @@
+mod logger;
+mod log_context;
+
+pub use self::logger::*;
+pub use self::log_context::*;
@@ -0,0 +1,28 @@
+use unsafe_code::UnsafeError;
+use networking::{team_key_hmac, secrets_match};
+
+use openssl::rand::rand_bytes;
+
+pub const CHALLENGE_LENGTH: usize = 32;
+
+/// Keeps an answer to a challenge from ever matching the signature of a beacon, which uses the same key.
+const CHALLENGE_CONTEXT: &'static [u8] = b"sports_record client challenge\0";
+
+/// Random bytes the server sends a new client, which has to answer them with the team key.
+pub fn new_challenge() -> Result<Vec<u8>, UnsafeError> {
+    let mut challenge = vec![0u8; CHALLENGE_LENGTH];
+    rand_bytes(&mut challenge)?;
+    Ok(challenge)
+}
+
+/// Proves knowledge of the team key without sending it over the connection.
+pub fn answer_challenge(team_key: &[u8], challenge: &[u8]) -> Result<Vec<u8>, UnsafeError> {
+    let mut data = CHALLENGE_CONTEXT.to_vec();
+    data.extend_from_slice(challenge);
+    team_key_hmac(team_key, &data)
+}
+
+pub fn verify_challenge_answer(team_key: &[u8], challenge: &[u8], answer: &[u8]) -> Result<bool, UnsafeError> {
+    let expected = answer_challenge(team_key, challenge)?;
+    Ok(secrets_match(&expected, answer))
+}
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many exchanges the server runs when a client connects, before any recording starts.
+pub const CLOCK_SYNC_ROUNDS: usize = 8;
+/// How many recent exchanges the offset estimate is chosen from.
+const CLOCK_SYNC_WINDOW: usize = 16;
+
+/// Microseconds since the unix epoch on this machine's clock.
+pub fn wall_clock_micros() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64 * 1_000_000 + (d.subsec_nanos() / 1_000) as i64,
+        Err(e) => -(e.duration().as_secs() as i64 * 1_000_000 + (e.duration().subsec_nanos() / 1_000) as i64),
+    }
+}
+
+/// One NTP-style exchange. The server stamps when it sent the request, the client
+/// stamps when it received it and when it sent the reply; all in wall-clock microseconds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ClockSample {
+    pub server_send: i64,
+    pub client_receive: i64,
+    pub client_send: i64,
+}
+
+impl ClockSample {
+    pub fn new(server_send: i64, client_receive: i64) -> ClockSample {
+        ClockSample {
+            server_send: server_send,
+            client_receive: client_receive,
+            client_send: wall_clock_micros(),
+        }
+    }
+}
+
+/// The estimated offset of a client's clock from the server's.
+/// The sample with the shortest round trip of the last few is used, since its offset has the smallest error bound.
+#[derive(Debug, Clone, Default)]
+pub struct ClockOffset {
+    samples: VecDeque<(i64, i64)>,
+}
+
+impl ClockOffset {
+    pub fn new() -> ClockOffset {
+        ClockOffset::default()
+    }
+
+    /// Folds in a reply the server received at `server_receive`.
+    pub fn add_sample(&mut self, sample: &ClockSample, server_receive: i64) {
+        let offset = ((sample.client_receive - sample.server_send) + (sample.client_send - server_receive)) / 2;
+        let round_trip = (server_receive - sample.server_send) - (sample.client_send - sample.client_receive);
+        if round_trip < 0 {
+            return;
+        }
+        if self.samples.len() == CLOCK_SYNC_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((offset, round_trip));
+    }
+
+    fn best_sample(&self) -> Option<(i64, i64)> {
+        self.samples.iter().min_by_key(|&&(_, round_trip)| round_trip).cloned()
+    }
+
+    /// How far the client's clock is ahead of the server's, in microseconds.
+    pub fn offset(&self) -> Option<i64> {
+        self.best_sample().map(|(offset, _)| offset)
+    }
+
+    pub fn round_trip(&self) -> Option<i64> {
+        self.best_sample().map(|(_, round_trip)| round_trip)
+    }
+
+    /// Converts a client wall-clock time to the server's clock. Unsynced clients are taken at face value.
+    pub fn to_server_time(&self, client_time: i64) -> i64 {
+        client_time - self.offset().unwrap_or(0)
+    }
+}
@@ -0,0 +1,78 @@
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
+use networking::team_key_hmac;
+
+use openssl::memcmp;
+
+pub const BEACON_MAGIC: [u8; 4] = [0xE, 0xE, 0xA, 0xB];
+pub const BEACON_VERSION: u8 = 1;
+pub const BEACON_MAX_LENGTH: usize = 512;
+
+const SIGNATURE_LENGTH: usize = 32;
+
+/// Announcement multicast by the server so clients can find it without configuration.
+///
+/// Layout: `magic[4] version[1] session_code[4] port[2] name_len[1] team_name[name_len] hmac_sha256[32]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryBeacon {
+    pub session_code: u32,
+    pub team_name: String,
+    pub clip_server_port: u16,
+}
+
+impl DiscoveryBeacon {
+    pub fn new(session_code: u32, team_name: &str, clip_server_port: u16) -> DiscoveryBeacon {
+        DiscoveryBeacon {
+            session_code: session_code,
+            team_name: team_name.to_owned(),
+            clip_server_port: clip_server_port,
+        }
+    }
+
+    fn unsigned_bytes(&self) -> Vec<u8> {
+        let name = self.team_name.as_bytes();
+        let name = &name[..name.len().min(255)];
+
+        let mut buf = Vec::with_capacity(12 + name.len() + SIGNATURE_LENGTH);
+        buf.extend_from_slice(&BEACON_MAGIC);
+        buf.push(BEACON_VERSION);
+        buf.extend_from_slice(&[(self.session_code >> 24) as u8, (self.session_code >> 16) as u8, (self.session_code >> 8) as u8, self.session_code as u8]);
+        buf.extend_from_slice(&[(self.clip_server_port >> 8) as u8, self.clip_server_port as u8]);
+        buf.push(name.len() as u8);
+        buf.extend_from_slice(name);
+        buf
+    }
+
+    /// Serializes the beacon and appends an HMAC-SHA256 of its contents keyed with the team key.
+    pub fn to_signed_bytes(&self, team_key: &[u8]) -> Result<Vec<u8>, UnsafeError> {
+        let mut buf = self.unsigned_bytes();
+        let signature = team_key_hmac(team_key, &buf)?;
+        buf.extend_from_slice(&signature);
+        Ok(buf)
+    }
+
+    /// Parses a beacon, rejecting it unless it was signed with the same team key.
+    pub fn from_signed_bytes(buf: &[u8], team_key: &[u8]) -> Result<DiscoveryBeacon, UnsafeError> {
+        if buf.len() < 12 + SIGNATURE_LENGTH || !buf.starts_with(&BEACON_MAGIC) || buf[4] != BEACON_VERSION {
+            return Err(UnsafeError::new(UnsafeErrorKind::InvalidBeacon));
+        }
+
+        let name_len = buf[11] as usize;
+        let body_len = 12 + name_len;
+        if buf.len() != body_len + SIGNATURE_LENGTH {
+            return Err(UnsafeError::new(UnsafeErrorKind::InvalidBeacon));
+        }
+
+        let expected = team_key_hmac(team_key, &buf[..body_len])?;
+        if !memcmp::eq(&expected, &buf[body_len..]) {
+            return Err(UnsafeError::new(UnsafeErrorKind::InvalidBeacon));
+        }
+
+        let team_name = String::from_utf8(buf[12..body_len].to_vec()).map_err(|_| UnsafeError::new(UnsafeErrorKind::InvalidBeacon))?;
+
+        Ok(DiscoveryBeacon {
+            session_code: buf[5..9].iter().fold(0u32, |acc, b| (acc << 8) | (*b as u32)),
+            clip_server_port: ((buf[9] as u16) << 8) | (buf[10] as u16),
+            team_name: team_name,
+        })
+    }
+}
@@ -0,0 +1,5 @@
+/// How often each side sends a heartbeat while it has nothing else to send, in seconds.
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 2;
+
+/// A peer that has sent nothing at all for this long is treated as gone, in seconds.
+pub const HEARTBEAT_TIMEOUT_SECS: u64 = 10;
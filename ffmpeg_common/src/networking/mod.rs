@@ -1,5 +1,19 @@
 mod network_packet;
 mod network_configuration;
+mod wire_format;
+mod discovery_beacon;
+mod team_key;
+mod clock_sync;
+mod heartbeat;
+mod authentication;
+mod tls;
 
 pub use self::network_packet::*;
-pub use self::network_configuration::*;
\ No newline at end of file
+pub use self::network_configuration::*;
+pub use self::wire_format::*;
+pub use self::discovery_beacon::*;
+pub use self::team_key::*;
+pub use self::clock_sync::*;
+pub use self::heartbeat::*;
+pub use self::authentication::*;
+pub use self::tls::*;
\ No newline at end of file
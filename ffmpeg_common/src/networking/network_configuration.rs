@@ -1,17 +1,28 @@
 use std::net::SocketAddr;
 
-use unsafe_code::StreamConfiguration;
+use unsafe_code::{StreamConfiguration, AudioStreamConfiguration};
+
+/// Stream index of the video in every `DataPacket` a client sends.
+pub const VIDEO_STREAM_INDEX: i32 = 0;
+/// Stream index of the audio, when the client captures any.
+pub const AUDIO_STREAM_INDEX: i32 = 1;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NetworkConfiguration {
+    /// Stays the same across reconnects, so the server can give a camera back its old slot.
+    pub client_id: String,
     pub stream_configuration: StreamConfiguration,
+    #[serde(default)]
+    pub audio_configuration: Option<AudioStreamConfiguration>,
     pub websocket_address: SocketAddr,
 }
 
 impl NetworkConfiguration {
-    pub fn new(stream_config: StreamConfiguration, ws_addr: SocketAddr) -> NetworkConfiguration {
+    pub fn new(client_id: String, stream_config: StreamConfiguration, audio_config: Option<AudioStreamConfiguration>, ws_addr: SocketAddr) -> NetworkConfiguration {
         NetworkConfiguration {
+            client_id: client_id,
             stream_configuration: stream_config,
+            audio_configuration: audio_config,
             websocket_address: ws_addr,
         }
     }
@@ -1,10 +1,11 @@
 use std::convert::From;
-use std::io::Write;
+use std::io::{Read, Write};
 
 use unsafe_code::DataPacket;
-use unsafe_code::UnsafeError;
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
 
-use networking::NetworkConfiguration;
+use networking::{NetworkConfiguration, ClockSample};
+use networking::{FrameHeader, PacketType, FLAG_KEYFRAME, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION};
 
 use serde_json;
 
@@ -13,6 +14,43 @@ pub enum NetworkPacket {
     PacketStream(Vec<DataPacket>),
     JSONPayload(NetworkConfiguration),
     PayloadEnd,
+    Acknowledgement(ClientAcknowledgement),
+    ClockSync(ClockSample),
+    /// Sent by the client whenever it has had nothing else to send for a while.
+    Heartbeat,
+    /// The data packets that follow, up to the next `PayloadEnd`, are a spooled copy of this play.
+    Replay(i64),
+    /// The client's answer to the server's team key challenge, the first frame it sends after its hello.
+    Authentication(Vec<u8>),
+    /// The oldest and newest wire protocol versions the client speaks, always the first frame it sends.
+    Hello(u8, u8),
+}
+
+/// Sent by the client to confirm what it actually did with a server instruction.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ClientAcknowledgement {
+    RecordingStarted,
+    RecordingStopped,
+    RecordingFailed,
+}
+
+impl ClientAcknowledgement {
+    fn to_byte(&self) -> u8 {
+        match *self {
+            ClientAcknowledgement::RecordingStarted => 0,
+            ClientAcknowledgement::RecordingStopped => 1,
+            ClientAcknowledgement::RecordingFailed  => 2,
+        }
+    }
+
+    fn from_byte(item: u8) -> Result<ClientAcknowledgement, UnsafeError> {
+        match item {
+            0 => Ok(ClientAcknowledgement::RecordingStarted),
+            1 => Ok(ClientAcknowledgement::RecordingStopped),
+            2 => Ok(ClientAcknowledgement::RecordingFailed),
+            _ => Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader)),
+        }
+    }
 }
 
 impl From<Vec<DataPacket>> for NetworkPacket {
@@ -28,9 +66,238 @@ impl From<NetworkConfiguration> for NetworkPacket {
 }
 
 impl NetworkPacket {
+    /// The hello for the range of versions this build speaks.
+    pub fn hello() -> NetworkPacket {
+        NetworkPacket::Hello(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)
+    }
+
+    /// Writes the packet using the binary wire format of the newest protocol version.
     pub fn write_to(&self, writer: &mut Write) -> Result<(), UnsafeError> {
-        let vec = serde_json::to_vec(self)?;
-        writer.write(&vec)?;
+        self.write_versioned(writer, PROTOCOL_VERSION)
+    }
+
+    /// Writes the packet using the binary wire format of `version`, the one agreed on for the connection.
+    /// A `PacketStream` is written as one frame per contained `DataPacket`.
+    pub fn write_versioned(&self, writer: &mut Write, version: u8) -> Result<(), UnsafeError> {
+        match *self {
+            NetworkPacket::PacketStream(ref pkts) => {
+                for pkt in pkts {
+                    let mut header = FrameHeader::with_version(version, PacketType::Data, pkt.packet.len() as u32);
+                    header.stream_index = pkt.stream_index as u32;
+                    header.pts = pkt.pts;
+                    header.dts = pkt.dts;
+                    header.capture_time = pkt.capture_time;
+                    header.duration = pkt.duration;
+                    if pkt.is_keyframe() {
+                        header.flags |= FLAG_KEYFRAME;
+                    }
+                    header.write_to(writer)?;
+                    writer.write_all(&pkt.packet)?;
+                }
+            },
+            NetworkPacket::JSONPayload(ref cfg) => {
+                let vec = serde_json::to_vec(cfg)?;
+                FrameHeader::with_version(version, PacketType::Configuration, vec.len() as u32).write_to(writer)?;
+                writer.write_all(&vec)?;
+            },
+            NetworkPacket::PayloadEnd => {
+                FrameHeader::with_version(version, PacketType::PayloadEnd, 0).write_to(writer)?;
+            },
+            NetworkPacket::Acknowledgement(ref ack) => {
+                FrameHeader::with_version(version, PacketType::Acknowledgement, 1).write_to(writer)?;
+                writer.write_all(&[ack.to_byte()])?;
+            },
+            NetworkPacket::ClockSync(ref sample) => {
+                let vec = serde_json::to_vec(sample)?;
+                FrameHeader::with_version(version, PacketType::ClockSync, vec.len() as u32).write_to(writer)?;
+                writer.write_all(&vec)?;
+            },
+            NetworkPacket::Heartbeat => {
+                FrameHeader::with_version(version, PacketType::Heartbeat, 0).write_to(writer)?;
+            },
+            NetworkPacket::Replay(ref play_id) => {
+                let vec = serde_json::to_vec(play_id)?;
+                FrameHeader::with_version(version, PacketType::Replay, vec.len() as u32).write_to(writer)?;
+                writer.write_all(&vec)?;
+            },
+            NetworkPacket::Authentication(ref answer) => {
+                FrameHeader::with_version(version, PacketType::Authentication, answer.len() as u32).write_to(writer)?;
+                writer.write_all(answer)?;
+            },
+            NetworkPacket::Hello(min_version, max_version) => {
+                FrameHeader::with_version(version, PacketType::Hello, 2).write_to(writer)?;
+                writer.write_all(&[min_version, max_version])?;
+            },
+        }
+        writer.flush()?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Reads a single frame from the wire. Data frames are returned as a `PacketStream` of length one.
+    pub fn read_from(reader: &mut Read) -> Result<NetworkPacket, UnsafeError> {
+        let header = FrameHeader::read_from(reader)?;
+        let mut payload = vec![0u8; header.payload_length as usize];
+        reader.read_exact(&mut payload)?;
+
+        match header.packet_type {
+            PacketType::Data => {
+                Ok(NetworkPacket::PacketStream(vec![DataPacket {
+                    packet: payload,
+                    pts: header.pts,
+                    dts: header.dts,
+                    capture_time: header.capture_time,
+                    duration: header.duration,
+                    stream_index: header.stream_index as i32,
+                    flags: if header.flags & FLAG_KEYFRAME != 0 { DataPacket::KEYFRAME_FLAG } else { 0 },
+                }]))
+            },
+            PacketType::Configuration => Ok(NetworkPacket::JSONPayload(serde_json::from_slice(&payload)?)),
+            PacketType::PayloadEnd => {
+                if header.payload_length != 0 {
+                    return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+                }
+                Ok(NetworkPacket::PayloadEnd)
+            },
+            PacketType::Acknowledgement => {
+                if header.payload_length != 1 {
+                    return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+                }
+                Ok(NetworkPacket::Acknowledgement(ClientAcknowledgement::from_byte(payload[0])?))
+            },
+            PacketType::ClockSync => Ok(NetworkPacket::ClockSync(serde_json::from_slice(&payload)?)),
+            PacketType::Heartbeat => {
+                if header.payload_length != 0 {
+                    return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+                }
+                Ok(NetworkPacket::Heartbeat)
+            },
+            PacketType::Replay => Ok(NetworkPacket::Replay(serde_json::from_slice(&payload)?)),
+            PacketType::Authentication => Ok(NetworkPacket::Authentication(payload)),
+            PacketType::Hello => {
+                if header.payload_length != 2 {
+                    return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+                }
+                Ok(NetworkPacket::Hello(payload[0], payload[1]))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use networking::HEADER_LENGTH;
+
+    fn data_packet() -> DataPacket {
+        DataPacket {
+            packet: vec![0, 0, 1, 0x65, 0xff],
+            pts: 3003,
+            dts: 0,
+            capture_time: 1_500_000_000_000_000,
+            duration: 1001,
+            stream_index: 0,
+            flags: DataPacket::KEYFRAME_FLAG,
+        }
+    }
+
+    fn round_trip(network_packet: &NetworkPacket) -> NetworkPacket {
+        let mut bytes = Vec::new();
+        network_packet.write_to(&mut bytes).unwrap();
+        let mut reader = &bytes[..];
+        let read = NetworkPacket::read_from(&mut reader).unwrap();
+        assert!(reader.is_empty(), "{} bytes left over", reader.len());
+        read
+    }
+
+    #[test]
+    fn round_trips_data() {
+        match round_trip(&NetworkPacket::PacketStream(vec![data_packet()])) {
+            NetworkPacket::PacketStream(pkts) => {
+                assert_eq!(pkts.len(), 1);
+                let expected = data_packet();
+                assert_eq!(pkts[0].packet, expected.packet);
+                assert_eq!(pkts[0].pts, expected.pts);
+                assert_eq!(pkts[0].dts, expected.dts);
+                assert_eq!(pkts[0].capture_time, expected.capture_time);
+                assert_eq!(pkts[0].duration, expected.duration);
+                assert_eq!(pkts[0].stream_index, expected.stream_index);
+                assert!(pkts[0].is_keyframe());
+            },
+            other => panic!("unexpected packet: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_one_frame_per_data_packet() {
+        let mut bytes = Vec::new();
+        NetworkPacket::PacketStream(vec![data_packet(), data_packet()]).write_to(&mut bytes).unwrap();
+        let mut reader = &bytes[..];
+        for _ in 0..2 {
+            match NetworkPacket::read_from(&mut reader).unwrap() {
+                NetworkPacket::PacketStream(ref pkts) if pkts.len() == 1 => {},
+                other => panic!("unexpected packet: {:?}", other),
+            }
+        }
+        assert!(NetworkPacket::read_from(&mut reader).unwrap_err().is_end_of_stream());
+    }
+
+    #[test]
+    fn round_trips_control_frames() {
+        match round_trip(&NetworkPacket::hello()) {
+            NetworkPacket::Hello(min_version, max_version) => {
+                assert_eq!(min_version, MIN_PROTOCOL_VERSION);
+                assert_eq!(max_version, PROTOCOL_VERSION);
+            },
+            other => panic!("unexpected packet: {:?}", other),
+        }
+        match round_trip(&NetworkPacket::Authentication(vec![1, 2, 3])) {
+            NetworkPacket::Authentication(answer) => assert_eq!(answer, vec![1, 2, 3]),
+            other => panic!("unexpected packet: {:?}", other),
+        }
+        match round_trip(&NetworkPacket::Acknowledgement(ClientAcknowledgement::RecordingStopped)) {
+            NetworkPacket::Acknowledgement(ack) => assert_eq!(ack, ClientAcknowledgement::RecordingStopped),
+            other => panic!("unexpected packet: {:?}", other),
+        }
+        match round_trip(&NetworkPacket::Replay(17)) {
+            NetworkPacket::Replay(play_id) => assert_eq!(play_id, 17),
+            other => panic!("unexpected packet: {:?}", other),
+        }
+        match round_trip(&NetworkPacket::PayloadEnd) {
+            NetworkPacket::PayloadEnd => {},
+            other => panic!("unexpected packet: {:?}", other),
+        }
+        match round_trip(&NetworkPacket::Heartbeat) {
+            NetworkPacket::Heartbeat => {},
+            other => panic!("unexpected packet: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_the_agreed_version() {
+        let mut bytes = Vec::new();
+        NetworkPacket::Heartbeat.write_versioned(&mut bytes, MIN_PROTOCOL_VERSION).unwrap();
+        assert_eq!(bytes[4], MIN_PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn reports_a_payload_cut_off_part_way() {
+        let mut bytes = Vec::new();
+        NetworkPacket::PacketStream(vec![data_packet()]).write_to(&mut bytes).unwrap();
+        for length in HEADER_LENGTH..bytes.len() {
+            let err = NetworkPacket::read_from(&mut &bytes[..length]).unwrap_err();
+            assert!(!err.is_end_of_stream(), "{} bytes read as a clean close", length);
+            assert!(err.is_truncated(), "{} bytes were not reported as truncated", length);
+        }
+    }
+
+    #[test]
+    fn refuses_a_hello_of_the_wrong_length() {
+        let mut bytes = Vec::new();
+        FrameHeader::new(PacketType::Hello, 1).write_to(&mut bytes).unwrap();
+        bytes.push(PROTOCOL_VERSION);
+        match *NetworkPacket::read_from(&mut &bytes[..]).unwrap_err().kind() {
+            UnsafeErrorKind::InvalidFrameHeader => {},
+            ref e => panic!("unexpected error: {}", e),
+        }
+    }
+}
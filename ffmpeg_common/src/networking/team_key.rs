@@ -0,0 +1,27 @@
+use unsafe_code::UnsafeError;
+
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+
+/// What a freshly written configuration holds until the team picks a key of its own.
+pub const PLACEHOLDER_TEAM_KEY: &'static str = "TEAM_KEY";
+
+/// True for a key that has actually been set, rather than left empty or at the placeholder.
+pub fn is_team_key_set(team_key: &str) -> bool {
+    !team_key.is_empty() && team_key != PLACEHOLDER_TEAM_KEY
+}
+
+/// HMAC-SHA256 of `data` keyed with the team key.
+pub fn team_key_hmac(team_key: &[u8], data: &[u8]) -> Result<Vec<u8>, UnsafeError> {
+    let key = PKey::hmac(team_key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+/// Compares a secret in constant time, so how long a wrong guess takes says nothing about how close it was.
+pub fn secrets_match(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && memcmp::eq(expected, given)
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpStream, SocketAddr, Shutdown};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use libc;
+
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
+
+use openssl::ssl::{SslMethod, SslAcceptorBuilder, SslConnectorBuilder, SslStream};
+use openssl::x509::X509_FILETYPE_PEM;
+
+pub use openssl::ssl::{SslAcceptor, SslConnector};
+
+/// Server side TLS for the clip port from a PEM certificate chain and private key.
+pub fn tls_acceptor(certificate_chain: &Path, private_key: &Path) -> Result<SslAcceptor, UnsafeError> {
+    let mut builder = SslAcceptorBuilder::mozilla_intermediate_raw(SslMethod::tls())?;
+    {
+        let context = builder.builder_mut();
+        context.set_certificate_chain_file(certificate_chain)?;
+        context.set_private_key_file(private_key, X509_FILETYPE_PEM)?;
+        context.check_private_key()?;
+    }
+    Ok(builder.build())
+}
+
+/// Client side TLS. Without a CA certificate the server has to present one the system trusts.
+pub fn tls_connector(ca_certificate: Option<&Path>) -> Result<SslConnector, UnsafeError> {
+    let mut builder = SslConnectorBuilder::new(SslMethod::tls())?;
+    if let Some(ca_certificate) = ca_certificate {
+        builder.builder_mut().set_ca_file(ca_certificate)?;
+    }
+    Ok(builder.build())
+}
+
+/// Runs the server side of the handshake on a socket that has not been read from yet.
+pub fn accept_tls(acceptor: &SslAcceptor, stream: TcpStream) -> Result<Connection, UnsafeError> {
+    match acceptor.accept(stream) {
+        Ok(tls) => Connection::from_tls(tls),
+        Err(e) => Err(UnsafeError::new(UnsafeErrorKind::TlsHandshake(e.to_string()))),
+    }
+}
+
+/// Runs the client side of the handshake, checking the certificate against `server_name`.
+pub fn connect_tls(connector: &SslConnector, server_name: &str, stream: TcpStream) -> Result<Connection, UnsafeError> {
+    match connector.connect(server_name, stream) {
+        Ok(tls) => Connection::from_tls(tls),
+        Err(e) => Err(UnsafeError::new(UnsafeErrorKind::TlsHandshake(e.to_string()))),
+    }
+}
+
+/// A connection between a client and the server, in the clear or over TLS. Clones share the
+/// connection, so one thread can read while another writes, as with cloned `TcpStream`s.
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Arc<TlsConnection>),
+}
+
+/// An `SslStream` cannot be read and written from two threads at once, so the session sits behind
+/// a lock and its socket is non-blocking: the lock is only held while OpenSSL works on what is
+/// already there, and waiting for the peer happens on the socket with the lock released.
+pub struct TlsConnection {
+    /// The socket under the session, to wait on and shut down without taking the lock.
+    socket: TcpStream,
+    session: Mutex<SslStream<TcpStream>>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Connection {
+    fn from_tls(tls: SslStream<TcpStream>) -> Result<Connection, UnsafeError> {
+        let socket = tls.get_ref().try_clone()?;
+        // the blocking mode belongs to the socket, so this covers the session's handle too
+        socket.set_nonblocking(true)?;
+        Ok(Connection::Tls(Arc::new(TlsConnection {
+            socket: socket,
+            session: Mutex::new(tls),
+            read_timeout: Mutex::new(None),
+        })))
+    }
+
+    pub fn try_clone(&self) -> io::Result<Connection> {
+        match *self {
+            Connection::Plain(ref stream) => stream.try_clone().map(Connection::Plain),
+            Connection::Tls(ref tls) => Ok(Connection::Tls(tls.clone())),
+        }
+    }
+
+    /// Like `TcpStream::set_read_timeout`, it applies to every clone of the connection.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Connection::Plain(ref stream) => stream.set_read_timeout(timeout),
+            Connection::Tls(ref tls) => {
+                *tls.read_timeout.lock().expect("mutex poisoned") = timeout;
+                Ok(())
+            },
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match *self {
+            Connection::Plain(ref stream) => stream.peer_addr(),
+            Connection::Tls(ref tls) => tls.socket.peer_addr(),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match *self {
+            Connection::Plain(ref stream) => stream.shutdown(how),
+            Connection::Tls(ref tls) => {
+                // best effort, the peer may well be gone already
+                if let Ok(mut session) = tls.session.lock() {
+                    let _ = session.shutdown();
+                }
+                tls.socket.shutdown(how)
+            },
+        }
+    }
+}
+
+impl From<TcpStream> for Connection {
+    fn from(stream: TcpStream) -> Connection {
+        Connection::Plain(stream)
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Connection::Plain(ref mut stream) => stream.read(buf),
+            Connection::Tls(ref tls) => {
+                let timeout = *tls.read_timeout.lock().expect("mutex poisoned");
+                loop {
+                    // the session may hold decrypted data already, so it is asked before the socket
+                    match tls.session.lock().expect("mutex poisoned").read(buf) {
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                        result => return result,
+                    }
+                    wait_for(&tls.socket, libc::POLLIN, timeout)?;
+                }
+            },
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Connection::Plain(ref mut stream) => stream.write(buf),
+            Connection::Tls(ref tls) => loop {
+                match tls.session.lock().expect("mutex poisoned").write(buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    result => return result,
+                }
+                wait_for(&tls.socket, libc::POLLOUT, None)?;
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Connection::Plain(ref mut stream) => stream.flush(),
+            Connection::Tls(ref tls) => tls.session.lock().expect("mutex poisoned").flush(),
+        }
+    }
+}
+
+/// Blocks until the socket is ready for `events`. Running out of `timeout` is reported as
+/// `WouldBlock`, the way a blocking socket reports its read timeout.
+fn wait_for(socket: &TcpStream, events: libc::c_short, timeout: Option<Duration>) -> io::Result<()> {
+    let mut poll_fd = libc::pollfd { fd: socket.as_raw_fd(), events: events, revents: 0 };
+    let millis = match timeout {
+        Some(timeout) => (timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000) as libc::c_int,
+        None => -1,
+    };
+    loop {
+        let ret = unsafe { libc::poll(&mut poll_fd, 1, millis) };
+        if ret > 0 {
+            return Ok(());
+        } else if ret == 0 {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "timed out waiting for the peer"));
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp;
+use std::io;
+use std::io::{Read, Write};
+
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
+
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"SRWP";
+/// Version 2 added the packet duration to the header.
+/// Version 3 added the capture timestamp to the header and clock sync frames.
+/// Version 4 added heartbeat frames and the client identity in the configuration.
+/// Version 5 added replay frames for plays sent again from the client's spool.
+/// Version 6 added the team key challenge answered before the configuration.
+/// Version 7 lets the client send its configuration again between plays and adds the server's rate reports.
+pub const PROTOCOL_VERSION: u8 = 7;
+/// The oldest version this build can still read and write.
+pub const MIN_PROTOCOL_VERSION: u8 = 7;
+
+pub const HEADER_LENGTH: usize = 48;
+pub const MAX_PAYLOAD_LENGTH: u32 = 64 * 1024 * 1024;
+
+pub const FLAG_KEYFRAME: u16 = 0x0001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PacketType {
+    Data,
+    Configuration,
+    PayloadEnd,
+    Hello,
+    Acknowledgement,
+    ClockSync,
+    Heartbeat,
+    Replay,
+    Authentication,
+}
+
+impl PacketType {
+    fn to_byte(&self) -> u8 {
+        match *self {
+            PacketType::Data            => 0,
+            PacketType::Configuration   => 1,
+            PacketType::PayloadEnd      => 2,
+            PacketType::Hello           => 3,
+            PacketType::Acknowledgement => 4,
+            PacketType::ClockSync       => 5,
+            PacketType::Heartbeat       => 6,
+            PacketType::Replay          => 7,
+            PacketType::Authentication  => 8,
+        }
+    }
+
+    fn from_byte(item: u8) -> Result<PacketType, UnsafeError> {
+        match item {
+            0 => Ok(PacketType::Data),
+            1 => Ok(PacketType::Configuration),
+            2 => Ok(PacketType::PayloadEnd),
+            3 => Ok(PacketType::Hello),
+            4 => Ok(PacketType::Acknowledgement),
+            5 => Ok(PacketType::ClockSync),
+            6 => Ok(PacketType::Heartbeat),
+            7 => Ok(PacketType::Replay),
+            8 => Ok(PacketType::Authentication),
+            e => Err(UnsafeError::new(UnsafeErrorKind::UnknownPacketType(e))),
+        }
+    }
+}
+
+/// Fixed size header sent in front of every frame on the wire.
+///
+/// Layout (all integers big-endian):
+/// `magic[4] version[1] type[1] flags[2] stream_index[4] pts[8] dts[8] capture_time[8] duration[8] payload_length[4]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub packet_type: PacketType,
+    pub flags: u16,
+    pub stream_index: u32,
+    pub pts: i64,
+    pub dts: i64,
+    pub capture_time: i64,
+    /// How long the packet plays for, in the same time base as `pts`. 0 if unknown.
+    pub duration: i64,
+    pub payload_length: u32,
+}
+
+impl FrameHeader {
+    pub fn new(packet_type: PacketType, payload_length: u32) -> FrameHeader {
+        FrameHeader::with_version(PROTOCOL_VERSION, packet_type, payload_length)
+    }
+
+    /// A header for a connection that agreed on `version`.
+    pub fn with_version(version: u8, packet_type: PacketType, payload_length: u32) -> FrameHeader {
+        FrameHeader {
+            version: version,
+            packet_type: packet_type,
+            flags: 0,
+            stream_index: 0,
+            pts: 0,
+            dts: 0,
+            capture_time: 0,
+            duration: 0,
+            payload_length: payload_length,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut buf = [0u8; HEADER_LENGTH];
+        buf[0..4].copy_from_slice(&PROTOCOL_MAGIC);
+        buf[4] = self.version;
+        buf[5] = self.packet_type.to_byte();
+        write_be(&mut buf[6..8], self.flags as u64);
+        write_be(&mut buf[8..12], self.stream_index as u64);
+        write_be(&mut buf[12..20], self.pts as u64);
+        write_be(&mut buf[20..28], self.dts as u64);
+        write_be(&mut buf[28..36], self.capture_time as u64);
+        write_be(&mut buf[36..44], self.duration as u64);
+        write_be(&mut buf[44..48], self.payload_length as u64);
+        buf
+    }
+
+    /// Parses a header, refusing anything that does not carry our magic or
+    /// that was produced by a protocol version we do not understand. A hello is read whatever
+    /// version wrote it, since agreeing on one is what it is for.
+    pub fn from_bytes(buf: &[u8; HEADER_LENGTH]) -> Result<FrameHeader, UnsafeError> {
+        if buf[0..4] != PROTOCOL_MAGIC {
+            return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+        }
+        let packet_type = PacketType::from_byte(buf[5])?;
+        if packet_type != PacketType::Hello && !supports_version(buf[4]) {
+            return Err(UnsafeError::new(UnsafeErrorKind::UnsupportedProtocolVersion(buf[4])));
+        }
+
+        let payload_length = read_be(&buf[44..48]) as u32;
+        if payload_length > MAX_PAYLOAD_LENGTH {
+            return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+        }
+
+        Ok(FrameHeader {
+            version: buf[4],
+            packet_type: packet_type,
+            flags: read_be(&buf[6..8]) as u16,
+            stream_index: read_be(&buf[8..12]) as u32,
+            pts: read_be(&buf[12..20]) as i64,
+            dts: read_be(&buf[20..28]) as i64,
+            capture_time: read_be(&buf[28..36]) as i64,
+            duration: read_be(&buf[36..44]) as i64,
+            payload_length: payload_length,
+        })
+    }
+
+    pub fn write_to(&self, writer: &mut Write) -> Result<(), UnsafeError> {
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Reads the next header. Only a stream that ends before the first byte of a header ended
+    /// cleanly; one that ends anywhere after that was cut off.
+    pub fn read_from(reader: &mut Read) -> Result<FrameHeader, UnsafeError> {
+        let mut buf = [0u8; HEADER_LENGTH];
+        loop {
+            match reader.read(&mut buf[..1]) {
+                Ok(0) => return Err(UnsafeError::new(UnsafeErrorKind::EndOfStream)),
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(UnsafeError::from(e)),
+            }
+        }
+        reader.read_exact(&mut buf[1..])?;
+        FrameHeader::from_bytes(&buf)
+    }
+}
+
+/// Whether this build can read and write frames of `version`.
+pub fn supports_version(version: u8) -> bool {
+    version >= MIN_PROTOCOL_VERSION && version <= PROTOCOL_VERSION
+}
+
+/// The newest version both sides speak, given the range the peer sent in its hello, or an error
+/// naming the newest version the peer speaks if the ranges do not overlap.
+pub fn negotiate_version(peer_min: u8, peer_max: u8) -> Result<u8, UnsafeError> {
+    let version = cmp::min(peer_max, PROTOCOL_VERSION);
+    if peer_min > peer_max || version < cmp::max(peer_min, MIN_PROTOCOL_VERSION) {
+        return Err(UnsafeError::new(UnsafeErrorKind::UnsupportedProtocolVersion(peer_max)));
+    }
+    Ok(version)
+}
+
+fn write_be(buf: &mut [u8], value: u64) {
+    let len = buf.len();
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = (value >> (8 * (len - i - 1))) as u8;
+    }
+}
+
+fn read_be(buf: &[u8]) -> u64 {
+    buf.iter().fold(0u64, |acc, byte| (acc << 8) | (*byte as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FrameHeader {
+        let mut header = FrameHeader::new(PacketType::Data, 3);
+        header.flags = FLAG_KEYFRAME;
+        header.stream_index = 1;
+        header.pts = -42;
+        header.dts = 1 << 40;
+        header.capture_time = 1_500_000_000_000_000;
+        header.duration = 1001;
+        header
+    }
+
+    fn header_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        header().write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips_a_header() {
+        let bytes = header_bytes();
+        assert_eq!(bytes.len(), HEADER_LENGTH);
+        assert_eq!(FrameHeader::read_from(&mut &bytes[..]).unwrap(), header());
+    }
+
+    #[test]
+    fn ends_cleanly_before_the_first_byte() {
+        let err = FrameHeader::read_from(&mut &[0u8; 0][..]).unwrap_err();
+        assert!(err.is_end_of_stream());
+    }
+
+    #[test]
+    fn reports_a_header_cut_off_part_way() {
+        let bytes = header_bytes();
+        for length in 1..HEADER_LENGTH {
+            let err = FrameHeader::read_from(&mut &bytes[..length]).unwrap_err();
+            assert!(!err.is_end_of_stream(), "{} bytes read as a clean close", length);
+            assert!(err.is_truncated(), "{} bytes were not reported as truncated", length);
+        }
+    }
+
+    #[test]
+    fn refuses_other_magic() {
+        let mut bytes = header_bytes();
+        bytes[0] = b'X';
+        match *FrameHeader::read_from(&mut &bytes[..]).unwrap_err().kind() {
+            UnsafeErrorKind::InvalidFrameHeader => {},
+            ref e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn refuses_unsupported_versions_except_in_a_hello() {
+        let mut bytes = header_bytes();
+        bytes[4] = PROTOCOL_VERSION + 1;
+        match *FrameHeader::read_from(&mut &bytes[..]).unwrap_err().kind() {
+            UnsafeErrorKind::UnsupportedProtocolVersion(v) => assert_eq!(v, PROTOCOL_VERSION + 1),
+            ref e => panic!("unexpected error: {}", e),
+        }
+
+        bytes[5] = PacketType::Hello.to_byte();
+        assert_eq!(FrameHeader::read_from(&mut &bytes[..]).unwrap().version, PROTOCOL_VERSION + 1);
+    }
+
+    #[test]
+    fn negotiates_the_newest_common_version() {
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(negotiate_version(0, PROTOCOL_VERSION + 5).unwrap(), PROTOCOL_VERSION);
+        assert_eq!(negotiate_version(0, MIN_PROTOCOL_VERSION).unwrap(), MIN_PROTOCOL_VERSION);
+        assert!(negotiate_version(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 5).is_err());
+        assert!(negotiate_version(0, MIN_PROTOCOL_VERSION - 1).is_err());
+        assert!(negotiate_version(PROTOCOL_VERSION, MIN_PROTOCOL_VERSION - 1).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use std::marker::{Send};
+use std::convert::{AsRef, AsMut};
+
+use unsafe_code::{UnsafeError, Codec, CodecId, CodecContext, Rational, Frame, Packet, EncodingCodecContext, AsRawPtr};
+
+use ffmpeg_sys::*;
+
+/// Frame size used for encoders that accept any number of samples per frame.
+const DEFAULT_AUDIO_FRAME_SIZE: i32 = 1024;
+
+/// An audio encoder (AAC or Opus). Kept apart from `EncodingCodecContext` because
+/// cloning an encoder goes through the video-only `StreamConfiguration`.
+pub struct AudioEncodingContext(EncodingCodecContext);
+
+unsafe impl Send for AudioEncodingContext {}
+
+impl AudioEncodingContext {
+
+    unsafe fn allocate_audio_encoding_context(codec_type: CodecId, sample_rate: i32, channels: i32, bit_rate: i64) -> Result<AudioEncodingContext, UnsafeError> {
+        let encoding_codec = Codec::new_encoder(codec_type);
+        let mut temp_context = CodecContext::new_codec_based_context(&encoding_codec);
+
+        {
+            let codec_ref: &AVCodec = encoding_codec.as_ref();
+            let internal_ref: &mut AVCodecContext = temp_context.as_mut();
+
+            internal_ref.sample_rate = sample_rate;
+            internal_ref.channels = channels;
+            internal_ref.channel_layout = av_get_default_channel_layout(channels) as u64;
+            internal_ref.bit_rate = bit_rate;
+            internal_ref.time_base = Rational::new(1, sample_rate).into();
+            // the first supported format is the encoder's native one, e.g. planar float for AAC
+            internal_ref.sample_fmt = if codec_ref.sample_fmts.is_null() { AVSampleFormat::AV_SAMPLE_FMT_FLTP } else { *codec_ref.sample_fmts };
+            // the built-in opus encoder is still marked experimental
+            internal_ref.strict_std_compliance = FF_COMPLIANCE_EXPERIMENTAL;
+        }
+
+        let mut encoding_context = AudioEncodingContext(EncodingCodecContext::new(encoding_codec, temp_context));
+        encoding_context.0.open()?;
+
+        Ok(encoding_context)
+    }
+
+    pub fn create_audio_encoding_context(codec_type: CodecId, sample_rate: i32, channels: i32, bit_rate: i64) -> Result<AudioEncodingContext, UnsafeError> {
+        unsafe {
+            AudioEncodingContext::allocate_audio_encoding_context(codec_type, sample_rate, channels, bit_rate)
+        }
+    }
+
+    /// The number of samples per channel every frame sent to the encoder must hold.
+    pub fn frame_size(&self) -> i32 {
+        match <AudioEncodingContext as AsRef<AVCodecContext>>::as_ref(self).frame_size {
+            0 => DEFAULT_AUDIO_FRAME_SIZE,
+            size => size,
+        }
+    }
+
+    pub fn encode_frame(&mut self, frame: Frame) -> Result<Vec<Packet>, UnsafeError> {
+        self.0.encode_frame(frame)
+    }
+
+    pub fn encode_null_frame(&mut self) -> Result<Vec<Packet>, UnsafeError> {
+        self.0.encode_null_frame()
+    }
+}
+
+impl AsRef<CodecContext> for AudioEncodingContext {
+    fn as_ref(&self) -> &CodecContext {
+        self.0.as_ref()
+    }
+}
+
+impl AsRef<AVCodecContext> for AudioEncodingContext {
+    fn as_ref(&self) -> &AVCodecContext {
+        self.0.as_ref()
+    }
+}
+
+impl AsMut<AVCodecContext> for AudioEncodingContext {
+    fn as_mut(&mut self) -> &mut AVCodecContext {
+        self.0.as_mut()
+    }
+}
+
+impl AsRawPtr<AVCodecContext> for AudioEncodingContext {
+    fn as_ptr(&self) -> *const AVCodecContext {
+        self.0.as_ptr()
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut AVCodecContext {
+        self.0.as_mut_ptr()
+    }
+}
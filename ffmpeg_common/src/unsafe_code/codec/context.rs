@@ -50,6 +50,7 @@ impl CodecContext {
                 internal_ref.height = params.height;
                 internal_ref.width = params.width;
                 internal_ref.time_base = params.time_base.into();
+                internal_ref.framerate = params.frame_rate.into();
                 internal_ref.gop_size = params.gop_size;
                 internal_ref.max_b_frames = params.max_b_frames;
                 internal_ref.pix_fmt = *params.pix_fmt;
@@ -2,7 +2,6 @@ use std::marker::{Send};
 use std::convert::{From, AsRef, AsMut};
 
 use std::ptr;
-use std::ffi::CString;
 
 use unsafe_code::{AsRawPtr, UnsafeError, CodecId, UnsafeErrorKind, Frame, CodecParameters};
 use unsafe_code::format::Stream;
@@ -52,18 +51,6 @@ impl DecodingCodecContext {
 
     pub fn open(&mut self) -> Result<(), UnsafeError> {
         unsafe {
-            if self.as_ref().codec_id == AVCodecID::AV_CODEC_ID_H264 {
-                let preset_string = CString::new("preset").unwrap();
-                let ultrafast = CString::new("ultrafast").unwrap();
-                let crf_string = CString::new("crf").unwrap();
-                let crf_setting = CString::new("28").unwrap();
-                let ret = av_opt_set(self.as_mut_void_ptr(), preset_string.as_ptr(), ultrafast.as_ptr(), 0);
-                let ret2 = av_opt_set(self.as_mut_void_ptr(), crf_string.as_ptr(), crf_setting.as_ptr(), 0);
-                if ret < 0 || ret2 < 0 {
-                    println!("ret 1: {} and ret 2: {}", ret, ret2);
-                }
-            }
-
             let ret = avcodec_open2(self.0.as_mut_ptr(), self.1.as_ptr(), ptr::null_mut());
             if ret < 0 {
                 return Err(UnsafeError::new(UnsafeErrorKind::OpenDecoder(ret)));
@@ -116,6 +103,43 @@ impl DecodingCodecContext {
             self.decode_raw_packet(packet)
         }
     }
+
+    /// Hands a packet to the decoder without asking for a frame back, for decoders that hold
+    /// frames back or return several per packet. Collect them with `receive_frame`.
+    pub fn send_packet(&mut self, packet: &AVPacket) -> Result<(), UnsafeError> {
+        unsafe {
+            let ret = avcodec_send_packet(self.as_mut_ptr(), packet);
+            if ret < 0 {
+                return Err(UnsafeError::new(UnsafeErrorKind::SendPacket(ret)));
+            }
+            Ok(())
+        }
+    }
+
+    /// Tells the decoder no more packets follow, so `receive_frame` gives back the frames it still holds.
+    pub fn send_end_of_stream(&mut self) -> Result<(), UnsafeError> {
+        unsafe {
+            let ret = avcodec_send_packet(self.as_mut_ptr(), ptr::null());
+            if ret < 0 && ret != AVERROR_EOF {
+                return Err(UnsafeError::new(UnsafeErrorKind::SendPacket(ret)));
+            }
+            Ok(())
+        }
+    }
+
+    /// The next decoded frame, or `None` once the decoder needs another packet or has been drained.
+    pub fn receive_frame(&mut self) -> Result<Option<Frame>, UnsafeError> {
+        unsafe {
+            let mut frame = Frame::new();
+            let ret = avcodec_receive_frame(self.as_mut_ptr(), frame.as_mut_ptr());
+            if ret == -11 || ret == AVERROR_EOF {
+                return Ok(None);
+            } else if ret < 0 {
+                return Err(UnsafeError::new(UnsafeErrorKind::ReceiveFrame(ret)));
+            }
+            Ok(Some(frame))
+        }
+    }
 }
 
 impl AsRef<AVCodecContext> for DecodingCodecContext {
@@ -2,10 +2,10 @@ use std::marker::{Send};
 use std::convert::{From, AsRef, AsMut};
 
 use std::ptr;
-use std::ffi::CString;
+use libc;
 
 use unsafe_code::codec::{CodecContext, Codec};
-use unsafe_code::{AsRawPtr, Packet, Frame, UnsafeError, UnsafeErrorKind, CodecId, Rational};
+use unsafe_code::{AsRawPtr, SetOption, Packet, Frame, UnsafeError, UnsafeErrorKind, CodecId, Rational, CodecOptions};
 
 use ffmpeg_sys::*;
 
@@ -42,26 +42,18 @@ impl AsRawPtr<AVCodec> for EncodingCodec {
     } 
 }
 
-pub struct EncodingCodecContext(CodecContext, EncodingCodec);
+/// The options are kept so a cloned encoder is opened with the same settings.
+pub struct EncodingCodecContext(CodecContext, EncodingCodec, CodecOptions);
 
 impl EncodingCodecContext {
     pub fn new(codec: EncodingCodec, context: CodecContext) -> EncodingCodecContext {
-        EncodingCodecContext(context, codec)
+        EncodingCodecContext(context, codec, CodecOptions::new())
     }
 
     pub fn open(&mut self) -> Result<(), UnsafeError> {
+        let options = self.2.clone();
+        self.set_options(&options)?;
         unsafe {
-            if <EncodingCodecContext as AsRef<AVCodecContext>>::as_ref(self).codec_id == AVCodecID::AV_CODEC_ID_H264 {
-                let preset_string = CString::new("preset").unwrap();
-                let ultrafast = CString::new("ultrafast").unwrap();
-                let crf_string = CString::new("crf").unwrap();
-                let crf_setting = CString::new("28").unwrap();
-                let ret = av_opt_set(self.as_mut_void_ptr(), preset_string.as_ptr(), ultrafast.as_ptr(), AV_OPT_SEARCH_CHILDREN);
-                let ret2 = av_opt_set(self.as_mut_void_ptr(), crf_string.as_ptr(), crf_setting.as_ptr(), AV_OPT_SEARCH_CHILDREN);
-                if ret < 0 || ret2 < 0 {
-                    println!("ret 1: {} and ret 2: {}", ret, ret2);
-                }
-            }
             let ret = avcodec_open2(self.0.as_mut_ptr(), self.1.as_ptr(), ptr::null_mut());
             if ret < 0 {
                 return Err(UnsafeError::new(UnsafeErrorKind::OpenEncoder(ret)));
@@ -70,10 +62,10 @@ impl EncodingCodecContext {
         }
     }
     
-    unsafe fn allocate_encoding_context(codec_type: CodecId, height: i32, width: i32, time_base: Rational, gop_size: i32, max_b_frames: i32) -> Result<EncodingCodecContext, UnsafeError> {
+    unsafe fn allocate_encoding_context(codec_type: CodecId, height: i32, width: i32, time_base: Rational, frame_rate: Rational, gop_size: i32, max_b_frames: i32, options: &CodecOptions) -> Result<EncodingCodecContext, UnsafeError> {
         let encoding_codec = Codec::new_encoder(codec_type);
         let temp_context = CodecContext::new_codec_based_context(&encoding_codec);
-        let mut encoding_context = EncodingCodecContext::new(encoding_codec, temp_context);
+        let mut encoding_context = EncodingCodecContext(temp_context, encoding_codec, options.clone());
 
         {
             let internal_ref = <EncodingCodecContext as AsMut<AVCodecContext>>::as_mut(&mut encoding_context);
@@ -82,6 +74,8 @@ impl EncodingCodecContext {
             internal_ref.width = width;
 
             internal_ref.time_base = time_base.into();
+            // without a frame rate, encoders like x264 derive one from the time base, which is wrong for fine-grained capture clocks
+            internal_ref.framerate = frame_rate.into();
 
             internal_ref.gop_size = gop_size;
             internal_ref.max_b_frames = max_b_frames;
@@ -94,9 +88,15 @@ impl EncodingCodecContext {
 
     }
 
-    pub fn create_encoding_context(codec_type: CodecId, height: i32, width: i32, time_base: Rational, gop_size: i32, max_b_frames: i32) -> Result<EncodingCodecContext, UnsafeError> {
+    pub fn create_encoding_context(codec_type: CodecId, height: i32, width: i32, time_base: Rational, frame_rate: Rational, gop_size: i32, max_b_frames: i32) -> Result<EncodingCodecContext, UnsafeError> {
+        EncodingCodecContext::create_encoding_context_with_options(codec_type, height, width, time_base, frame_rate, gop_size, max_b_frames, &CodecOptions::new())
+    }
+
+    /// Creates and opens an encoder, applying `options` (preset, crf, b, tune, profile, ...) beforehand.
+    /// An option the encoder does not know is reported as `OptionRejected`.
+    pub fn create_encoding_context_with_options(codec_type: CodecId, height: i32, width: i32, time_base: Rational, frame_rate: Rational, gop_size: i32, max_b_frames: i32, options: &CodecOptions) -> Result<EncodingCodecContext, UnsafeError> {
         unsafe {
-            EncodingCodecContext::allocate_encoding_context(codec_type, height, width, time_base, gop_size, max_b_frames)
+            EncodingCodecContext::allocate_encoding_context(codec_type, height, width, time_base, frame_rate, gop_size, max_b_frames, options)
         }
     }
 
@@ -132,6 +132,16 @@ impl EncodingCodecContext {
         }
     }
 
+    /// Retargets a running encoder. Encoders that support it, like libx264 when it was opened with
+    /// a bitrate rather than a constant quality, pick the change up with the next frame.
+    pub fn set_bit_rate(&mut self, bit_rate: i64) {
+        let internal_ref = <EncodingCodecContext as AsMut<AVCodecContext>>::as_mut(self);
+        internal_ref.bit_rate = bit_rate;
+        if internal_ref.rc_max_rate > 0 {
+            internal_ref.rc_max_rate = bit_rate;
+        }
+    }
+
     pub fn encode_null_frame(&mut self) -> Result<Vec<Packet>, UnsafeError> {
         unsafe {
             self.encode_raw_frame(Frame::null())
@@ -163,6 +173,12 @@ impl AsMut<AVCodecContext> for EncodingCodecContext {
     }
 }
 
+impl SetOption for EncodingCodecContext {
+    fn as_option_target(&mut self) -> *mut libc::c_void {
+        self.as_mut_void_ptr()
+    }
+}
+
 impl AsRawPtr<AVCodecContext> for EncodingCodecContext {
     fn as_ptr(&self) -> *const AVCodecContext {
         self.0.as_ptr()
@@ -177,7 +193,7 @@ impl Clone for EncodingCodecContext {
     fn clone(&self) -> Self {
         let cloned_codec = self.1.clone();
         let cloned_context = self.0.clone();
-        let mut cloned_encoding_context = EncodingCodecContext(cloned_context, cloned_codec);
+        let mut cloned_encoding_context = EncodingCodecContext(cloned_context, cloned_codec, self.2.clone());
         cloned_encoding_context.open().expect("Cloning an EncodingContext failed");
         cloned_encoding_context
     }
@@ -4,10 +4,12 @@ mod context_storage;
 mod encoder;
 mod decoder;
 mod image_encoding;
+mod audio_encoding;
 
 pub use self::codec::*;
 pub use self::context::*;
 pub use self::context_storage::*;
 pub use self::encoder::*;
 pub use self::decoder::*;
-pub use self::image_encoding::*;
\ No newline at end of file
+pub use self::image_encoding::*;
+pub use self::audio_encoding::*;
\ No newline at end of file
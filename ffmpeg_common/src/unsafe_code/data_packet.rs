@@ -2,12 +2,54 @@
 use std::slice::from_raw_parts;
 
 use unsafe_code::packet::Packet;
+use unsafe_code::Rational;
+
+use ffmpeg_sys::{AV_PKT_FLAG_KEY, AV_NOPTS_VALUE, av_rescale_q};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DataPacket {
     pub packet: Vec<u8>,
     pub pts: i64,
     pub dts: i64,
+    /// When the frame was captured, in microseconds on the client's wall clock. 0 if unknown.
+    #[serde(default)]
+    pub capture_time: i64,
+    /// How long the packet plays for, in the same time base as `pts`. 0 if unknown.
+    #[serde(default)]
+    pub duration: i64,
+    pub stream_index: i32,
+    pub flags: i32,
+}
+
+impl DataPacket {
+    pub const KEYFRAME_FLAG: i32 = AV_PKT_FLAG_KEY as i32;
+
+    pub fn is_keyframe(&self) -> bool {
+        self.flags & DataPacket::KEYFRAME_FLAG != 0
+    }
+
+    /// Sets the capture time from the packet's pts, given the wall-clock time pts 0 was captured at.
+    pub fn stamp_capture_time(&mut self, play_start: i64, time_base: Rational) {
+        if self.pts == AV_NOPTS_VALUE {
+            return;
+        }
+        self.capture_time = play_start + unsafe {
+            av_rescale_q(self.pts, time_base.into(), Rational::new(1, 1_000_000).into())
+        };
+    }
+
+    /// Shifts pts and dts so the packet sits at its capture time relative to `start`, a capture time
+    /// in microseconds. Lets packets of every stream be rebased to the same moment.
+    pub fn rebase_to(&mut self, start: i64, time_base: Rational) {
+        let pts = unsafe {
+            av_rescale_q(self.capture_time - start, Rational::new(1, 1_000_000).into(), time_base.into())
+        };
+        let shift = self.pts - pts;
+        self.pts = pts;
+        if self.dts != AV_NOPTS_VALUE {
+            self.dts = self.dts - shift;
+        }
+    }
 }
 
 impl From<Packet> for DataPacket {
@@ -17,6 +59,10 @@ impl From<Packet> for DataPacket {
                 packet: from_raw_parts(pkt.data, pkt.size as usize).to_vec(),
                 pts: pkt.pts,
                 dts: pkt.dts,
+                capture_time: 0,
+                duration: pkt.duration,
+                stream_index: pkt.stream_index,
+                flags: pkt.flags,
             }
         }
     }
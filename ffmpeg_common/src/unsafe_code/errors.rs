@@ -4,6 +4,7 @@ use std::io;
 use serde_json;
 use std::sync::mpsc::{RecvError, TryRecvError};
 use messenger_plus::stream;
+use openssl::error::ErrorStack;
 
 
 #[derive(Debug)]
@@ -17,12 +18,18 @@ pub enum UnsafeErrorKind {
     SendPacket(i32),
     ReceivePacket(i32),
 
+    AllocateFrame(i32),
+
     OpenInput(i32),
+    OpenOutput(i32),
     FindInputStream,
 
     OpenSWSContext,
     SWSError,
 
+    OpenSWRContext(i32),
+    SWRError(i32),
+
     IOError(io::Error),
     ImageMagickError(&'static str),
 
@@ -30,6 +37,8 @@ pub enum UnsafeErrorKind {
     WriteHeaderError(i32),
     WriteTrailerError(i32),
     WriteVideoFrameError(i32),
+    CopyCodecParameters(i32),
+    AddChapter,
 
     SerdeJsonError(serde_json::Error),
 
@@ -37,6 +46,22 @@ pub enum UnsafeErrorKind {
     TryRecvError(TryRecvError),
 
     ReadMessageError(stream::Error),
+
+    InvalidFrameHeader,
+    UnsupportedProtocolVersion(u8),
+    EndOfStream,
+    UnknownPacketType(u8),
+
+    InvalidBeacon,
+    OpenSSLError(ErrorStack),
+    TlsHandshake(String),
+    Unauthenticated,
+
+    InvalidOption(String),
+    OptionRejected(String, String, i32),
+    UnrecognizedOptions(Vec<String>),
+
+    LoggingSetup(String),
 }
 
 impl fmt::Display for UnsafeErrorKind {
@@ -48,20 +73,38 @@ impl fmt::Display for UnsafeErrorKind {
             &UnsafeErrorKind::ReceivePacket(ref i)        => write!(fmter, "An issue occured while receiving a packet: ERR {}",                             i),
             &UnsafeErrorKind::SendFrame(ref i)            => write!(fmter, "An issue occured while sending a frame: ERR {}",                                i),
             &UnsafeErrorKind::SendPacket(ref i)           => write!(fmter, "An issue occured while sending a packet: ERR {}",                               i),
+            &UnsafeErrorKind::AllocateFrame(ref i)        => write!(fmter, "An issue occured while allocating a frame: ERR {}",                             i),
             &UnsafeErrorKind::OpenInput(ref i)            => write!(fmter, "An issue occured while opening the input: ERR {}",                              i),
+            &UnsafeErrorKind::OpenOutput(ref i)           => write!(fmter, "An issue occured while opening the output: ERR {}",                             i),
             &UnsafeErrorKind::OpenSWSContext              => write!(fmter, "An issue occured setting up SWS"),
             &UnsafeErrorKind::SWSError                    => write!(fmter, "An unknown error occured from SWS. Check the server logs for SWS entries"),
+            &UnsafeErrorKind::OpenSWRContext(ref i)       => write!(fmter, "An issue occured setting up SWR: ERR {}",                                        i),
+            &UnsafeErrorKind::SWRError(ref i)             => write!(fmter, "An issue occured while resampling audio: ERR {}",                               i),
             &UnsafeErrorKind::ImageMagickError(ref e)     => write!(fmter, "{}",                                                                            e),
             &UnsafeErrorKind::IOError(ref e)              => e.fmt(fmter),
             &UnsafeErrorKind::AVIOError(ref e)            => write!(fmter, "An issue occured while trying to open the AVIO file: ERR {}",                   e),
             &UnsafeErrorKind::WriteHeaderError(ref e)     => write!(fmter, "An issue occured while trying to write the header of the AVIO file: ERR {}",    e),
             &UnsafeErrorKind::WriteTrailerError(ref e)         => write!(fmter, "An issue occured while trying to write the trailer of the AVIO file: ERR {}",   e),
             &UnsafeErrorKind::WriteVideoFrameError(ref e) => write!(fmter, "An issue occured while trying to write a video frame to the AVIO file: ERR {}", e),
+            &UnsafeErrorKind::CopyCodecParameters(ref e)  => write!(fmter, "An issue occured while copying the codec parameters of an input stream: ERR {}", e),
+            &UnsafeErrorKind::AddChapter                  => write!(fmter, "A chapter could not be added to the output"),
             &UnsafeErrorKind::SerdeJsonError(ref e)       => write!(fmter, "A Serde Error occured: {}", e),
             &UnsafeErrorKind::RecvError(ref e)            => e.fmt(fmter),
             &UnsafeErrorKind::TryRecvError(ref e)         => e.fmt(fmter),
             &UnsafeErrorKind::ReadMessageError(ref e)     => e.fmt(fmter),
             &UnsafeErrorKind::FindInputStream             => write!(fmter, "A valid input stream wasn't found"),
+            &UnsafeErrorKind::InvalidFrameHeader          => write!(fmter, "Received a frame that did not match the sports_record wire format"),
+            &UnsafeErrorKind::UnsupportedProtocolVersion(ref v) => write!(fmter, "The peer speaks wire protocol version {}, which is not supported", v),
+            &UnsafeErrorKind::EndOfStream                 => write!(fmter, "The peer closed the connection"),
+            &UnsafeErrorKind::UnknownPacketType(ref t)    => write!(fmter, "Received a frame with an unknown packet type: {}", t),
+            &UnsafeErrorKind::InvalidBeacon               => write!(fmter, "Received a discovery beacon that was malformed or not signed with the team key"),
+            &UnsafeErrorKind::OpenSSLError(ref e)         => e.fmt(fmter),
+            &UnsafeErrorKind::TlsHandshake(ref e)         => write!(fmter, "The TLS handshake failed: {}", e),
+            &UnsafeErrorKind::Unauthenticated             => write!(fmter, "The peer did not prove it knows the team key"),
+            &UnsafeErrorKind::InvalidOption(ref k)        => write!(fmter, "The option {} could not be set", k),
+            &UnsafeErrorKind::OptionRejected(ref k, ref v, ref e) => write!(fmter, "libav rejected the option {}={}: ERR {}", k, v, e),
+            &UnsafeErrorKind::UnrecognizedOptions(ref k)  => write!(fmter, "libav did not recognise the options: {}", k.join(", ")),
+            &UnsafeErrorKind::LoggingSetup(ref e)         => write!(fmter, "Logging could not be set up: {}", e),
         }
     }
 }
@@ -75,6 +118,34 @@ impl UnsafeError {
     pub fn new(err_type: UnsafeErrorKind) -> UnsafeError {
         UnsafeError { kind: err_type }
     }
+
+    pub fn kind(&self) -> &UnsafeErrorKind {
+        &self.kind
+    }
+
+    /// True when the peer hung up cleanly between frames.
+    pub fn is_end_of_stream(&self) -> bool {
+        match self.kind {
+            UnsafeErrorKind::EndOfStream => true,
+            _ => false,
+        }
+    }
+
+    /// True when the connection ended part way through a frame.
+    pub fn is_truncated(&self) -> bool {
+        match self.kind {
+            UnsafeErrorKind::IOError(ref e) => e.kind() == io::ErrorKind::UnexpectedEof,
+            _ => false,
+        }
+    }
+
+    /// True when a read gave up because the socket's read timeout passed without any data.
+    pub fn is_timed_out(&self) -> bool {
+        match self.kind {
+            UnsafeErrorKind::IOError(ref e) => e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for UnsafeError {
@@ -121,4 +192,10 @@ impl From<stream::Error> for UnsafeError {
     fn from(err: stream::Error) -> UnsafeError {
         UnsafeError::new(UnsafeErrorKind::ReadMessageError(err))
     }
+}
+
+impl From<ErrorStack> for UnsafeError {
+    fn from(err: ErrorStack) -> UnsafeError {
+        UnsafeError::new(UnsafeErrorKind::OpenSSLError(err))
+    }
 }
\ No newline at end of file
@@ -22,6 +22,19 @@ impl FormatContext {
         }
     }
 
+    /// Allocates an output context for a named muxer such as `hls`, rather than guessing it from the filename.
+    pub fn new_output_with_format(format_name: &str, filename: CString) -> Result<OutputContext, UnsafeError> {
+        unsafe {
+            let mut for_ctx_ptr: *mut AVFormatContext = ptr::null_mut();
+            let c_format_name = CString::new(format_name).map_err(|_| UnsafeError::new(UnsafeErrorKind::InvalidOption(format_name.to_owned())))?;
+            let ret = avformat_alloc_output_context2(&mut for_ctx_ptr, ptr::null_mut(), c_format_name.as_ptr(), filename.as_ptr());
+            if ret < 0 || for_ctx_ptr.is_null() {
+                return Err(UnsafeError::new(UnsafeErrorKind::OpenOutput(ret)));
+            }
+            Ok(OutputContext::from(FormatContext(for_ctx_ptr)))
+        }
+    }
+
     pub fn new_input(input_format: &mut AVInputFormat, input_location: CString) -> Result<InputContext, UnsafeError> {
         unsafe {
             let mut input_context_ptr: *mut AVFormatContext = ptr::null_mut();
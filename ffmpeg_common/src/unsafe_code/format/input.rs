@@ -27,9 +27,21 @@ impl InputContext {
         }
     }
 
+    /// Like `read_input`, but `None` once the input is exhausted or cannot be read, which matters for files.
+    pub fn next_packet(&mut self) -> Option<Packet> {
+        unsafe {
+            let mut pkt = av_packet_alloc();
+            if av_read_frame(self.as_mut_ptr(), pkt) < 0 {
+                av_packet_free(&mut pkt);
+                return None;
+            }
+            Some(Packet::from(pkt))
+        }
+    }
+
     unsafe fn get_specific_stream(&self, stream_num: usize) -> Option<Stream> {
         let input_streams = from_raw_parts(self.streams, self.nb_streams as usize);
-        if input_streams.len() < stream_num {
+        if input_streams.len() <= stream_num {
             None
         } else {
             Some(Stream::from(input_streams[stream_num]))
@@ -1,11 +1,13 @@
 use std::ops::{Deref, DerefMut};
 use std::convert::From;
 use std::ffi::CString;
+use std::mem;
 use std::ptr;
 
 use unsafe_code::format::{FormatContext, Stream};
-use unsafe_code::{UnsafeError, UnsafeErrorKind, CodecContext, AsRawPtr};
+use unsafe_code::{UnsafeError, UnsafeErrorKind, CodecContext, AsRawPtr, Rational};
 use unsafe_code::packet::Packet;
+use unsafe_code::Dictionary;
 
 use ffmpeg_sys::*;
 
@@ -23,6 +25,66 @@ impl OutputContext {
             self.add_new_stream(pars)
         }
     }
+
+    unsafe fn add_copied_stream(&mut self, input: &Stream) -> Result<Stream, UnsafeError> {
+        let stream = avformat_new_stream(self.as_mut_ptr(), ptr::null());
+        let ret = avcodec_parameters_copy((*stream).codecpar, input.codecpar);
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::CopyCodecParameters(ret)));
+        }
+        // the input's tag belongs to its container, the muxer picks its own
+        (*(*stream).codecpar).codec_tag = 0;
+        (*stream).time_base = input.time_base;
+        let mut stream = Stream::from(stream);
+        stream.id = (self.nb_streams - 1) as i32;
+        Ok(stream)
+    }
+
+    /// Adds a stream with the codec parameters of an input stream, so its packets can be remuxed without re-encoding.
+    pub fn copy_stream(&mut self, input: &Stream) -> Result<Stream, UnsafeError> {
+        unsafe {
+            self.add_copied_stream(input)
+        }
+    }
+
+    unsafe fn allocate_chapter(&mut self, id: i32, time_base: Rational, start: i64, end: i64, title: &CString) -> Result<(), UnsafeError> {
+        let chapter = av_mallocz(mem::size_of::<AVChapter>()) as *mut AVChapter;
+        if chapter.is_null() {
+            return Err(UnsafeError::new(UnsafeErrorKind::AddChapter));
+        }
+        (*chapter).id = id;
+        (*chapter).time_base = time_base.into();
+        (*chapter).start = start;
+        (*chapter).end = end;
+        let key = CString::new("title").unwrap();
+        if av_dict_set(&mut (*chapter).metadata, key.as_ptr(), title.as_ptr(), 0) < 0 {
+            free_chapter(chapter);
+            return Err(UnsafeError::new(UnsafeErrorKind::AddChapter));
+        }
+        // freed along with the context, the same as chapters a demuxer read; unlike `av_dynarray_add`
+        // this leaves the chapters already added alone when it fails
+        let context = self.as_mut_ptr();
+        if av_dynarray_add_nofree(&mut (*context).chapters as *mut _ as *mut _, &mut (*context).nb_chapters as *mut _ as *mut i32, chapter as *mut _) < 0 {
+            free_chapter(chapter);
+            return Err(UnsafeError::new(UnsafeErrorKind::AddChapter));
+        }
+        Ok(())
+    }
+
+    /// Marks `title` from `start` to `end` in `time_base`. The mp4 muxer writes chapters with the
+    /// trailer, so they can be added while packets are written.
+    pub fn add_chapter(&mut self, id: i32, time_base: Rational, start: i64, end: i64, title: &str) -> Result<(), UnsafeError> {
+        let title = CString::new(title).map_err(|_| UnsafeError::new(UnsafeErrorKind::AddChapter))?;
+        unsafe {
+            self.allocate_chapter(id, time_base, start, end, &title)
+        }
+    }
+}
+
+/// Frees a chapter that never made it into a context.
+unsafe fn free_chapter(chapter: *mut AVChapter) {
+    av_dict_free(&mut (*chapter).metadata);
+    av_free(chapter as *mut _);
 }
 
 impl OutputContext { 
@@ -42,9 +104,9 @@ impl OutputContext {
         }
     }
 
-    unsafe fn write_header(&mut self) -> Result<(), UnsafeError> {
-        let ret = avformat_write_header(self.as_mut_ptr(), &mut ptr::null_mut());
-        if ret != 0 {
+    unsafe fn write_header(&mut self, options: &mut Dictionary) -> Result<(), UnsafeError> {
+        let ret = avformat_write_header(self.as_mut_ptr(), options.as_mut_ptr());
+        if ret < 0 {
             return Err(UnsafeError::new(UnsafeErrorKind::WriteHeaderError(ret)));
         }
         Ok(())
@@ -52,10 +114,22 @@ impl OutputContext {
 
     pub fn write_video_header(&mut self) -> Result<(), UnsafeError> {
         unsafe {
-            self.write_header()
+            self.write_header(&mut Dictionary::new())
         }
     }
 
+    /// Writes the header with muxer private options, e.g. `movflags=frag_keyframe+empty_moov`.
+    /// Any option the muxer did not consume is reported as `UnrecognizedOptions`.
+    pub fn write_video_header_with_options(&mut self, mut options: Dictionary) -> Result<(), UnsafeError> {
+        unsafe {
+            self.write_header(&mut options)?;
+        }
+        if !options.is_empty() {
+            return Err(UnsafeError::new(UnsafeErrorKind::UnrecognizedOptions(options.keys())));
+        }
+        Ok(())
+    }
+
     unsafe fn write_trailer(&mut self) -> Result<(), UnsafeError> {
         let ret = av_write_trailer(self.as_mut_ptr());
         if ret != 0 {
@@ -25,7 +25,7 @@ impl Stream {
         unsafe {
             let ret = avcodec_parameters_from_context((**self).codecpar, context.as_ptr());
             if ret != 0 {
-                println!("failed to put codec parms into stream: {}", ret);
+                error!("failed to put codec parms into stream: {}", ret);
                 return Err(ret);
             } else {
                 Ok(())
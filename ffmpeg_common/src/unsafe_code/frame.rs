@@ -2,9 +2,10 @@ use std::marker::{Send, Sync};
 use std::convert::{From};
 use std::ops::{Deref, DerefMut};
 
+use std::cmp;
 use std::ptr;
 
-use unsafe_code::AsRawPtr;
+use unsafe_code::{AsRawPtr, PixelFormat, UnsafeError, UnsafeErrorKind};
 
 use ffmpeg_sys::*;
 
@@ -23,6 +24,85 @@ impl Frame {
             Frame(av_frame_alloc())
         }
     }
+
+    /// A frame with picture buffers of its own, ready to be drawn on.
+    pub fn new_video<T: Into<PixelFormat>>(width: i32, height: i32, format: T) -> Result<Frame, UnsafeError> {
+        let format: PixelFormat = format.into();
+        let mut frame = Frame::new();
+        frame.width = width;
+        frame.height = height;
+        frame.format = *format as i32;
+        let ret = unsafe { av_frame_get_buffer(frame.as_mut_ptr(), 32) };
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::AllocateFrame(ret)));
+        }
+        Ok(frame)
+    }
+
+    /// Paints a rectangle of a YUV420P frame in one colour, given as Y, U and V. The rectangle is
+    /// clipped to the frame and snapped to even coordinates, since each chroma sample covers 2x2 pixels.
+    pub fn fill_yuv420p(&mut self, x: i32, y: i32, width: i32, height: i32, color: [u8; 3]) {
+        let (x0, y0, x1, y1) = self.clip_rect(x & !1, y & !1, width, height);
+        for plane in 0..3 {
+            let shift = if plane == 0 { 0 } else { 1 };
+            let linesize = self.linesize[plane] as isize;
+            let data = self.data[plane];
+            for row in (y0 >> shift)..(y1 >> shift) {
+                unsafe {
+                    let start = data.offset(row as isize * linesize + (x0 >> shift) as isize);
+                    ptr::write_bytes(start, color[plane], ((x1 - x0) >> shift) as usize);
+                }
+            }
+        }
+    }
+
+    /// Copies the picture of another YUV420P frame into this one with its top left corner at `x`, `y`,
+    /// snapped to even coordinates. Whatever falls outside this frame is left out.
+    pub fn draw_yuv420p(&mut self, source: &Frame, x: i32, y: i32) {
+        let (x, y) = (x & !1, y & !1);
+        let (x0, y0, x1, y1) = self.clip_rect(x, y, source.width, source.height);
+        for plane in 0..3 {
+            let shift = if plane == 0 { 0 } else { 1 };
+            let (linesize, source_linesize) = (self.linesize[plane] as isize, source.linesize[plane] as isize);
+            let (data, source_data) = (self.data[plane], source.data[plane]);
+            let source_x = ((x0 - x) >> shift) as isize;
+            for row in (y0 >> shift)..(y1 >> shift) {
+                let source_row = (row - (y >> shift)) as isize;
+                unsafe {
+                    let from = source_data.offset(source_row * source_linesize + source_x);
+                    let to = data.offset(row as isize * linesize + (x0 >> shift) as isize);
+                    ptr::copy_nonoverlapping(from, to, ((x1 - x0) >> shift) as usize);
+                }
+            }
+        }
+    }
+
+    /// The part of a rectangle inside the frame as left, top, right and bottom edges, all even.
+    /// A rectangle entirely outside comes back empty.
+    fn clip_rect(&self, x: i32, y: i32, width: i32, height: i32) -> (i32, i32, i32, i32) {
+        let x0 = cmp::max(x, 0);
+        let y0 = cmp::max(y, 0);
+        let x1 = cmp::max(x0, cmp::min(x + width, self.width) & !1);
+        let y1 = cmp::max(y0, cmp::min(y + height, self.height) & !1);
+        (x0, y0, x1, y1)
+    }
+
+    /// When the frame was captured, in the time base of the stream it was decoded from.
+    /// Falls back to the frame's own pts for decoders that don't estimate one.
+    pub fn capture_timestamp(&self) -> i64 {
+        match self.best_effort_timestamp {
+            AV_NOPTS_VALUE => self.pts,
+            pts => pts,
+        }
+    }
+
+    /// A new frame with the same properties and picture. Refcounted buffers are shared,
+    /// anything else is copied.
+    pub fn duplicate(&self) -> Frame {
+        unsafe {
+            Frame(av_frame_clone(self.0))
+        }
+    }
 }
 
 impl AsRawPtr<AVFrame> for Frame {
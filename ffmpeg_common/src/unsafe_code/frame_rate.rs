@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+use unsafe_code::Rational;
+
+use ffmpeg_sys::*;
+
+/// Maps capture timestamps onto the timestamps frames are encoded at, rebased so the first frame sits at 0.
+///
+/// With a constant output rate, frames that arrive before their output slot is due are dropped and gaps
+/// are filled by repeating the previous frame, so the encoder sees one frame per tick. In passthrough
+/// mode the capture timestamps are kept as they are, so variable frame rate sources stay in sync.
+pub struct FrameRateConverter {
+    input_time_base: Rational,
+    output_time_base: Rational,
+    constant_rate: bool,
+    first_pts: Option<i64>,
+    next_pts: i64,
+    max_gap: i64,
+}
+
+impl FrameRateConverter {
+    /// `output_time_base` is one tick of the output frame rate, e.g. `1/30`.
+    pub fn new(input_time_base: Rational, output_time_base: Rational) -> FrameRateConverter {
+        let output: AVRational = output_time_base.into();
+        // gaps longer than a second are treated as a stall and skipped rather than filled
+        let max_gap = if output.num > 0 { (output.den / output.num).max(1) as i64 } else { 1 };
+        FrameRateConverter {
+            input_time_base: input_time_base,
+            output_time_base: output_time_base,
+            constant_rate: true,
+            first_pts: None,
+            next_pts: 0,
+            max_gap: max_gap,
+        }
+    }
+
+    /// Keeps every frame at its capture time, only rebasing it to the start of the play.
+    pub fn passthrough(time_base: Rational) -> FrameRateConverter {
+        FrameRateConverter {
+            input_time_base: time_base,
+            output_time_base: time_base,
+            constant_rate: false,
+            first_pts: None,
+            next_pts: 0,
+            max_gap: 0,
+        }
+    }
+
+    pub fn get_output_time_base(&self) -> Rational {
+        self.output_time_base
+    }
+
+    /// The output timestamps a frame captured at `pts` should be encoded at.
+    /// An empty range drops the frame; more than one timestamp duplicates it.
+    pub fn convert(&mut self, pts: i64) -> Range<i64> {
+        if pts == AV_NOPTS_VALUE {
+            let next_pts = self.next_pts;
+            return self.advance_to(next_pts);
+        }
+
+        let first_pts = *self.first_pts.get_or_insert(pts);
+        let target = unsafe {
+            av_rescale_q(pts - first_pts, self.input_time_base.into(), self.output_time_base.into())
+        };
+
+        if target < self.next_pts {
+            // out of order or early, either way the encoder needs increasing timestamps
+            self.next_pts..self.next_pts
+        } else if !self.constant_rate || target - self.next_pts > self.max_gap {
+            self.next_pts = target;
+            self.advance_to(target)
+        } else {
+            self.advance_to(target)
+        }
+    }
+
+    fn advance_to(&mut self, target: i64) -> Range<i64> {
+        let range = self.next_pts..target + 1;
+        self.next_pts = target + 1;
+        range
+    }
+}
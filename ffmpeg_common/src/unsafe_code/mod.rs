@@ -1,4 +1,5 @@
 pub mod sws;
+pub mod swr;
 pub mod format;
 
 #[macro_use]
@@ -9,6 +10,7 @@ mod utils;
 mod errors;
 mod traits;
 mod frame;
+mod frame_rate;
 mod data_packet;
 mod packet;
 mod stream_config;
@@ -18,6 +20,7 @@ pub use self::utils::*;
 pub use self::errors::*;
 pub use self::traits::*;
 pub use self::frame::*;
+pub use self::frame_rate::*;
 pub use self::data_packet::*;
 pub use self::packet::*;
 pub use self::stream_config::*;
\ No newline at end of file
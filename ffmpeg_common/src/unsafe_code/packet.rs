@@ -99,6 +99,9 @@ impl From<DataPacket> for Packet {
             let _ = data.write(pkt.packet.as_ref());
             packet.pts = pkt.pts;
             packet.dts = pkt.dts;
+            packet.duration = pkt.duration;
+            packet.stream_index = pkt.stream_index;
+            packet.flags = pkt.flags;
 
             packet
         }
@@ -1,7 +1,7 @@
 use std::convert::From;
 use std::net::SocketAddr;
 
-use unsafe_code::{Rational, PixelFormat, CodecId, CodecContext, Codec};
+use unsafe_code::{Rational, PixelFormat, CodecId, CodecContext, Codec, AudioEncodingContext};
 use unsafe_code::format::Stream;
 
 use ffmpeg_sys::*;
@@ -41,12 +41,16 @@ pub struct StreamConfiguration {
     pub pix_fmt: PixelFormat,
     pub codec_id: CodecVariant,
     pub time_base: Rational,
+    /// The nominal frame rate. Timestamps are still what place frames; this only guides rate control.
+    #[serde(default)]
+    pub frame_rate: Rational,
 }
 
 impl<'a> From<&'a AVStream> for StreamConfiguration {
     fn from(stream: &AVStream) -> StreamConfiguration {
         unsafe {
             let stream_codec_context = &*stream.codec;
+            let frame_rate = if stream.avg_frame_rate.num > 0 { stream.avg_frame_rate } else { stream.r_frame_rate };
             StreamConfiguration {
                 height: stream_codec_context.height,
                 width: stream_codec_context.width,
@@ -55,6 +59,7 @@ impl<'a> From<&'a AVStream> for StreamConfiguration {
                 pix_fmt: PixelFormat::from(stream_codec_context.pix_fmt),
                 codec_id: CodecVariant::Decoding(CodecId::from(stream_codec_context.codec_id)),
                 time_base: Rational::from(stream_codec_context.time_base),
+                frame_rate: Rational::from(frame_rate),
             }
         }
     }
@@ -71,6 +76,7 @@ impl<'a> From<&'a AVCodecContext> for StreamConfiguration {
                 pix_fmt: PixelFormat::from(item.pix_fmt),
                 codec_id: CodecVariant::from(&*item.codec),
                 time_base: Rational::from(item.time_base),
+                frame_rate: Rational::from(item.framerate),
             }
         }
     }
@@ -86,4 +92,32 @@ impl <'a> From<&'a Stream> for StreamConfiguration {
     fn from(item: &'a Stream) -> StreamConfiguration {
         StreamConfiguration::from(&**item)
     }
-}
\ No newline at end of file
+}
+
+/// Describes the optional audio stream a client sends next to its video.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct AudioStreamConfiguration {
+    pub codec_id: CodecId,
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub bit_rate: i64,
+    pub time_base: Rational,
+}
+
+impl<'a> From<&'a AVCodecContext> for AudioStreamConfiguration {
+    fn from(item: &'a AVCodecContext) -> AudioStreamConfiguration {
+        AudioStreamConfiguration {
+            codec_id: CodecId::from(item.codec_id),
+            sample_rate: item.sample_rate,
+            channels: item.channels,
+            bit_rate: item.bit_rate,
+            time_base: Rational::from(item.time_base),
+        }
+    }
+}
+
+impl<'a> From<&'a AudioEncodingContext> for AudioStreamConfiguration {
+    fn from(item: &'a AudioEncodingContext) -> AudioStreamConfiguration {
+        AudioStreamConfiguration::from(<AudioEncodingContext as AsRef<AVCodecContext>>::as_ref(item))
+    }
+}
@@ -0,0 +1,3 @@
+mod swr_context;
+
+pub use self::swr_context::*;
\ No newline at end of file
@@ -0,0 +1,161 @@
+use std::marker::{Send};
+use std::ops::{Drop};
+
+use std::ptr;
+use libc::c_void;
+
+use ffmpeg_sys::*;
+use unsafe_code::{AsRawPtr, Frame, UnsafeError, UnsafeErrorKind, AudioEncodingContext};
+
+/// Resamples captured audio into the encoder's format and re-chunks it into
+/// frames of exactly the encoder's frame size.
+pub struct SWRContext(*mut SwrContext, *mut AVAudioFifo, SWRAudioDefinition, i32, i64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct SWRAudioDefinition(pub u64, pub AVSampleFormat, pub i32);
+
+impl SWRAudioDefinition {
+    pub fn new(channel_layout: u64, channels: i32, sample_fmt: AVSampleFormat, sample_rate: i32) -> SWRAudioDefinition {
+        // capture devices often leave the layout unset and only report a channel count
+        let layout = match channel_layout {
+            0 => unsafe { av_get_default_channel_layout(channels) as u64 },
+            layout => layout,
+        };
+        SWRAudioDefinition(layout, sample_fmt, sample_rate)
+    }
+
+    fn channels(&self) -> i32 {
+        unsafe {
+            av_get_channel_layout_nb_channels(self.0)
+        }
+    }
+}
+
+impl<'a> From<&'a AVCodecContext> for SWRAudioDefinition {
+    fn from(ctx: &'a AVCodecContext) -> SWRAudioDefinition {
+        SWRAudioDefinition::new(ctx.channel_layout, ctx.channels, ctx.sample_fmt, ctx.sample_rate)
+    }
+}
+
+unsafe impl Send for SWRContext {}
+
+impl SWRContext {
+    unsafe fn allocate_swr_context(input: SWRAudioDefinition, output: SWRAudioDefinition, frame_size: i32) -> Result<SWRContext, UnsafeError> {
+        let swr = swr_alloc_set_opts(ptr::null_mut(), output.0 as i64, output.1, output.2, input.0 as i64, input.1, input.2, 0, ptr::null_mut());
+        if swr.is_null() {
+            return Err(UnsafeError::new(UnsafeErrorKind::OpenSWRContext(0)));
+        }
+
+        let ret = swr_init(swr);
+        let fifo = av_audio_fifo_alloc(output.1, output.channels(), frame_size);
+        // building the struct first means Drop cleans up whichever half did get allocated
+        let context = SWRContext(swr, fifo, output, frame_size, 0);
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::OpenSWRContext(ret)));
+        }
+        if fifo.is_null() {
+            return Err(UnsafeError::new(UnsafeErrorKind::OpenSWRContext(0)));
+        }
+
+        Ok(context)
+    }
+
+    pub fn new(input: SWRAudioDefinition, encoder: &AudioEncodingContext) -> Result<SWRContext, UnsafeError> {
+        unsafe {
+            SWRContext::allocate_swr_context(input, SWRAudioDefinition::from(<AudioEncodingContext as AsRef<AVCodecContext>>::as_ref(encoder)), encoder.frame_size())
+        }
+    }
+
+    unsafe fn resample_into_fifo(&mut self, frame: &Frame, pts: i64) -> Result<(), UnsafeError> {
+        // samples still queued were captured before this frame; when the frame does not follow on
+        // from them, because the device dropped samples or its clock drifted from the sample rate,
+        // the queue is moved so its samples end where this frame starts
+        let queued = av_audio_fifo_size(self.1) as i64;
+        if (pts - (self.4 + queued)).abs() > self.3 as i64 {
+            self.4 = pts - queued;
+        }
+
+        let mut converted = Frame::new();
+        converted.channel_layout = (self.2).0;
+        converted.format = (self.2).1 as i32;
+        converted.sample_rate = (self.2).2;
+
+        let ret = swr_convert_frame(self.0, converted.as_mut_ptr(), frame.as_ptr());
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::SWRError(ret)));
+        }
+
+        if converted.nb_samples > 0 {
+            let ret = av_audio_fifo_write(self.1, converted.extended_data as *mut *mut c_void, converted.nb_samples);
+            if ret < converted.nb_samples {
+                return Err(UnsafeError::new(UnsafeErrorKind::SWRError(ret)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts a decoded frame and queues its samples until a full encoder frame is available.
+    /// `pts` is when the frame's first sample was captured, in the encoder time base of 1/sample_rate.
+    pub fn push_frame(&mut self, frame: &Frame, pts: i64) -> Result<(), UnsafeError> {
+        unsafe {
+            self.resample_into_fifo(frame, pts)
+        }
+    }
+
+    unsafe fn read_from_fifo(&mut self) -> Result<Option<Frame>, UnsafeError> {
+        if av_audio_fifo_size(self.1) < self.3 {
+            return Ok(None);
+        }
+
+        let mut frame = Frame::new();
+        frame.nb_samples = self.3;
+        frame.channel_layout = (self.2).0;
+        frame.format = (self.2).1 as i32;
+        frame.sample_rate = (self.2).2;
+
+        let ret = av_frame_get_buffer(frame.as_mut_ptr(), 0);
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::SWRError(ret)));
+        }
+
+        let ret = av_audio_fifo_read(self.1, frame.extended_data as *mut *mut c_void, self.3);
+        if ret < self.3 {
+            return Err(UnsafeError::new(UnsafeErrorKind::SWRError(ret)));
+        }
+
+        // the encoder time base is 1/sample_rate, so pts counts samples on from the last capture time
+        frame.pts = self.4;
+        self.4 = self.4 + self.3 as i64;
+        Ok(Some(frame))
+    }
+
+    /// Takes the next full encoder frame, if enough samples have been queued.
+    pub fn pop_frame(&mut self) -> Result<Option<Frame>, UnsafeError> {
+        unsafe {
+            self.read_from_fifo()
+        }
+    }
+}
+
+impl AsRawPtr<SwrContext> for SWRContext {
+    fn as_ptr(&self) -> *const SwrContext {
+        self.0 as *const _
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut SwrContext {
+        self.0
+    }
+}
+
+impl Drop for SWRContext {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.1.is_null() {
+                av_audio_fifo_free(self.1);
+            }
+            if !self.0.is_null() {
+                swr_free(&mut self.0);
+            }
+        }
+    }
+}
@@ -9,8 +9,9 @@ use unsafe_code::{AsRawPtr, Frame, UnsafeError, UnsafeErrorKind};
 
 use unsafe_code::PixelFormat;
 
-pub struct SWSContext(*mut SwsContext, SWSImageDefinition, SWSImageDefinition);
+pub struct SWSContext(*mut SwsContext, SWSImageDefinition, SWSImageDefinition, ScalingAlgorithm);
 
+#[derive(Debug, Clone, Copy)]
 pub struct SWSImageDefinition(pub i32, pub i32, pub PixelFormat);
 
 impl SWSImageDefinition {
@@ -19,11 +20,43 @@ impl SWSImageDefinition {
     }
 }
 
+/// The filter libswscale uses when the output size differs from the input.
+/// Bicubic is a good default for downscaling; `point` and `fast_bilinear` trade quality for speed.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScalingAlgorithm {
+    FastBilinear,
+    Bilinear,
+    Bicubic,
+    Point,
+    Area,
+    Lanczos,
+}
+
+impl ScalingAlgorithm {
+    fn sws_flags(&self) -> i32 {
+        (match *self {
+            ScalingAlgorithm::FastBilinear => SWS_FAST_BILINEAR,
+            ScalingAlgorithm::Bilinear     => SWS_BILINEAR,
+            ScalingAlgorithm::Bicubic      => SWS_BICUBIC,
+            ScalingAlgorithm::Point        => SWS_POINT,
+            ScalingAlgorithm::Area         => SWS_AREA,
+            ScalingAlgorithm::Lanczos      => SWS_LANCZOS,
+        }) as i32
+    }
+}
+
+impl Default for ScalingAlgorithm {
+    fn default() -> Self {
+        ScalingAlgorithm::Bicubic
+    }
+}
+
 unsafe impl Send for SWSContext {}
 
 impl SWSContext {
-    unsafe fn allocate_sws_context(height: i32, width: i32, in_pix_fmt: PixelFormat, out_pix_fmt: PixelFormat) -> Result<*mut SwsContext, UnsafeError> {
-        let cached = sws_getCachedContext(ptr::null_mut(), width, height, *in_pix_fmt, width, height, *out_pix_fmt, SWS_BICUBIC, ptr::null_mut(), ptr::null_mut(), ptr::null());
+    unsafe fn allocate_sws_context(input: &SWSImageDefinition, output: &SWSImageDefinition, algorithm: ScalingAlgorithm) -> Result<*mut SwsContext, UnsafeError> {
+        let cached = sws_getCachedContext(ptr::null_mut(), input.1, input.0, *input.2, output.1, output.0, *output.2, algorithm.sws_flags(), ptr::null_mut(), ptr::null_mut(), ptr::null());
 
         if cached.is_null() {
             return Err(UnsafeError::new(UnsafeErrorKind::OpenSWSContext));
@@ -32,27 +65,42 @@ impl SWSContext {
         Ok(cached)
     }
 
+    /// Converts between pixel formats while keeping the frame size.
     pub fn new<T: Into<PixelFormat> + Copy>(height: i32, width: i32, in_pix_fmt: T, out_pix_fmt: T) -> Result<SWSContext, UnsafeError> {
+        SWSContext::new_scaled(SWSImageDefinition::new(height, width, in_pix_fmt), SWSImageDefinition::new(height, width, out_pix_fmt), ScalingAlgorithm::default())
+    }
+
+    /// Converts frames described by `input` into frames described by `output`, resizing them if the dimensions differ.
+    pub fn new_scaled(input: SWSImageDefinition, output: SWSImageDefinition, algorithm: ScalingAlgorithm) -> Result<SWSContext, UnsafeError> {
         unsafe {
-            match SWSContext::allocate_sws_context(height, width, in_pix_fmt.into(), out_pix_fmt.into()) {
-                Ok(sws) => Ok(SWSContext(sws, SWSImageDefinition::new(height, width, in_pix_fmt.into()), SWSImageDefinition::new(height, width, out_pix_fmt.into()))),
-                Err(e) => Err(e),
-            }
+            let sws = SWSContext::allocate_sws_context(&input, &output, algorithm)?;
+            Ok(SWSContext(sws, input, output, algorithm))
         }
     }
 
+    pub fn get_input(&self) -> &SWSImageDefinition {
+        &self.1
+    }
+
+    pub fn get_output(&self) -> &SWSImageDefinition {
+        &self.2
+    }
+
     unsafe fn scale_using_sws(&mut self, old_frame: &mut Frame, align: i32, pts: i64) -> Result<Frame, UnsafeError> {
         let mut scaled_frame = Frame::new();
-        scaled_frame.width = old_frame.width;
-        scaled_frame.height = old_frame.height;
-        scaled_frame.format = 0;
+        scaled_frame.width = (self.2).1;
+        scaled_frame.height = (self.2).0;
+        scaled_frame.format = *(self.2).2 as i32;
         scaled_frame.pts = pts;
 
         let scaled_frame_data_ptr: *mut *mut u8 = scaled_frame.data.as_mut_ptr();
         let scaled_frame_const_ptr: *const *const u8 = scaled_frame_data_ptr as *const *const u8;
         let scaled_frame_linesize_ptr: *mut i32 = scaled_frame.linesize.as_mut_ptr();
 
-        av_image_alloc(scaled_frame_data_ptr, scaled_frame_linesize_ptr, old_frame.width, old_frame.height, *(self.2).2, align);
+        let ret = av_image_alloc(scaled_frame_data_ptr, scaled_frame_linesize_ptr, (self.2).1, (self.2).0, *(self.2).2, align);
+        if ret < 0 {
+            return Err(UnsafeError::new(UnsafeErrorKind::OpenSWSContext));
+        }
 
         let raw_frame_data_ptr: *const *const u8 = old_frame.data.as_ptr() as *const *const u8;
         let raw_frame_linesize_ptr: *mut i32 = old_frame.linesize.as_mut_ptr();
@@ -62,14 +110,34 @@ impl SWSContext {
         Ok(Frame::from(scaled_frame))
     }
 
-    pub fn change_pixel_format(&mut self, old_frame: &mut Frame, align: i32, pts: i64) -> Result<Frame, UnsafeError> {
+    /// Scales and converts `old_frame` into a newly allocated frame stamped with `pts`.
+    pub fn scale_frame(&mut self, old_frame: &mut Frame, align: i32, pts: i64) -> Result<Frame, UnsafeError> {
+        unsafe {
+            self.scale_using_sws(old_frame, align, pts)
+        }
+    }
+
+    /// Scales and converts `old_frame` into `target`, which already has buffers of this context's
+    /// output size and format, e.g. from `Frame::new_video`. Unlike `scale_frame` nothing is allocated,
+    /// so one target can be reused for every frame.
+    pub fn scale_into(&mut self, old_frame: &mut Frame, target: &mut Frame) -> Result<(), UnsafeError> {
         unsafe {
-            match self.scale_using_sws(old_frame, align, pts) {
-                Ok(frame) => Ok(frame),
-                Err(e) => Err(e),
+            let raw_frame_data_ptr: *const *const u8 = old_frame.data.as_ptr() as *const *const u8;
+            let raw_frame_linesize_ptr: *mut i32 = old_frame.linesize.as_mut_ptr();
+            let target_data_ptr: *const *const u8 = target.data.as_ptr() as *const *const u8;
+            let target_linesize_ptr: *mut i32 = target.linesize.as_mut_ptr();
+
+            let ret = sws_scale(self.as_mut_ptr(), raw_frame_data_ptr, raw_frame_linesize_ptr, 0, old_frame.height, target_data_ptr, target_linesize_ptr);
+            if ret <= 0 {
+                return Err(UnsafeError::new(UnsafeErrorKind::SWSError));
             }
+            Ok(())
         }
     }
+
+    pub fn change_pixel_format(&mut self, old_frame: &mut Frame, align: i32, pts: i64) -> Result<Frame, UnsafeError> {
+        self.scale_frame(old_frame, align, pts)
+    }
 }
 
 impl AsRawPtr<SwsContext> for SWSContext {
@@ -124,6 +192,6 @@ impl Drop for SWSContext {
 
 impl Clone for SWSContext {
     fn clone(&self) -> Self {
-        SWSContext::new((self.1).0, (self.1).1, (self.1).2, (self.2).2).unwrap()
+        SWSContext::new_scaled(self.1, self.2, self.3).unwrap()
     }
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
 mod as_raw;
+mod set_option;
 
-pub use self::as_raw::*;
\ No newline at end of file
+pub use self::as_raw::*;
+pub use self::set_option::*;
\ No newline at end of file
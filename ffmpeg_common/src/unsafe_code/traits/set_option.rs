@@ -0,0 +1,32 @@
+use std::ffi::CString;
+use libc;
+
+use unsafe_code::{UnsafeError, UnsafeErrorKind, CodecOptions};
+
+use ffmpeg_sys::*;
+
+/// Implemented by wrappers around libav structs that start with an `AVClass`,
+/// which is what `av_opt_set` needs to find an option by name.
+pub trait SetOption {
+    fn as_option_target(&mut self) -> *mut libc::c_void;
+
+    fn set_option(&mut self, key: &str, value: &str) -> Result<(), UnsafeError> {
+        let rejected = || UnsafeError::new(UnsafeErrorKind::OptionRejected(key.to_owned(), value.to_owned(), AVERROR_OPTION_NOT_FOUND));
+        let c_key = CString::new(key).map_err(|_| rejected())?;
+        let c_value = CString::new(value).map_err(|_| rejected())?;
+        unsafe {
+            let ret = av_opt_set(self.as_option_target(), c_key.as_ptr(), c_value.as_ptr(), AV_OPT_SEARCH_CHILDREN);
+            if ret < 0 {
+                return Err(UnsafeError::new(UnsafeErrorKind::OptionRejected(key.to_owned(), value.to_owned(), ret)));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_options(&mut self, options: &CodecOptions) -> Result<(), UnsafeError> {
+        for &(ref key, ref value) in options.iter() {
+            self.set_option(key, value)?;
+        }
+        Ok(())
+    }
+}
@@ -23,6 +23,9 @@ impl CodecId {
             AVCodecID::AV_CODEC_ID_RAWVIDEO => "AV_CODEC_ID_RAWVIDEO",
             AVCodecID::AV_CODEC_ID_JPEG2000 => "AV_CODEC_ID_JPEG2000",
             AVCodecID::AV_CODEC_ID_H264     => "AV_CODEC_ID_H264",
+            AVCodecID::AV_CODEC_ID_HEVC     => "AV_CODEC_ID_HEVC",
+            AVCodecID::AV_CODEC_ID_AAC      => "AV_CODEC_ID_AAC",
+            AVCodecID::AV_CODEC_ID_OPUS     => "AV_CODEC_ID_OPUS",
             _                    => "AV_CODEC_ID_NONE",
         }
     }
@@ -35,6 +38,9 @@ impl CodecId {
             "AV_CODEC_ID_RAWVIDEO" => CodecId::from(AVCodecID::AV_CODEC_ID_RAWVIDEO),
             "AV_CODEC_ID_JPEG2000" => CodecId::from(AVCodecID::AV_CODEC_ID_JPEG2000),
             "AV_CODEC_ID_H264"      => CodecId::from(AVCodecID::AV_CODEC_ID_H264),
+            "AV_CODEC_ID_HEVC"     => CodecId::from(AVCodecID::AV_CODEC_ID_HEVC),
+            "AV_CODEC_ID_AAC"      => CodecId::from(AVCodecID::AV_CODEC_ID_AAC),
+            "AV_CODEC_ID_OPUS"     => CodecId::from(AVCodecID::AV_CODEC_ID_OPUS),
             _                      => CodecId::from(AVCodecID::AV_CODEC_ID_NONE),
         }
     }
@@ -48,7 +54,7 @@ impl From<AVCodecID> for CodecId {
 
 impl Into<AVCodecID> for CodecId {
     fn into(self) -> AVCodecID {
-        println!("{:?}", self.0);
+        trace!("{:?}", self.0);
         self.0
     } 
 }
@@ -0,0 +1,79 @@
+use std::ffi::{CString, CStr};
+use std::ptr;
+
+use unsafe_code::{UnsafeError, UnsafeErrorKind};
+
+use ffmpeg_sys::*;
+
+/// An owned `AVDictionary` used to hand options such as `movflags` to libav.
+pub struct Dictionary(*mut AVDictionary);
+
+unsafe impl Send for Dictionary {}
+
+impl Dictionary {
+    pub fn new() -> Dictionary {
+        Dictionary(ptr::null_mut())
+    }
+
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Result<Dictionary, UnsafeError> {
+        let mut dict = Dictionary::new();
+        for &(key, value) in pairs {
+            dict.set(key, value)?;
+        }
+        Ok(dict)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), UnsafeError> {
+        let c_key = CString::new(key).map_err(|_| UnsafeError::new(UnsafeErrorKind::InvalidOption(key.to_owned())))?;
+        let c_value = CString::new(value).map_err(|_| UnsafeError::new(UnsafeErrorKind::InvalidOption(key.to_owned())))?;
+        unsafe {
+            let ret = av_dict_set(&mut self.0, c_key.as_ptr(), c_value.as_ptr(), 0);
+            if ret < 0 {
+                return Err(UnsafeError::new(UnsafeErrorKind::InvalidOption(key.to_owned())));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe {
+            av_dict_count(self.0) as usize
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The keys still in the dictionary. libav removes every option it consumed,
+    /// so after use these are the options nothing recognised.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        unsafe {
+            let empty = CString::new("").unwrap();
+            let mut entry: *mut AVDictionaryEntry = ptr::null_mut();
+            loop {
+                entry = av_dict_get(self.0, empty.as_ptr(), entry, AV_DICT_IGNORE_SUFFIX as i32);
+                if entry.is_null() {
+                    break;
+                }
+                keys.push(CStr::from_ptr((*entry).key).to_string_lossy().into_owned());
+            }
+        }
+        keys
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut *mut AVDictionary {
+        &mut self.0
+    }
+}
+
+impl Drop for Dictionary {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.0.is_null() {
+                av_dict_free(&mut self.0);
+            }
+        }
+    }
+}
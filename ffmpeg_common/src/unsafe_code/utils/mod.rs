@@ -3,9 +3,13 @@ mod av_register;
 mod pixel_fmt;
 mod codec_id;
 mod codec_parameters;
+mod dictionary;
+mod options;
 
 pub use self::rational::*;
 pub use self::av_register::*;
 pub use self::pixel_fmt::*;
 pub use self::codec_id::*;
-pub use self::codec_parameters::*;
\ No newline at end of file
+pub use self::codec_parameters::*;
+pub use self::dictionary::*;
+pub use self::options::*;
\ No newline at end of file
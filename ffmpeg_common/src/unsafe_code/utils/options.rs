@@ -0,0 +1,31 @@
+use std::slice::Iter;
+
+/// An ordered list of libav option names and values, e.g. `preset=veryfast` or `b=4M`.
+/// Applied through `SetOption`, so codec private options such as x264's `crf` are reachable too.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct CodecOptions(Vec<(String, String)>);
+
+impl CodecOptions {
+    pub fn new() -> CodecOptions {
+        CodecOptions(Vec::new())
+    }
+
+    /// Adds an option, replacing any earlier value for the same name.
+    pub fn set<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        let key = key.into();
+        self.0.retain(|&(ref k, _)| k != &key);
+        self.0.push((key, value.into()));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.0.retain(|&(ref k, _)| k != key);
+    }
+
+    pub fn iter(&self) -> Iter<(String, String)> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
@@ -14,6 +14,11 @@ impl Rational {
     pub fn new(num: i32, den: i32) -> Rational {
         Rational(num, den)
     }
+
+    /// Turns a rate into the time base of one of its ticks, e.g. 30/1 fps into 1/30.
+    pub fn invert(&self) -> Rational {
+        Rational(self.1, self.0)
+    }
 }
 
 impl From<AVRational> for Rational {
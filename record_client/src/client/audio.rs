@@ -0,0 +1,79 @@
+use client::AudioConfiguration;
+
+use ffmpeg_common::unsafe_code::{UnsafeError, Packet, DataPacket, DecodingCodecContext, AudioEncodingContext, AudioStreamConfiguration, Rational};
+use ffmpeg_common::unsafe_code::format::Stream;
+use ffmpeg_common::unsafe_code::swr::{SWRContext, SWRAudioDefinition};
+use ffmpeg_common::networking::{AUDIO_STREAM_INDEX, wall_clock_micros};
+
+use ffmpeg_sys::*;
+
+/// Decodes captured audio and re-encodes it for the server.
+/// Audio is encoded continuously alongside the video, so it can be part of the pre-roll;
+/// every packet carries its capture time and is rebased with the video when a play starts.
+/// Capture times follow the device's own timestamps, so a gap in the captured audio stays a gap.
+pub struct AudioPipeline {
+    decoding_context: DecodingCodecContext,
+    encoding_context: AudioEncodingContext,
+    swr_context: SWRContext,
+    sample_rate: i32,
+    /// The time base of the timestamps the device puts on what it captured.
+    device_time_base: Rational,
+    /// The device timestamp of the first frame and the wall-clock time it was read at.
+    started_at: Option<(i64, i64)>,
+}
+
+impl AudioPipeline {
+    pub fn new(stream: &mut Stream, config: &AudioConfiguration) -> Result<AudioPipeline, UnsafeError> {
+        let decoding_context = DecodingCodecContext::create_decoding_context_from_av_stream(stream)?;
+        let encoding_context = AudioEncodingContext::create_audio_encoding_context(config.get_codec().get_codec_id(), config.get_sample_rate(), config.get_channels(), config.get_bit_rate())?;
+        let input = SWRAudioDefinition::from(<DecodingCodecContext as AsRef<AVCodecContext>>::as_ref(&decoding_context));
+        let swr_context = SWRContext::new(input, &encoding_context)?;
+        Ok(AudioPipeline {
+            decoding_context: decoding_context,
+            encoding_context: encoding_context,
+            swr_context: swr_context,
+            sample_rate: config.get_sample_rate(),
+            device_time_base: Rational::from(stream.time_base),
+            started_at: None,
+        })
+    }
+
+    /// Describes the encoded stream so the server can add a matching audio stream to its clips.
+    pub fn stream_configuration(&self) -> AudioStreamConfiguration {
+        AudioStreamConfiguration::from(&self.encoding_context)
+    }
+
+    pub fn transcode_packet(&mut self, packet: &Packet) -> Result<Vec<DataPacket>, UnsafeError> {
+        let frame = self.decoding_context.decode_packet(packet)?;
+        // the first frame is pts 0, so its read time anchors every later capture time
+        let device_pts = frame.capture_timestamp();
+        let (device_start, _) = *self.started_at.get_or_insert((device_pts, wall_clock_micros()));
+        let pts = unsafe {
+            av_rescale_q(device_pts - device_start, self.device_time_base.into(), Rational::new(1, self.sample_rate).into())
+        };
+        let mut pkts = Vec::new();
+        self.swr_context.push_frame(&frame, pts)?;
+        while let Some(resampled) = self.swr_context.pop_frame()? {
+            pkts.extend(self.encoding_context.encode_frame(resampled)?);
+        }
+        Ok(self.audio_data_packets(pkts))
+    }
+
+    /// Drains the encoder when capture ends. Samples short of a full frame are dropped.
+    pub fn finish(&mut self) -> Result<Vec<DataPacket>, UnsafeError> {
+        let pkts = self.encoding_context.encode_null_frame()?;
+        Ok(self.audio_data_packets(pkts))
+    }
+
+    /// The encoder time base is 1/sample_rate, and its pts 0 is when the first samples were captured.
+    fn audio_data_packets(&self, pkts: Vec<Packet>) -> Vec<DataPacket> {
+        let time_base = Rational::new(1, self.sample_rate);
+        let started_at = self.started_at.map(|(_, read_at)| read_at).unwrap_or(0);
+        pkts.into_iter().map(|pkt| {
+            let mut data_packet = DataPacket::from(pkt);
+            data_packet.stream_index = AUDIO_STREAM_INDEX;
+            data_packet.stamp_capture_time(started_at, time_base);
+            data_packet
+        }).collect()
+    }
+}
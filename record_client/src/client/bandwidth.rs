@@ -0,0 +1,289 @@
+use std::cmp;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use client::AdaptiveBitrateConfiguration;
+use ffmpeg_common::networking::NetworkPacket;
+
+/// How often the controller looks at the backlog while a play is sent.
+const ADJUST_INTERVAL_MILLIS: u64 = 1000;
+/// Seconds of video waiting to be sent, at the current bitrate, above which the bitrate is lowered.
+const CONGESTED_BACKLOG_SECS: f64 = 0.5;
+/// Seconds of video waiting to be sent below which the link is keeping up.
+const CLEAR_BACKLOG_SECS: f64 = 0.1;
+const DECREASE_FACTOR: f64 = 0.75;
+const INCREASE_FACTOR: f64 = 1.1;
+/// Lowering the bitrate never goes below this share of what the server says it receives.
+const SERVER_RATE_HEADROOM: f64 = 0.9;
+/// Checks in a row that have to find the link keeping up before anything is raised again, so it does not flap.
+const STABLE_INTERVALS: u32 = 5;
+/// Each resolution step scales both dimensions by this much.
+const RESOLUTION_STEP_FACTOR: f64 = 0.75;
+
+/// What is known about the link to the server, shared by the encoding thread, the uplink and the
+/// thread reading from the server.
+#[derive(Debug, Clone)]
+pub struct Bandwidth {
+    queued_bytes: Arc<AtomicUsize>,
+    /// The last receive rate reported by the server in bytes per second, 0 until it sent one.
+    server_rate: Arc<AtomicUsize>,
+    /// The backlog in bytes past which the uplink leaves out GOPs, 0 when it never should.
+    drop_threshold: Arc<AtomicUsize>,
+}
+
+impl Bandwidth {
+    pub fn new() -> Bandwidth {
+        Bandwidth {
+            queued_bytes: Arc::new(AtomicUsize::new(0)),
+            server_rate: Arc::new(AtomicUsize::new(0)),
+            drop_threshold: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// The encoding thread handed `bytes` of video and audio to the uplink.
+    pub fn queued(&self, bytes: usize) {
+        self.queued_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    /// The uplink took `bytes` off its queue, whether it sent them or not.
+    pub fn dequeued(&self, bytes: usize) {
+        self.queued_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    /// Bytes handed to the uplink that it has not got to yet.
+    pub fn backlog(&self) -> usize {
+        self.queued_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn report_server_rate(&self, bytes_per_second: u64) {
+        self.server_rate.store(bytes_per_second as usize, Ordering::SeqCst);
+    }
+
+    pub fn server_rate(&self) -> Option<usize> {
+        match self.server_rate.load(Ordering::SeqCst) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    pub fn set_drop_threshold(&self, bytes: usize) {
+        self.drop_threshold.store(bytes, Ordering::SeqCst);
+    }
+
+    /// True once the uplink is so far behind that it should leave out the next GOP.
+    pub fn falling_behind(&self) -> bool {
+        let threshold = self.drop_threshold.load(Ordering::SeqCst);
+        threshold > 0 && self.backlog() > threshold
+    }
+}
+
+/// The payload bytes of the data packets in `network_packet`, which is what the backlog counts.
+pub fn payload_bytes(network_packet: &NetworkPacket) -> usize {
+    match *network_packet {
+        NetworkPacket::PacketStream(ref pkts) => pkts.iter().map(|pkt| pkt.packet.len()).sum(),
+        _ => 0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Adjustment {
+    BitRate(i64),
+    /// A new output width and height.
+    Resolution(i32, i32),
+}
+
+/// Picks the encoder's bitrate between the configured floor and ceiling from how far the uplink
+/// is behind and what the server receives. Once the bitrate is at its floor and the link still
+/// cannot keep up, the resolution is stepped down, down to the configured minimum height.
+pub struct BitrateController {
+    config: AdaptiveBitrateConfiguration,
+    bandwidth: Bandwidth,
+    bit_rate: i64,
+    full_width: i32,
+    full_height: i32,
+    resolution_step: u32,
+    /// The step to switch to once the current play is over.
+    wanted_step: u32,
+    clear_intervals: u32,
+    next_check: Instant,
+}
+
+impl BitrateController {
+    /// `width` and `height` are the configured output size, which is also the largest one used.
+    pub fn new(config: AdaptiveBitrateConfiguration, bandwidth: Bandwidth, width: i32, height: i32) -> BitrateController {
+        let controller = BitrateController {
+            bit_rate: config.get_max_bit_rate(),
+            config: config,
+            bandwidth: bandwidth,
+            full_width: width,
+            full_height: height,
+            resolution_step: 0,
+            wanted_step: 0,
+            clear_intervals: 0,
+            next_check: Instant::now(),
+        };
+        controller.update_drop_threshold();
+        controller
+    }
+
+    pub fn bit_rate(&self) -> i64 {
+        self.bit_rate
+    }
+
+    /// Bitrate changes are returned as soon as they are decided. A resolution change is held back
+    /// until no play is recording, since every clip has a single size.
+    pub fn evaluate(&mut self, recording: bool) -> Option<Adjustment> {
+        if !recording && self.wanted_step != self.resolution_step {
+            self.resolution_step = self.wanted_step;
+            let (width, height) = self.scaled(self.resolution_step);
+            return Some(Adjustment::Resolution(width, height));
+        }
+        // nothing is sent between plays, so there is nothing to learn about the link
+        let now = Instant::now();
+        if !recording || now < self.next_check {
+            return None;
+        }
+        self.next_check = now + Duration::from_millis(ADJUST_INTERVAL_MILLIS);
+
+        let backlog_secs = self.bandwidth.backlog() as f64 * 8.0 / self.bit_rate as f64;
+        if backlog_secs > CONGESTED_BACKLOG_SECS {
+            self.clear_intervals = 0;
+            let mut target = (self.bit_rate as f64 * DECREASE_FACTOR) as i64;
+            if let Some(rate) = self.bandwidth.server_rate() {
+                target = cmp::min(target, (rate as f64 * 8.0 * SERVER_RATE_HEADROOM) as i64);
+            }
+            let target = cmp::max(target, self.config.get_min_bit_rate());
+            if target < self.bit_rate {
+                return Some(self.set_bit_rate(target));
+            }
+            if self.resolution_step < self.max_step() {
+                self.wanted_step = self.resolution_step + 1;
+            }
+        } else if backlog_secs < CLEAR_BACKLOG_SECS {
+            self.clear_intervals += 1;
+            if self.clear_intervals >= STABLE_INTERVALS {
+                self.clear_intervals = 0;
+                let max_bit_rate = self.config.get_max_bit_rate();
+                if self.bit_rate < max_bit_rate {
+                    let target = cmp::min(max_bit_rate, (self.bit_rate as f64 * INCREASE_FACTOR) as i64);
+                    return Some(self.set_bit_rate(target));
+                }
+                if self.resolution_step > 0 {
+                    self.wanted_step = self.resolution_step - 1;
+                }
+            }
+        } else {
+            self.clear_intervals = 0;
+        }
+        None
+    }
+
+    fn set_bit_rate(&mut self, bit_rate: i64) -> Adjustment {
+        self.bit_rate = bit_rate;
+        self.update_drop_threshold();
+        Adjustment::BitRate(bit_rate)
+    }
+
+    fn update_drop_threshold(&self) {
+        let bytes = self.bit_rate as u64 / 8 * self.config.get_max_backlog_millis() / 1000;
+        self.bandwidth.set_drop_threshold(bytes as usize);
+    }
+
+    fn scaled(&self, step: u32) -> (i32, i32) {
+        let factor = RESOLUTION_STEP_FACTOR.powi(step as i32);
+        // encoders want even dimensions
+        let width = (self.full_width as f64 * factor) as i32 & !1;
+        let height = (self.full_height as f64 * factor) as i32 & !1;
+        (width, height)
+    }
+
+    fn max_step(&self) -> u32 {
+        let min_height = match self.config.get_min_height() {
+            Some(min_height) => cmp::max(min_height, 2),
+            None => return 0,
+        };
+        let mut step = 0;
+        while self.scaled(step + 1).1 >= min_height {
+            step += 1;
+        }
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml;
+
+    const MIN_BIT_RATE: i64 = 1_000_000;
+    const MAX_BIT_RATE: i64 = 4_000_000;
+
+    fn controller(bandwidth: &Bandwidth) -> BitrateController {
+        let config = toml::from_str(&format!("min_bit_rate = {}\nmax_bit_rate = {}", MIN_BIT_RATE, MAX_BIT_RATE)).unwrap();
+        BitrateController::new(config, bandwidth.clone(), 1280, 720)
+    }
+
+    /// Evaluates a recording play without waiting for the next check to come around.
+    fn check(controller: &mut BitrateController) -> Option<Adjustment> {
+        controller.next_check = Instant::now();
+        controller.evaluate(true)
+    }
+
+    /// Checks until the controller stops changing the bitrate, returning each rate it picked.
+    fn settle(controller: &mut BitrateController) -> Vec<i64> {
+        let mut rates = Vec::new();
+        for _ in 0..100 {
+            match check(controller) {
+                Some(Adjustment::BitRate(bit_rate)) => rates.push(bit_rate),
+                Some(other) => panic!("unexpected adjustment: {:?}", other),
+                None => {},
+            }
+        }
+        rates
+    }
+
+    #[test]
+    fn congestion_steps_the_bit_rate_down_to_the_minimum() {
+        let bandwidth = Bandwidth::new();
+        let mut controller = controller(&bandwidth);
+        assert_eq!(controller.bit_rate(), MAX_BIT_RATE);
+
+        bandwidth.queued(10_000_000);
+        let rates = settle(&mut controller);
+        assert!(rates.len() > 1);
+        assert!(rates.windows(2).all(|pair| pair[1] < pair[0]));
+        assert!(rates.iter().all(|&bit_rate| bit_rate >= MIN_BIT_RATE));
+        assert_eq!(rates.last(), Some(&MIN_BIT_RATE));
+        assert_eq!(controller.bit_rate(), MIN_BIT_RATE);
+    }
+
+    #[test]
+    fn a_clear_link_steps_the_bit_rate_back_up_to_the_maximum() {
+        let bandwidth = Bandwidth::new();
+        let mut controller = controller(&bandwidth);
+        bandwidth.queued(10_000_000);
+        settle(&mut controller);
+
+        bandwidth.dequeued(10_000_000);
+        for _ in 1..STABLE_INTERVALS {
+            assert_eq!(check(&mut controller), None);
+        }
+        let rates = settle(&mut controller);
+        assert!(rates.len() > 1);
+        assert!(rates.windows(2).all(|pair| pair[1] > pair[0]));
+        assert!(rates.iter().all(|&bit_rate| bit_rate <= MAX_BIT_RATE));
+        assert_eq!(rates.last(), Some(&MAX_BIT_RATE));
+        assert_eq!(controller.bit_rate(), MAX_BIT_RATE);
+    }
+
+    #[test]
+    fn nothing_changes_between_plays() {
+        let bandwidth = Bandwidth::new();
+        let mut controller = controller(&bandwidth);
+        bandwidth.queued(10_000_000);
+        controller.next_check = Instant::now();
+        assert_eq!(controller.evaluate(false), None);
+        assert_eq!(controller.bit_rate(), MAX_BIT_RATE);
+    }
+}
@@ -3,17 +3,30 @@ use std::net::SocketAddr;
 use std::io::{Write, Read};
 use std::io;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use toml;
 use std::fmt;
 use std::error::Error;
 use std::ffi::CString;
 use std::default::Default;
+use std::cmp;
+
+use std::collections::BTreeMap;
+
+use ffmpeg_common::networking::{is_team_key_set, PLACEHOLDER_TEAM_KEY};
+use ffmpeg_common::unsafe_code::{CodecId, CodecOptions};
+use ffmpeg_common::logging::LogConfiguration;
+use ffmpeg_common::unsafe_code::sws::ScalingAlgorithm;
+use ffmpeg_sys::AVCodecID;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub enum ClientConfigurationError {
     TOMLDEError(toml::de::Error),
     TOMLSERError(toml::ser::Error),
     IOError(io::Error),
+    /// `team_key` is missing or still the placeholder, so the server would turn the camera away.
+    TeamKeyNotSet,
 }
 
 impl fmt::Display for ClientConfigurationError {
@@ -22,6 +35,7 @@ impl fmt::Display for ClientConfigurationError {
             ClientConfigurationError::TOMLDEError(ref e) => e.fmt(f),
             ClientConfigurationError::TOMLSERError(ref e) => e.fmt(f),
             ClientConfigurationError::IOError(ref e) => e.fmt(f),
+            ClientConfigurationError::TeamKeyNotSet => write!(f, "team_key is not set; copy the key from the server configuration and restart"),
         }
     } 
 }
@@ -54,12 +68,29 @@ impl From<toml::ser::Error> for ClientConfigurationError {
     }
 }
 
+const DEFAULT_SPOOL_DIRECTORY: &'static str = "sr_spool";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ClientConfiguration {
     name: String,
+    /// Older configurations do not have one; the client refuses to start until it is set.
+    #[serde(default)]
+    team_key: Option<String>,
+    /// Generated on first run and written back to the file, so the server recognises this camera when it reconnects.
+    #[serde(default)]
+    client_id: Option<String>,
+    /// Where plays are kept until the server confirms it has all of them.
+    #[serde(default)]
+    spool_directory: Option<String>,
 
     ip_settings: IpConfiguration,
     camera_settings: CameraConfiguration,
+
+    #[serde(default)]
+    logging: LogConfiguration,
+
+    #[serde(default)]
+    tls: Option<TlsConfiguration>,
 }
 
 impl ClientConfiguration {
@@ -78,6 +109,31 @@ impl ClientConfiguration {
         &self.name
     }
 
+    pub fn get_team_key(&self) -> Result<&str, ClientConfigurationError> {
+        match self.team_key {
+            Some(ref key) if is_team_key_set(key) => Ok(key.as_str()),
+            _ => Err(ClientConfigurationError::TeamKeyNotSet),
+        }
+    }
+
+    pub fn get_client_id(&self) -> &str {
+        self.client_id.as_ref().map(|x| x.as_str()).expect("client id was never assigned")
+    }
+
+    /// Gives the configuration an identity if it does not have one yet. Returns true when one was
+    /// generated, in which case the configuration should be saved again.
+    pub fn assign_client_id(&mut self) -> bool {
+        if self.client_id.is_some() {
+            return false;
+        }
+        self.client_id = Some(Uuid::new_v4().simple().to_string());
+        true
+    }
+
+    pub fn get_spool_directory(&self) -> &str {
+        self.spool_directory.as_ref().map(|x| x.as_str()).unwrap_or(DEFAULT_SPOOL_DIRECTORY)
+    }
+
     pub fn get_ip_settings(&self) -> &IpConfiguration {
         &self.ip_settings
     }
@@ -85,14 +141,28 @@ impl ClientConfiguration {
     pub fn get_camera_settings(&self) -> &CameraConfiguration {
         &self.camera_settings
     }
+
+    pub fn get_logging_settings(&self) -> &LogConfiguration {
+        &self.logging
+    }
+
+    /// Set when the server's clip port speaks TLS.
+    pub fn get_tls_settings(&self) -> Option<&TlsConfiguration> {
+        self.tls.as_ref()
+    }
 }
 
 impl Default for ClientConfiguration {
     fn default() -> Self {
         ClientConfiguration {
             name: String::from("CAMERA_NAME"),
+            team_key: Some(String::from(PLACEHOLDER_TEAM_KEY)),
+            client_id: Some(Uuid::new_v4().simple().to_string()),
+            spool_directory: Some(String::from(DEFAULT_SPOOL_DIRECTORY)),
             ip_settings: IpConfiguration::default(),
             camera_settings: CameraConfiguration::default(),
+            logging: LogConfiguration::default(),
+            tls: None,
         }
     }
 }
@@ -101,6 +171,20 @@ impl Default for ClientConfiguration {
 pub struct CameraConfiguration {
     input_type: String,
     location: String,
+    #[serde(default)]
+    encoder: EncoderProfile,
+    #[serde(default)]
+    output: OutputConfiguration,
+    /// Seconds of video kept from before START, e.g. `pre_roll = 5.0`.
+    #[serde(default)]
+    pre_roll: f32,
+    /// Seconds kept recording after STOP.
+    #[serde(default)]
+    post_roll: f32,
+    #[serde(default)]
+    audio: Option<AudioConfiguration>,
+    #[serde(default)]
+    adaptive: Option<AdaptiveBitrateConfiguration>,
 }
 
 impl CameraConfiguration {
@@ -111,19 +195,295 @@ impl CameraConfiguration {
     pub fn get_camera_location(&self) -> CString {
         CString::new(self.location.as_bytes()).expect("Failed to create CString")
     }
+
+    pub fn get_encoder_profile(&self) -> &EncoderProfile {
+        &self.encoder
+    }
+
+    pub fn get_output_settings(&self) -> &OutputConfiguration {
+        &self.output
+    }
+
+    /// The pre-roll in microseconds.
+    pub fn get_pre_roll(&self) -> i64 {
+        (self.pre_roll.max(0.0) as f64 * 1_000_000.0) as i64
+    }
+
+    /// The post-roll in microseconds.
+    pub fn get_post_roll(&self) -> i64 {
+        (self.post_roll.max(0.0) as f64 * 1_000_000.0) as i64
+    }
+
+    pub fn get_audio_settings(&self) -> Option<&AudioConfiguration> {
+        self.audio.as_ref()
+    }
+
+    pub fn get_adaptive_settings(&self) -> Option<&AdaptiveBitrateConfiguration> {
+        self.adaptive.as_ref()
+    }
 }
 
 impl Default for CameraConfiguration {
     fn default() -> Self {
         CameraConfiguration {
             input_type: String::from("v4l2"),
-            location: String::from("/dev/video0")
+            location: String::from("/dev/video0"),
+            encoder: EncoderProfile::default(),
+            output: OutputConfiguration::default(),
+            pre_roll: 0.0,
+            post_roll: 0.0,
+            audio: None,
+            adaptive: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Mpeg4,
+}
+
+impl VideoCodec {
+    pub fn get_codec_id(&self) -> CodecId {
+        match *self {
+            VideoCodec::H264 => CodecId::from(AVCodecID::AV_CODEC_ID_H264),
+            VideoCodec::Hevc => CodecId::from(AVCodecID::AV_CODEC_ID_HEVC),
+            VideoCodec::Mpeg4 => CodecId::from(AVCodecID::AV_CODEC_ID_MPEG4),
+        }
+    }
+}
+
+/// Either constant quality (`rate_control = { crf = 23 }`) or a target bitrate in bits per second
+/// (`rate_control = { bit_rate = 4000000 }`).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum RateControl {
+    Crf { crf: f32 },
+    Bitrate { bit_rate: i64 },
+}
+
+/// How the camera's video is encoded before it is sent to the server.
+/// `options` is passed straight through to libav for anything not covered by a named field.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncoderProfile {
+    codec: VideoCodec,
+    preset: Option<String>,
+    rate_control: RateControl,
+    gop_size: i32,
+    max_b_frames: i32,
+    tune: Option<String>,
+    profile: Option<String>,
+    level: Option<String>,
+    #[serde(default)]
+    options: BTreeMap<String, String>,
+}
+
+impl EncoderProfile {
+    pub fn get_codec(&self) -> VideoCodec {
+        self.codec
+    }
+
+    pub fn get_gop_size(&self) -> i32 {
+        self.gop_size
+    }
+
+    pub fn get_max_b_frames(&self) -> i32 {
+        self.max_b_frames
+    }
+
+    /// The libav options this profile sets on the encoder.
+    pub fn get_codec_options(&self) -> CodecOptions {
+        let mut options = CodecOptions::new();
+        if let Some(ref preset) = self.preset {
+            options.set("preset", preset.as_str());
+        }
+        match self.rate_control {
+            RateControl::Crf { crf } => options.set("crf", crf.to_string()),
+            RateControl::Bitrate { bit_rate } => options.set("b", bit_rate.to_string()),
+        }
+        if let Some(ref tune) = self.tune {
+            options.set("tune", tune.as_str());
+        }
+        if let Some(ref profile) = self.profile {
+            options.set("profile", profile.as_str());
+        }
+        if let Some(ref level) = self.level {
+            options.set("level", level.as_str());
+        }
+        for (key, value) in self.options.iter() {
+            options.set(key.as_str(), value.as_str());
+        }
+        options
+    }
+
+    /// The same options, but targeting `bit_rate` so the encoder can be retuned while it runs.
+    pub fn get_adaptive_codec_options(&self, bit_rate: i64) -> CodecOptions {
+        let mut options = self.get_codec_options();
+        options.remove("crf");
+        options.set("b", bit_rate.to_string());
+        options
+    }
+}
+
+impl Default for EncoderProfile {
+    fn default() -> Self {
+        EncoderProfile {
+            codec: VideoCodec::H264,
+            preset: Some(String::from("ultrafast")),
+            rate_control: RateControl::Crf { crf: 28.0 },
+            gop_size: 0,
+            max_b_frames: 0,
+            tune: None,
+            profile: None,
+            level: None,
+            options: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_max_backlog_millis() -> u64 {
+    2000
+}
+
+/// Lets the client lower its bitrate, and between plays its resolution, when the network cannot keep up,
+/// e.g. `adaptive = { min_bit_rate = 500000, max_bit_rate = 6000000, min_height = 360 }`.
+/// The encoder then always targets a bitrate, starting at `max_bit_rate`, whatever `rate_control` says.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdaptiveBitrateConfiguration {
+    min_bit_rate: i64,
+    max_bit_rate: i64,
+    /// The lowest output height to step down to. Without it the resolution stays as configured.
+    #[serde(default)]
+    min_height: Option<i32>,
+    /// Once this much video is waiting to be sent, whole GOPs are left out of the live upload.
+    /// They are still spooled, so the server gets them when the play is replayed.
+    #[serde(default = "default_max_backlog_millis")]
+    max_backlog_millis: u64,
+}
+
+impl AdaptiveBitrateConfiguration {
+    pub fn get_min_bit_rate(&self) -> i64 {
+        self.min_bit_rate
+    }
+
+    pub fn get_max_bit_rate(&self) -> i64 {
+        cmp::max(self.max_bit_rate, self.min_bit_rate)
+    }
+
+    pub fn get_min_height(&self) -> Option<i32> {
+        self.min_height
+    }
+
+    pub fn get_max_backlog_millis(&self) -> u64 {
+        self.max_backlog_millis
+    }
+}
+
+/// The size and frame rate of the video sent to the server. Anything left unset follows the camera,
+/// so e.g. `width = 1280` and `height = 720` downscales a 1080p camera while the local preview stays at full resolution.
+/// Setting `frame_rate` drops or repeats frames to hit a constant rate; without it the camera's own
+/// timestamps are sent as captured. MPEG-4 part 2 only accepts coarse time bases, so it needs a `frame_rate`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OutputConfiguration {
+    width: Option<i32>,
+    height: Option<i32>,
+    frame_rate: Option<i32>,
+    #[serde(default)]
+    scaling_algorithm: ScalingAlgorithm,
+}
+
+impl OutputConfiguration {
+    pub fn get_width(&self) -> Option<i32> {
+        self.width
+    }
+
+    pub fn get_height(&self) -> Option<i32> {
+        self.height
+    }
+
+    pub fn get_frame_rate(&self) -> Option<i32> {
+        self.frame_rate
+    }
+
+    pub fn get_scaling_algorithm(&self) -> ScalingAlgorithm {
+        self.scaling_algorithm
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+}
+
+impl AudioCodec {
+    pub fn get_codec_id(&self) -> CodecId {
+        match *self {
+            AudioCodec::Aac => CodecId::from(AVCodecID::AV_CODEC_ID_AAC),
+            AudioCodec::Opus => CodecId::from(AVCodecID::AV_CODEC_ID_OPUS),
+        }
+    }
+}
+
+/// Where a camera's audio comes from: either a separate capture device
+/// (e.g. `input_type = "alsa"`, `location = "hw:1"`) or another stream of the camera input.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum AudioSource {
+    Device { input_type: String, location: String },
+    CameraStream { stream_index: usize },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AudioConfiguration {
+    source: AudioSource,
+    codec: AudioCodec,
+    sample_rate: i32,
+    channels: i32,
+    bit_rate: i64,
+}
+
+impl AudioConfiguration {
+    pub fn get_source(&self) -> &AudioSource {
+        &self.source
+    }
+
+    pub fn get_codec(&self) -> AudioCodec {
+        self.codec
+    }
+
+    pub fn get_sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    pub fn get_channels(&self) -> i32 {
+        self.channels
+    }
+
+    pub fn get_bit_rate(&self) -> i64 {
+        self.bit_rate
+    }
+}
+
+impl Default for AudioConfiguration {
+    fn default() -> Self {
+        AudioConfiguration {
+            source: AudioSource::Device { input_type: String::from("alsa"), location: String::from("default") },
+            codec: AudioCodec::Aac,
+            sample_rate: 48000,
+            channels: 1,
+            bit_rate: 96000,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IpConfiguration {
+    server_address: Option<SocketAddr>,
     websocket_bind_address: SocketAddr,
     http_bind_address: SocketAddr,
     discovery_port: u16,
@@ -131,6 +491,11 @@ pub struct IpConfiguration {
 }
 
 impl IpConfiguration {
+    /// The clip server to connect to. When unset the server is found through multicast discovery.
+    pub fn get_server_address(&self) -> Option<SocketAddr> {
+        self.server_address.clone()
+    }
+
     pub fn get_multicast_ip(&self) -> net::Ipv4Addr {
         self.multicast_address.clone()
     }
@@ -151,10 +516,31 @@ impl IpConfiguration {
 impl Default for IpConfiguration {
     fn default() -> Self {
         IpConfiguration {
+            server_address: None,
             multicast_address: net::Ipv4Addr::new(224, 0, 0, 12),
             websocket_bind_address: SocketAddr::from(net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 4000)),
             http_bind_address: SocketAddr::from(net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 8070)),
             discovery_port: 9000,
         }
     }
-}
\ No newline at end of file
+}
+
+/// How the client checks the certificate the clip server presents.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsConfiguration {
+    /// The name the certificate has to be issued for.
+    server_name: String,
+    /// A PEM file to trust instead of the system's certificate store, e.g. for a self-signed server.
+    #[serde(default)]
+    ca_certificate: Option<PathBuf>,
+}
+
+impl TlsConfiguration {
+    pub fn get_server_name(&self) -> &str {
+        &self.server_name
+    }
+
+    pub fn get_ca_certificate(&self) -> Option<&Path> {
+        self.ca_certificate.as_ref().map(|x| x.as_path())
+    }
+}
@@ -1,49 +1,122 @@
-use std::net::{SocketAddr, TcpStream};
+use std::net::{SocketAddr, TcpStream, Shutdown};
 use std::thread;
 use std::thread::JoinHandle;
 use std::sync::{Arc};
 use std::sync::mpsc::{channel, Sender};
 use std::cell::Cell;
+use std::time::Duration;
+use std::path::Path;
+use std::cmp;
 
 use messenger_plus::stream::DualMessenger;
 use messenger_plus::stream;
 use client::CameraConfiguration;
 use client::errors::ClientError;
-use client::{ClientStatusFlag, send_video, ClientConfiguration};
+use client::{ClientStatusFlag, send_video, ClientConfiguration, discover_server};
+use client::{Spool, Uplink, UplinkControl, Bandwidth};
 use client::web::WebHandler;
-use ffmpeg_common::networking::NetworkPacket;
+use ffmpeg_common::networking::{NetworkPacket, ClockSample, PROTOCOL_VERSION, HEARTBEAT_TIMEOUT_SECS, wall_clock_micros, supports_version};
+use ffmpeg_common::networking::{Connection, answer_challenge, tls_connector, connect_tls};
+use ffmpeg_common::logging::{set_log_field, CLIENT_FIELD};
+use base64;
 
 use ffmpeg_common::unsafe_code::UnsafeError;
 
+/// The wait before reconnecting starts here and doubles after every failed attempt, up to the maximum.
+const RECONNECT_BACKOFF_INITIAL_SECS: u64 = 1;
+const RECONNECT_BACKOFF_MAX_SECS: u64 = 30;
+
+/// Why a connection to the server ended.
+#[derive(Debug, PartialEq)]
+pub enum Disconnect {
+    /// The server hung up or went quiet, so it is worth connecting again.
+    Lost,
+    /// The server will not talk to this version of the client, or does not accept its team key.
+    Refused,
+}
+
 pub struct Client {
     name: String,
-    stream: TcpStream,
+    client_id: String,
+    team_key: String,
     http_server: WebHandler,
 }
 
 impl Client {
-    pub fn new(conf: ClientConfiguration) -> Result<Client, ClientError> {
-        let stream = TcpStream::connect(conf.get_ip_settings().get_server_ip())?;
+    pub fn new(conf: &ClientConfiguration) -> Result<Client, ClientError> {
+        let team_key = String::from(conf.get_team_key()?);
         let wh_tuple = WebHandler::new((conf.get_ip_settings().get_ws_bind_address(), conf.get_ip_settings().get_http_bind_address()))?;
 
-        Ok(Client { name: String::from(conf.get_name()), http_server: wh_tuple, stream: stream })
+        Ok(Client { name: String::from(conf.get_name()), client_id: String::from(conf.get_client_id()), team_key: team_key, http_server: wh_tuple })
+    }
+
+    fn connect(conf: &ClientConfiguration) -> Result<Connection, ClientError> {
+        let ip_settings = conf.get_ip_settings();
+        let server_address = match ip_settings.get_server_address() {
+            Some(addr) => addr,
+            None => {
+                info!("No server address configured, waiting for a discovery beacon on {}:{}", ip_settings.get_multicast_ip(), ip_settings.get_discovery_port());
+                discover_server(ip_settings.get_multicast_ip(), ip_settings.get_discovery_port(), conf.get_team_key()?)?.0
+            },
+        };
+        let stream = TcpStream::connect(server_address)?;
+        let stream = match conf.get_tls_settings() {
+            Some(tls_conf) => {
+                // a server that stalls the TLS handshake is given up on like one that stops pinging
+                stream.set_read_timeout(Some(Duration::from_secs(HEARTBEAT_TIMEOUT_SECS)))?;
+                let connector = tls_connector(tls_conf.get_ca_certificate())?;
+                connect_tls(&connector, tls_conf.get_server_name(), stream)?
+            },
+            None => Connection::from(stream),
+        };
+        // the server pings while it has nothing else to say, so a long silence means it is gone
+        stream.set_read_timeout(Some(Duration::from_secs(HEARTBEAT_TIMEOUT_SECS)))?;
+        Ok(stream)
+    }
+
+    /// Stays connected to the server, reconnecting with a growing backoff whenever the connection is lost.
+    /// The camera keeps running between connections and plays are spooled, so an outage costs no footage.
+    pub fn run(&mut self, conf: &ClientConfiguration, arc_sender: Sender<Arc<Vec<u8>>>) -> Result<(), ClientError> {
+        set_log_field(CLIENT_FIELD, &self.client_id);
+        let spool = Spool::open(Path::new(conf.get_spool_directory()))?;
+        let video_processing = ClientVideoThreadHandler::new(self.client_id.clone(), conf.get_camera_settings().clone(), arc_sender, self.http_server.sockets.0.clone(), spool);
+        let mut backoff = RECONNECT_BACKOFF_INITIAL_SECS;
+        loop {
+            let result = match Client::connect(conf) {
+                Ok(stream) => {
+                    backoff = RECONNECT_BACKOFF_INITIAL_SECS;
+                    self.stream_handler(stream, &video_processing)
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Disconnect::Refused) => {
+                    video_processing.shutdown();
+                    return Ok(());
+                },
+                Ok(Disconnect::Lost) => warn!("Lost the connection to the server, reconnecting in {}s", backoff),
+                Err(e) => warn!("Could not talk to the server ({}), reconnecting in {}s", e, backoff),
+            }
+            thread::sleep(Duration::from_secs(backoff));
+            backoff = cmp::min(backoff * 2, RECONNECT_BACKOFF_MAX_SECS);
+        }
     }
 
-    pub fn stream_handler(&mut self, camera_config: CameraConfiguration, arc_sender: Sender<Arc<Vec<u8>>>) -> Result<(), ClientError> {
-        let read_stream = try!(self.stream.try_clone());
-        let write_stream = try!(self.stream.try_clone());
-        let mut read_channel: DualMessenger<TcpStream> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), read_stream);
-        let write_channel: DualMessenger<TcpStream> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), write_stream);
-        let video_processing = ClientVideoThreadHandler::new(write_channel, camera_config, arc_sender, self.http_server.sockets.0.clone());
+    fn stream_handler(&mut self, stream: Connection, video_processing: &ClientVideoThreadHandler) -> Result<Disconnect, ClientError> {
+        let read_stream = try!(stream.try_clone());
+        let write_stream = try!(stream.try_clone());
+        let mut read_channel: DualMessenger<Connection> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), read_stream);
+        video_processing.connected(write_stream);
 
         let mut stream_open = true;
+        let mut outcome = Ok(Disconnect::Lost);
         
         while stream_open {
             let results = read_channel.read_next_message();
+            let received_at = wall_clock_micros();
             match results {
                 Err(ref e) if e == &stream::Error::from(stream::ErrorKind::BufferEmpty) => {
-                    println!("Server EOS");
-                    video_processing.server_disconnect();
+                    info!("Server EOS");
                     stream_open = false;
                 },
                 Ok(v) => {
@@ -52,25 +125,89 @@ impl Client {
                     match curr_data {
                         Ok(s) => {
                             match s.as_ref() {
-                                "START" => {
-                                    println!("Starting Recording");
-                                    video_processing.start();
+                                start if start.starts_with("START ") => {
+                                    match start["START ".len()..].parse::<i64>() {
+                                        Ok(play_id) => {
+                                            info!("Starting Recording of play {}", play_id);
+                                            video_processing.start(play_id);
+                                        },
+                                        Err(e) => warn!("Received a malformed START: {}", e),
+                                    }
                                 },
                                 "STOP" => {
-                                    println!("Stopping Recording");
+                                    info!("Stopping Recording");
                                     video_processing.stop();
                                 },
-                                _ => println!("Received Unsupported Instruction"),
+                                version if version.starts_with("VERSION ") => {
+                                    match version["VERSION ".len()..].parse::<u8>() {
+                                        Ok(version) if supports_version(version) => {
+                                            debug!("Speaking wire protocol version {} with the server", version);
+                                            video_processing.use_version(version);
+                                        },
+                                        Ok(version) => {
+                                            error!("The server picked wire protocol version {}, which this client does not speak", version);
+                                            outcome = Ok(Disconnect::Refused);
+                                            stream_open = false;
+                                        },
+                                        Err(e) => warn!("Received a malformed VERSION: {}", e),
+                                    }
+                                },
+                                challenge if challenge.starts_with("AUTH ") => {
+                                    let answer = base64::decode(&challenge["AUTH ".len()..]).map_err(|e| e.to_string())
+                                        .and_then(|challenge| answer_challenge(self.team_key.as_bytes(), &challenge).map_err(|e| e.to_string()));
+                                    match answer {
+                                        Ok(answer) => video_processing.authenticate(answer),
+                                        Err(e) => warn!("Received a malformed team key challenge: {}", e),
+                                    }
+                                },
+                                "UNAUTHORIZED" => {
+                                    error!("The server rejected our team key, check team_key in the client configuration");
+                                    outcome = Ok(Disconnect::Refused);
+                                    stream_open = false;
+                                },
+                                // only sent so that we can tell the server is still there
+                                "PING" => {},
+                                "READY" => video_processing.server_ready(),
+                                rate if rate.starts_with("RATE ") => {
+                                    match rate["RATE ".len()..].parse::<u64>() {
+                                        Ok(bytes_per_second) => video_processing.server_rate(bytes_per_second),
+                                        Err(e) => warn!("Received a malformed RATE: {}", e),
+                                    }
+                                },
+                                stored if stored.starts_with("STORED ") => {
+                                    let mut fields = stored["STORED ".len()..].split_whitespace().map(|x| x.parse::<u64>());
+                                    match (fields.next(), fields.next()) {
+                                        (Some(Ok(play_id)), Some(Ok(packets))) => video_processing.clip_stored(play_id as i64, packets),
+                                        _ => warn!("Received a malformed STORED: {}", stored),
+                                    }
+                                },
+                                "REFUSE" => {
+                                    error!("The server refused our wire protocol version ({}), please update the client", PROTOCOL_VERSION);
+                                    outcome = Ok(Disconnect::Refused);
+                                    stream_open = false;
+                                },
+                                sync if sync.starts_with("SYNC ") => {
+                                    match sync["SYNC ".len()..].parse::<i64>() {
+                                        Ok(server_send) => video_processing.answer_clock_sync(server_send, received_at),
+                                        Err(e) => warn!("Received a malformed clock sync request: {}", e),
+                                    }
+                                },
+                                _ => warn!("Received Unsupported Instruction"),
                             }
                         },
-                        Err(e) => println!("{}", e),
+                        Err(e) => warn!("Received an instruction that is not UTF-8: {}", e),
                     }
                 },
-                Err(e) => return Err(ClientError::from(UnsafeError::from(e))),
+                Err(e) => {
+                    outcome = Err(ClientError::from(UnsafeError::from(e)));
+                    stream_open = false;
+                },
             }            
         }
 
-        Ok(())
+        let _ = stream.shutdown(Shutdown::Both);
+        video_processing.disconnected();
+        outcome
     }
 
     pub fn get_web_handler_ref(&self) -> &WebHandler {
@@ -82,36 +219,94 @@ struct ClientVideoThreadHandler {
     send_video_handle: Cell<JoinHandle<()>>,
     write_video_handle: Cell<JoinHandle<()>>,
     video_tunnel: Sender<ClientStatusFlag>,
+    packet_tunnel: Sender<NetworkPacket>,
+    uplink_tunnel: Sender<UplinkControl>,
+    spool: Spool,
+    bandwidth: Bandwidth,
 }
 
 impl ClientVideoThreadHandler {
-    fn new<'a>(mut write_channel: DualMessenger<TcpStream>, camera_config: CameraConfiguration, jpeg_sender: Sender<Arc<Vec<u8>>>, sock: SocketAddr) -> ClientVideoThreadHandler {
+    fn new(client_id: String, camera_config: CameraConfiguration, jpeg_sender: Sender<Arc<Vec<u8>>>, sock: SocketAddr, spool: Spool) -> ClientVideoThreadHandler {
         let (instr_tx, instr_rx) = channel();
         let (tx, rx) = channel::<NetworkPacket>();
+        let (uplink_tx, uplink_rx) = channel();
+        let packet_tunnel = tx.clone();
+        let video_spool = spool.clone();
+        let bandwidth = Bandwidth::new();
+        let video_bandwidth = bandwidth.clone();
+        let uplink = Uplink::new(spool.clone(), bandwidth.clone());
+        let uplink_client_id = client_id.clone();
         let send_video_handle = thread::Builder::new().name("send_video_thread".to_string()).spawn(move || {
-            println!("Send Video Completion Status: {:?}", send_video(camera_config, instr_rx, tx, jpeg_sender, sock));
+            set_log_field(CLIENT_FIELD, &client_id);
+            match send_video(camera_config, client_id.clone(), video_spool, video_bandwidth, instr_rx, tx, jpeg_sender, sock) {
+                Ok(_) => info!("Stopped sending video"),
+                Err(e) => error!("Stopped sending video: {}", e),
+            }
         }).unwrap();
         let write_video_handle = thread::Builder::new().name("write_video_thread".to_string()).spawn(move || {
-            for item in rx {
-                let _ = item.write_to(&mut write_channel);
-            }
+            set_log_field(CLIENT_FIELD, uplink_client_id);
+            uplink.run(rx, uplink_rx);
         }).unwrap();
         ClientVideoThreadHandler {
             send_video_handle: Cell::new(send_video_handle),
             write_video_handle: Cell::new(write_video_handle),
             video_tunnel: instr_tx,
+            packet_tunnel: packet_tunnel,
+            uplink_tunnel: uplink_tx,
+            spool: spool,
+            bandwidth: bandwidth,
         }
     }
 
-    fn start(&self) {
-        let _ = self.video_tunnel.send(ClientStatusFlag::StartRecording);
+    fn start(&self, play_id: i64) {
+        let _ = self.video_tunnel.send(ClientStatusFlag::StartRecording(play_id));
     }
 
     fn stop(&self) {
         let _ = self.video_tunnel.send(ClientStatusFlag::StopRecording);
     }
 
-    fn server_disconnect(&self) {
-        let _ = self.video_tunnel.send(ClientStatusFlag::ServerQuit);
+    fn answer_clock_sync(&self, server_send: i64, received_at: i64) {
+        let _ = self.packet_tunnel.send(NetworkPacket::ClockSync(ClockSample::new(server_send, received_at)));
+    }
+
+    fn connected(&self, write_stream: Connection) {
+        let _ = self.uplink_tunnel.send(UplinkControl::Connected(write_stream));
+    }
+
+    fn use_version(&self, version: u8) {
+        let _ = self.uplink_tunnel.send(UplinkControl::Version(version));
+    }
+
+    fn authenticate(&self, answer: Vec<u8>) {
+        let _ = self.uplink_tunnel.send(UplinkControl::Authenticate(answer));
+    }
+
+    fn server_ready(&self) {
+        let _ = self.uplink_tunnel.send(UplinkControl::Ready);
     }
-}
\ No newline at end of file
+
+    fn server_rate(&self, bytes_per_second: u64) {
+        self.bandwidth.report_server_rate(bytes_per_second);
+    }
+
+    fn clip_stored(&self, play_id: i64, packets: u64) {
+        if self.spool.confirm(play_id, packets) {
+            info!("The server has all of play {}, removed it from the spool", play_id);
+        }
+    }
+
+    fn disconnected(&self) {
+        let _ = self.uplink_tunnel.send(UplinkControl::Disconnected);
+    }
+
+    /// Stops the camera and waits for both threads to finish.
+    fn shutdown(self) {
+        let ClientVideoThreadHandler { send_video_handle, write_video_handle, video_tunnel, packet_tunnel, uplink_tunnel, .. } = self;
+        let _ = video_tunnel.send(ClientStatusFlag::ServerQuit);
+        drop(packet_tunnel);
+        drop(uplink_tunnel);
+        let _ = send_video_handle.into_inner().join();
+        let _ = write_video_handle.into_inner().join();
+    }
+}
@@ -1,24 +1,25 @@
-use std::net::{UdpSocket, UdpBuilder, Ipv4Addr};
-use std::io;
+use std::net::{UdpSocket, SocketAddr, Ipv4Addr};
 
-fn discover_server(discovery_ip: Ipv4Addr, port: u16) -> Result<SocketAddr, io::Error> {
+use ffmpeg_common::networking::{DiscoveryBeacon, BEACON_MAX_LENGTH};
+
+use client::ClientError;
+
+/// Blocks until a beacon signed with `team_key` arrives on the multicast group, then returns the clip server address it advertises.
+pub fn discover_server(discovery_ip: Ipv4Addr, port: u16, team_key: &str) -> Result<(SocketAddr, DiscoveryBeacon), ClientError> {
     let udp: UdpSocket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
     udp.join_multicast_v4(&discovery_ip, &Ipv4Addr::new(0, 0, 0, 0))?;
 
-    let mut buffer = [0u8; 32];
+    let mut buffer = [0u8; BEACON_MAX_LENGTH];
 
     loop {
         let (size, addr) = udp.recv_from(&mut buffer)?;
-        // header verification - 4 bytes (28)
-        if buffer.starts_with(&[0xE, 0xE, 0xA, 0xB]) {
-            // session code - next 4 bytes (24)
-            let session_code = buffer[4..8];
-            // port - next 2 bytes (22)
-            let byte_one = buffer[9];
-            let byte_two = buffer[10] as u16;
-            let port = ;
-
-            return Ok(SocketAddr::from((addr.ip(), port))?)
+        match DiscoveryBeacon::from_signed_bytes(&buffer[..size], team_key.as_bytes()) {
+            Ok(beacon) => {
+                let _ = udp.leave_multicast_v4(&discovery_ip, &Ipv4Addr::new(0, 0, 0, 0));
+                info!("Discovered server for {} (session {:08x}) at {}", beacon.team_name, beacon.session_code, addr.ip());
+                return Ok((SocketAddr::from((addr.ip(), beacon.clip_server_port)), beacon));
+            },
+            Err(e) => debug!("Ignoring beacon from {}: {}", addr, e),
         }
     }
-}
\ No newline at end of file
+}
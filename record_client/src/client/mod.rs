@@ -5,8 +5,20 @@ pub use self::errors::*;
 pub use self::status_enumeration::*;
 pub use self::sending::*;
 pub use self::client_configuration::*;
+pub use self::discovery::*;
+pub use self::audio::*;
+pub use self::play_gate::*;
+pub use self::spool::*;
+pub use self::uplink::*;
+pub use self::bandwidth::*;
 
 mod errors;
 mod status_enumeration;
 mod sending;
-mod client_configuration;
\ No newline at end of file
+mod client_configuration;
+mod discovery;
+mod audio;
+mod play_gate;
+mod spool;
+mod uplink;
+mod bandwidth;
\ No newline at end of file
@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+
+use ffmpeg_common::unsafe_code::{DataPacket, Rational};
+use ffmpeg_common::networking::{NetworkPacket, AUDIO_STREAM_INDEX};
+
+fn is_video_keyframe(pkt: &DataPacket) -> bool {
+    pkt.stream_index != AUDIO_STREAM_INDEX && pkt.is_keyframe()
+}
+
+/// The last few seconds of encoded packets, always starting at a video keyframe so it can be played on its own.
+pub struct PrerollBuffer {
+    packets: VecDeque<DataPacket>,
+    duration: i64,
+}
+
+impl PrerollBuffer {
+    /// `duration` is in microseconds. A buffer of zero duration keeps nothing.
+    pub fn new(duration: i64) -> PrerollBuffer {
+        PrerollBuffer {
+            packets: VecDeque::new(),
+            duration: duration,
+        }
+    }
+
+    pub fn push(&mut self, pkt: DataPacket) {
+        if self.duration <= 0 || (self.packets.is_empty() && !is_video_keyframe(&pkt)) {
+            return;
+        }
+        let cutoff = pkt.capture_time - self.duration;
+        self.packets.push_back(pkt);
+
+        // start from the latest keyframe that still covers the whole duration
+        let start = self.packets.iter().enumerate()
+            .filter(|&(_, pkt)| is_video_keyframe(pkt) && pkt.capture_time <= cutoff)
+            .map(|(index, _)| index)
+            .last();
+        if let Some(start) = start {
+            self.packets.drain(..start);
+        }
+    }
+
+    /// The capture time of the keyframe the buffer starts at.
+    pub fn start_time(&self) -> Option<i64> {
+        self.packets.front().map(|pkt| pkt.capture_time)
+    }
+
+    pub fn take(&mut self) -> Vec<DataPacket> {
+        self.packets.drain(..).collect()
+    }
+}
+
+enum PlayState {
+    /// Between plays, packets only go into the pre-roll buffer.
+    Buffering,
+    /// START arrived with nothing buffered, so the play begins at the next keyframe.
+    Starting,
+    Recording { start: i64 },
+    /// STOP arrived; packets captured before `end` still belong to the play.
+    PostRoll { start: i64, end: i64 },
+}
+
+/// Decides which encoded packets are sent to the server. The encoder runs continuously, so a play
+/// can begin with the buffered pre-roll and keep going for the post-roll after STOP.
+/// Every play is rebased so its first keyframe sits at pts 0.
+pub struct PlayGate {
+    buffer: PrerollBuffer,
+    post_roll: i64,
+    video_time_base: Rational,
+    audio_time_base: Option<Rational>,
+    state: PlayState,
+}
+
+impl PlayGate {
+    /// `pre_roll` and `post_roll` are in microseconds.
+    pub fn new(pre_roll: i64, post_roll: i64, video_time_base: Rational, audio_time_base: Option<Rational>) -> PlayGate {
+        PlayGate {
+            buffer: PrerollBuffer::new(pre_roll),
+            post_roll: post_roll,
+            video_time_base: video_time_base,
+            audio_time_base: audio_time_base,
+            state: PlayState::Buffering,
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match self.state {
+            PlayState::Buffering => false,
+            _ => true,
+        }
+    }
+
+    /// Starts a play. Returns the buffered pre-roll to send, and whether the encoder
+    /// has to be asked for a keyframe because nothing was buffered.
+    pub fn start(&mut self) -> (Vec<NetworkPacket>, bool) {
+        // a START during the post-roll cuts the previous play short
+        let mut outgoing = self.end_play();
+        match self.buffer.start_time() {
+            Some(start) => {
+                self.state = PlayState::Recording { start: start };
+                let pkts = self.buffer.take();
+                outgoing.extend(self.forward(pkts, start));
+                (outgoing, false)
+            },
+            None => {
+                self.state = PlayState::Starting;
+                (outgoing, true)
+            },
+        }
+    }
+
+    /// Ends the play once packets captured `post_roll` after `stopped_at` come through.
+    pub fn stop(&mut self, stopped_at: i64) -> Vec<NetworkPacket> {
+        match self.state {
+            PlayState::Recording { start } => {
+                self.state = PlayState::PostRoll { start: start, end: stopped_at + self.post_roll };
+                Vec::new()
+            },
+            PlayState::Starting => self.end_play(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, pkts: Vec<DataPacket>) -> Vec<NetworkPacket> {
+        let mut outgoing = Vec::new();
+        let mut current = Vec::new();
+        for pkt in pkts {
+            match self.state {
+                PlayState::Buffering => self.buffer.push(pkt),
+                PlayState::Starting => {
+                    if is_video_keyframe(&pkt) {
+                        let start = pkt.capture_time;
+                        self.state = PlayState::Recording { start: start };
+                        current.push(pkt);
+                    }
+                },
+                PlayState::Recording { .. } => current.push(pkt),
+                PlayState::PostRoll { end, .. } => {
+                    if pkt.stream_index != AUDIO_STREAM_INDEX && pkt.capture_time >= end {
+                        outgoing.extend(self.flush_current(&mut current));
+                        outgoing.extend(self.end_play());
+                        self.buffer.push(pkt);
+                    } else {
+                        current.push(pkt);
+                    }
+                },
+            }
+        }
+        outgoing.extend(self.flush_current(&mut current));
+        outgoing
+    }
+
+    /// Throws away the buffered pre-roll, e.g. when the encoder it came from was replaced.
+    pub fn discard_buffer(&mut self) {
+        self.buffer.take();
+    }
+
+    /// Ends whatever play is in progress right away.
+    pub fn end_play(&mut self) -> Vec<NetworkPacket> {
+        match self.state {
+            PlayState::Buffering => Vec::new(),
+            _ => {
+                self.state = PlayState::Buffering;
+                vec![NetworkPacket::PayloadEnd]
+            },
+        }
+    }
+
+    fn flush_current(&self, current: &mut Vec<DataPacket>) -> Vec<NetworkPacket> {
+        let start = match self.state {
+            PlayState::Recording { start } | PlayState::PostRoll { start, .. } => start,
+            _ => return Vec::new(),
+        };
+        let pkts = current.drain(..).collect();
+        self.forward(pkts, start)
+    }
+
+    /// Rebases packets to the start of the play, dropping any captured before it.
+    fn forward(&self, pkts: Vec<DataPacket>, start: i64) -> Vec<NetworkPacket> {
+        let pkts: Vec<DataPacket> = pkts.into_iter().filter_map(|mut pkt| {
+            if pkt.capture_time < start {
+                return None;
+            }
+            let time_base = if pkt.stream_index == AUDIO_STREAM_INDEX {
+                match self.audio_time_base {
+                    Some(time_base) => time_base,
+                    None => return None,
+                }
+            } else {
+                self.video_time_base
+            };
+            pkt.rebase_to(start, time_base);
+            Some(pkt)
+        }).collect();
+
+        if pkts.is_empty() {
+            Vec::new()
+        } else {
+            vec![NetworkPacket::PacketStream(pkts)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffmpeg_common::networking::VIDEO_STREAM_INDEX;
+
+    const MICROS: i32 = 1_000_000;
+
+    /// A packet stamped in microseconds, so pts and capture time are the same number.
+    fn packet(stream_index: i32, capture_time: i64, keyframe: bool) -> DataPacket {
+        DataPacket {
+            packet: vec![0; 4],
+            pts: capture_time,
+            dts: capture_time,
+            capture_time: capture_time,
+            duration: 0,
+            stream_index: stream_index,
+            flags: if keyframe { DataPacket::KEYFRAME_FLAG } else { 0 },
+        }
+    }
+
+    fn video(capture_time: i64, keyframe: bool) -> DataPacket {
+        packet(VIDEO_STREAM_INDEX, capture_time, keyframe)
+    }
+
+    fn gate(pre_roll: i64, post_roll: i64) -> PlayGate {
+        PlayGate::new(pre_roll, post_roll, Rational::new(1, MICROS), Some(Rational::new(1, MICROS)))
+    }
+
+    /// The capture times of each outgoing packet stream, with `None` for the end of a play.
+    fn sent(outgoing: Vec<NetworkPacket>) -> Vec<Option<Vec<i64>>> {
+        outgoing.into_iter().map(|network_packet| match network_packet {
+            NetworkPacket::PacketStream(pkts) => Some(pkts.iter().map(|pkt| pkt.capture_time).collect()),
+            NetworkPacket::PayloadEnd => None,
+            other => panic!("unexpected packet: {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn pre_roll_starts_on_a_keyframe() {
+        let mut buffer = PrerollBuffer::new(1_000_000);
+        buffer.push(video(0, false));
+        assert_eq!(buffer.start_time(), None);
+
+        buffer.push(video(100_000, true));
+        buffer.push(video(600_000, false));
+        buffer.push(video(1_300_000, true));
+        buffer.push(video(1_800_000, false));
+        // the keyframe at 1.3s does not cover a whole second yet
+        assert_eq!(buffer.start_time(), Some(100_000));
+
+        buffer.push(video(2_400_000, false));
+        assert_eq!(buffer.start_time(), Some(1_300_000));
+        let kept: Vec<i64> = buffer.take().iter().map(|pkt| pkt.capture_time).collect();
+        assert_eq!(kept, vec![1_300_000, 1_800_000, 2_400_000]);
+    }
+
+    #[test]
+    fn start_sends_the_pre_roll_rebased_to_its_keyframe() {
+        let mut gate = gate(1_000_000, 0);
+        gate.push(vec![video(500_000, true), video(700_000, false)]);
+        let (outgoing, needs_keyframe) = gate.start();
+        assert!(!needs_keyframe);
+        assert_eq!(outgoing.len(), 1);
+        match outgoing[0] {
+            NetworkPacket::PacketStream(ref pkts) => {
+                let pts: Vec<i64> = pkts.iter().map(|pkt| pkt.pts).collect();
+                assert_eq!(pts, vec![0, 200_000]);
+            },
+            ref other => panic!("unexpected packet: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn start_with_an_empty_buffer_waits_for_a_keyframe() {
+        let mut gate = gate(0, 0);
+        gate.push(vec![video(0, true)]);
+        let (outgoing, needs_keyframe) = gate.start();
+        assert!(outgoing.is_empty());
+        assert!(needs_keyframe);
+        assert!(gate.is_recording());
+
+        assert!(gate.push(vec![video(40_000, false)]).is_empty());
+        assert_eq!(sent(gate.push(vec![video(80_000, true), video(120_000, false)])), vec![Some(vec![80_000, 120_000])]);
+    }
+
+    #[test]
+    fn post_roll_ends_on_the_first_video_packet_at_or_past_its_end() {
+        let mut gate = gate(1_000_000, 500_000);
+        gate.push(vec![video(0, true)]);
+        gate.start();
+        assert!(gate.stop(1_000_000).is_empty());
+
+        let outgoing = gate.push(vec![
+            video(1_400_000, false),
+            // audio never ends the play, it only follows the video
+            packet(AUDIO_STREAM_INDEX, 1_600_000, false),
+            video(1_500_000, true),
+            video(1_540_000, false),
+        ]);
+        assert_eq!(sent(outgoing), vec![Some(vec![1_400_000, 1_600_000]), None]);
+        assert!(!gate.is_recording());
+        // the packet that ended the play starts the next pre-roll
+        assert_eq!(gate.buffer.start_time(), Some(1_500_000));
+    }
+
+    #[test]
+    fn start_during_the_post_roll_ends_the_previous_play() {
+        let mut gate = gate(1_000_000, 500_000);
+        gate.push(vec![video(0, true)]);
+        gate.start();
+        gate.stop(1_000_000);
+        gate.push(vec![video(1_100_000, true)]);
+
+        let (outgoing, needs_keyframe) = gate.start();
+        assert_eq!(sent(outgoing), vec![None]);
+        assert!(needs_keyframe);
+    }
+}
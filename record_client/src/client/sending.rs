@@ -2,27 +2,72 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
 use std::sync::Arc;
-use std::cell::Cell;
 use std::net::SocketAddr;
+use std::ffi::CString;
 
 use client::ClientStatusFlag;
 
-use client::CameraConfiguration;
-use ffmpeg_common::unsafe_code::{init_av, CodecStorage, UnsafeError, UnsafeErrorKind, Rational, CodecId, Frame};
+use client::{CameraConfiguration, EncoderProfile, OutputConfiguration, AudioConfiguration, AudioSource, AudioPipeline, PlayGate, Spool, PlaySpooler};
+use client::{Bandwidth, BitrateController, Adjustment, payload_bytes};
+use ffmpeg_common::unsafe_code::{init_av, CodecStorage, UnsafeError, UnsafeErrorKind, Rational, Frame, FrameRateConverter};
 use ffmpeg_common::unsafe_code::format::{FormatContext, InputContext, Stream};
-use ffmpeg_common::unsafe_code::sws::SWSContext;
+use ffmpeg_common::unsafe_code::sws::{SWSContext, SWSImageDefinition, ScalingAlgorithm};
 use ffmpeg_common::unsafe_code::{Packet, DataPacket, EncodingCodecContext, DecodingCodecContext};
 use ffmpeg_common::unsafe_code::StreamConfiguration;
-use ffmpeg_common::networking::{NetworkPacket, NetworkConfiguration};
+use ffmpeg_common::networking::{NetworkPacket, NetworkConfiguration, ClientAcknowledgement, wall_clock_micros};
+use ffmpeg_common::logging::{set_log_field, clear_log_field, CLIENT_FIELD, PLAY_FIELD};
 
 use ffmpeg_sys::*;
 
 enum PacketMessage {
-    Packet(Packet),
-    Flush,
+    /// A camera packet and the wall-clock time it was read at.
+    Packet(Packet, i64),
+    /// Audio that has already been encoded.
+    Encoded(Vec<DataPacket>),
+    /// START for the play with this id was received.
+    StartPlay(i64),
+    /// STOP was received at this wall-clock time.
+    StopPlay(i64),
+    Shutdown,
 }
 
-pub fn send_video(camera_config: CameraConfiguration, message_transfer: Receiver<ClientStatusFlag>, stream: Sender<NetworkPacket>, jpeg_sender: Sender<Arc<Vec<u8>>>, sock: SocketAddr) -> Result<(), UnsafeError> {  
+/// Everything needed to open the output encoder again at another size.
+struct EncoderSettings {
+    profile: EncoderProfile,
+    scaling_algorithm: ScalingAlgorithm,
+    time_base: Rational,
+    frame_rate: Rational,
+}
+
+impl EncoderSettings {
+    /// With a `bit_rate` the encoder targets it instead of the profile's own rate control, so it can be retuned.
+    fn open(&self, height: i32, width: i32, bit_rate: Option<i64>) -> Result<EncodingCodecContext, UnsafeError> {
+        let options = match bit_rate {
+            Some(bit_rate) => self.profile.get_adaptive_codec_options(bit_rate),
+            None => self.profile.get_codec_options(),
+        };
+        EncodingCodecContext::create_encoding_context_with_options(
+            self.profile.get_codec().get_codec_id(),
+            height, width,
+            self.time_base, self.frame_rate,
+            self.profile.get_gop_size(), self.profile.get_max_b_frames(),
+            &options
+        )
+    }
+
+    /// One frame in the encoder time base, for packets the encoder leaves without a duration.
+    fn frame_duration(&self) -> i64 {
+        let frame_rate: AVRational = self.frame_rate.into();
+        if frame_rate.num <= 0 || frame_rate.den <= 0 {
+            return 0;
+        }
+        unsafe {
+            av_rescale_q(1, self.frame_rate.invert().into(), self.time_base.into())
+        }
+    }
+}
+
+pub fn send_video(camera_config: CameraConfiguration, client_id: String, spool: Spool, bandwidth: Bandwidth, message_transfer: Receiver<ClientStatusFlag>, stream: Sender<NetworkPacket>, jpeg_sender: Sender<Arc<Vec<u8>>>, sock: SocketAddr) -> Result<(), UnsafeError> {  
     init_av();
 
     //INPUT ALLOCATION
@@ -32,134 +77,343 @@ pub fn send_video(camera_config: CameraConfiguration, message_transfer: Receiver
     //Grab the stream from the input context
     let mut in_str = input_context.find_input_stream(0).ok_or(UnsafeError::new(UnsafeErrorKind::FindInputStream))?;
 
-    let context_storage = try!(generate_contexts(&mut in_str));
+    let adaptive_bit_rate = camera_config.get_adaptive_settings().map(|adaptive| adaptive.get_max_bit_rate());
+    let (context_storage, encoder_settings) = try!(generate_contexts(&mut in_str, camera_config.get_encoder_profile(), camera_config.get_output_settings(), adaptive_bit_rate));
     let output_stream_configuration = StreamConfiguration::from(<EncodingCodecContext as AsRef<AVCodecContext>>::as_ref(&context_storage.encoding_context));
-    let network_config = NetworkConfiguration::new(output_stream_configuration, sock);
-    let _ = stream.send(NetworkPacket::JSONPayload(network_config));
+    // captured packets are stamped in the stream's time base, not the codec's
+    let capture_time_base = Rational::from(in_str.time_base);
+    let output_time_base = output_stream_configuration.time_base;
+    let constant_frame_rate = camera_config.get_output_settings().get_frame_rate().is_some();
+
+    // audio from a separate device is read on its own thread and handed over through this channel
+    let (audio_packet_tx, audio_packet_rx) = channel();
+    let (mut audio_pipeline, camera_audio_stream) = match camera_config.get_audio_settings() {
+        Some(audio_config) => match open_audio(audio_config, &input_context, audio_packet_tx) {
+            Ok((pipeline, stream_index)) => (Some(pipeline), stream_index),
+            Err(e) => {
+                warn!("Could not open the audio input, recording video only: {}", e);
+                (None, None)
+            },
+        },
+        None => (None, None),
+    };
+    let audio_stream_configuration = audio_pipeline.as_ref().map(|pipeline| pipeline.stream_configuration());
 
-    let sender = jpeg_sender;
+    let render_client_id = client_id.clone();
+    let network_config = NetworkConfiguration::new(client_id, output_stream_configuration, audio_stream_configuration, sock);
+    let _ = stream.send(NetworkPacket::JSONPayload(network_config.clone()));
 
-    let mut currently_recording = false;
-    let mut on_ending_frame = false;
-    let mut packets_read = 0;
-    let render_thread_handle = Cell::new(Option::None);
-    let (packet_tx, _) = channel();
-    let mut sender_cell = Cell::new(packet_tx);
+    let bitrate_controller = camera_config.get_adaptive_settings().map(|adaptive| {
+        BitrateController::new(adaptive.clone(), bandwidth.clone(), output_stream_configuration.width, output_stream_configuration.height)
+    });
+
+    // the encoder runs the whole time the client is connected; the gate picks out what belongs to a play
+    let play_gate = PlayGate::new(camera_config.get_pre_roll(), camera_config.get_post_roll(), output_time_base, audio_stream_configuration.map(|x| x.time_base));
+    let (packet_tx, packet_rx) = channel();
+    let output = RenderOutput {
+        play_gate: play_gate,
+        spooler: PlaySpooler::new(spool, output_stream_configuration),
+        bitrate_controller: bitrate_controller,
+        encoder_settings: encoder_settings,
+        network_config: network_config,
+        bandwidth: bandwidth,
+        stream: stream.clone(),
+    };
+    let render_thread_handle = spawn_thread(context_storage, frame_rate_converter(capture_time_base, output_time_base, constant_frame_rate), output, render_client_id, packet_rx, jpeg_sender);
+    let mut current_play = None;
     loop {
         match message_transfer.try_recv() {
-            Ok(ref m) if m == &ClientStatusFlag::StopRecording => {
-                currently_recording = false;
-                on_ending_frame = true;
+            Ok(ClientStatusFlag::StopRecording) => {
+                clear_log_field(PLAY_FIELD);
+                let _ = packet_tx.send(PacketMessage::StopPlay(wall_clock_micros()));
+                let _ = stream.send(NetworkPacket::Acknowledgement(ClientAcknowledgement::RecordingStopped));
             },
-            Ok(ref m) if m == &ClientStatusFlag::StartRecording => {
-                let (packet_tx, packet_rx) = channel();
-                sender_cell.replace(packet_tx);
-                // check if it's a render thread and panic if it is - desync occured
-                render_thread_handle.replace(Option::from(spawn_thread(context_storage.clone(), stream.clone(), packet_rx, sender.clone())));
-                packets_read = 0;
-                currently_recording = true;
+            Ok(ClientStatusFlag::StartRecording(play_id)) => {
+                // after a reconnect the server repeats the START of a play that is already being recorded
+                if current_play != Some(play_id) {
+                    current_play = Some(play_id);
+                    set_log_field(PLAY_FIELD, play_id);
+                    let _ = packet_tx.send(PacketMessage::StartPlay(play_id));
+                }
+                let _ = stream.send(NetworkPacket::Acknowledgement(ClientAcknowledgement::RecordingStarted));
             },
-            Ok(ref m) if m == &ClientStatusFlag::ServerQuit => {
-                eprintln!("The server has been closed, so the client will now exit the sending routine.");
+            Ok(ClientStatusFlag::ServerQuit) => {
+                info!("The client is shutting down, so it will now exit the sending routine.");
                 break;
             } 
             Err(ref e) if (e != &TryRecvError::Empty) => {
-                eprintln!("An internal error has occured. Please restart the server and client {:?}", e);
+                error!("An internal error has occured. Please restart the server and client {:?}", e);
                 break;
             } 
             _ => {},
         }
-        if currently_recording {
-            let mut packet = input_context.read_input();
-            packet.pts = packets_read;
-            let _ = sender_cell.get_mut().send(PacketMessage::Packet(packet));
-            packets_read = packets_read + 1;                    
-        }
 
-        if on_ending_frame {
-            println!("Read {} packets, now sending flush signal", packets_read);
-            let _ = sender_cell.get_mut().send(PacketMessage::Flush);
+        while let Ok(audio_packet) = audio_packet_rx.try_recv() {
+            send_audio(&mut audio_pipeline, &audio_packet, &packet_tx);
+        }
 
-            render_thread_handle.replace(Option::None).expect("desync").join().expect("couldn't join to thread");
-            on_ending_frame = false;
+        let packet = input_context.read_input();
+        let read_at = wall_clock_micros();
+        if camera_audio_stream == Some(packet.stream_index as usize) {
+            send_audio(&mut audio_pipeline, &packet, &packet_tx);
+        } else {
+            let _ = packet_tx.send(PacketMessage::Packet(packet, read_at));
         }
     }
+
+    match audio_pipeline.as_mut().map(|audio| audio.finish()) {
+        Some(Ok(pkts)) => { let _ = packet_tx.send(PacketMessage::Encoded(pkts)); },
+        Some(Err(e)) => warn!("Could not flush the audio encoder: {}", e),
+        None => {},
+    }
+    let _ = packet_tx.send(PacketMessage::Shutdown);
+    render_thread_handle.join().expect("couldn't join to thread");
     Ok(())
 }
 
-fn generate_contexts(stream: &mut Stream) -> Result<CodecStorage, UnsafeError> {
+/// Opens the configured audio input. A separate capture device gets its own reading thread;
+/// for a stream of the camera input, the index of that stream is returned instead.
+fn open_audio(audio_config: &AudioConfiguration, camera_input: &InputContext, audio_packet_tx: Sender<Packet>) -> Result<(AudioPipeline, Option<usize>), UnsafeError> {
+    match *audio_config.get_source() {
+        AudioSource::CameraStream { stream_index } => {
+            let mut audio_stream = camera_input.find_input_stream(stream_index).ok_or(UnsafeError::new(UnsafeErrorKind::FindInputStream))?;
+            Ok((AudioPipeline::new(&mut audio_stream, audio_config)?, Some(stream_index)))
+        },
+        AudioSource::Device { ref input_type, ref location } => {
+            let input_format: &mut AVInputFormat = InputContext::create_input_format(CString::new(input_type.as_bytes()).expect("Failed to create CString"));
+            let mut audio_input: InputContext = FormatContext::new_input(input_format, CString::new(location.as_bytes()).expect("Failed to create CString"))?;
+            let mut audio_stream = audio_input.find_input_stream(0).ok_or(UnsafeError::new(UnsafeErrorKind::FindInputStream))?;
+            let pipeline = AudioPipeline::new(&mut audio_stream, audio_config)?;
+            thread::spawn(move || {
+                loop {
+                    let packet = audio_input.read_input();
+                    if packet.size > 0 && audio_packet_tx.send(packet).is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok((pipeline, None))
+        },
+    }
+}
+
+fn send_audio(audio_pipeline: &mut Option<AudioPipeline>, packet: &Packet, encode_thread: &Sender<PacketMessage>) {
+    if let Some(ref mut audio) = *audio_pipeline {
+        match audio.transcode_packet(packet) {
+            Ok(ref pkts) if pkts.is_empty() => {},
+            Ok(pkts) => { let _ = encode_thread.send(PacketMessage::Encoded(pkts)); },
+            Err(e) => warn!("Dropped an audio packet: {}", e),
+        }
+    }
+}
+
+fn frame_rate_converter(capture_time_base: Rational, output_time_base: Rational, constant_frame_rate: bool) -> FrameRateConverter {
+    if constant_frame_rate {
+        FrameRateConverter::new(capture_time_base, output_time_base)
+    } else {
+        FrameRateConverter::passthrough(capture_time_base)
+    }
+}
+
+fn generate_contexts(stream: &mut Stream, profile: &EncoderProfile, output: &OutputConfiguration, adaptive_bit_rate: Option<i64>) -> Result<(CodecStorage, EncoderSettings), UnsafeError> {
     //CODEC ALLOCATION
     let decoding_context = try!(DecodingCodecContext::create_decoding_context_from_av_stream(stream));
 
     let stream_configuration = StreamConfiguration::from(stream as &_);
+    let output_height = output.get_height().unwrap_or(stream_configuration.height);
+    let output_width = output.get_width().unwrap_or(stream_configuration.width);
+    // a fixed output rate gets one tick per frame; otherwise the camera's own timestamps are kept
+    let (time_base, frame_rate) = match output.get_frame_rate() {
+        Some(rate) => (Rational::new(1, rate), Rational::new(rate, 1)),
+        None => (Rational::from(stream.time_base), stream_configuration.frame_rate),
+    };
 
-    let encoding_context = EncodingCodecContext::create_encoding_context(
-        CodecId::from(AVCodecID::AV_CODEC_ID_H264), 
-        stream_configuration.height, stream_configuration.width, 
-        Rational::new(1, 30),
-        0, 0
-    )?;
+    let encoder_settings = EncoderSettings {
+        profile: profile.clone(),
+        scaling_algorithm: output.get_scaling_algorithm(),
+        time_base: time_base,
+        frame_rate: frame_rate,
+    };
+    let encoding_context = encoder_settings.open(output_height, output_width, adaptive_bit_rate)?;
 
+    // the local preview is taken straight from the camera, so it keeps the full resolution
     let png_context = EncodingCodecContext::create_png_context(
         stream_configuration.height, stream_configuration.width,
-        Rational::new(1, 30)
+        time_base
     )?;
 
-    let png_sws_context = SWSContext::new(stream_configuration.height, stream_configuration.width, AVPixelFormat::AV_PIX_FMT_YUV420P, AVPixelFormat::AV_PIX_FMT_RGB24)?;
+    let png_sws_context = SWSContext::new(stream_configuration.height, stream_configuration.width, *stream_configuration.pix_fmt, AVPixelFormat::AV_PIX_FMT_RGB24)?;
 
     // SWS ALLOCATION
-    let sws_context = try!(SWSContext::new(stream_configuration.height, stream_configuration.width, *stream_configuration.pix_fmt, AVPixelFormat::AV_PIX_FMT_YUV420P));
+    let sws_context = try!(SWSContext::new_scaled(
+        SWSImageDefinition::new(stream_configuration.height, stream_configuration.width, stream_configuration.pix_fmt),
+        SWSImageDefinition::new(output_height, output_width, AVPixelFormat::AV_PIX_FMT_YUV420P),
+        output.get_scaling_algorithm()
+    ));
     let context_storage: CodecStorage = CodecStorage::new(encoding_context, decoding_context, png_context, sws_context, png_sws_context);
 
 
-    Ok(context_storage)
+    Ok((context_storage, encoder_settings))
+}
+
+/// Opens the output encoder and scaler at a new size, returning what the server needs to know about it.
+fn resize_output(context_storage: &mut CodecStorage, settings: &EncoderSettings, width: i32, height: i32, bit_rate: Option<i64>) -> Result<StreamConfiguration, UnsafeError> {
+    let encoding_context = settings.open(height, width, bit_rate)?;
+    let input = *context_storage.sws_context.get_input();
+    let sws_context = SWSContext::new_scaled(input, SWSImageDefinition::new(height, width, AVPixelFormat::AV_PIX_FMT_YUV420P), settings.scaling_algorithm)?;
+    // whatever the old encoder still holds is only pre-roll, which is thrown away with it
+    context_storage.encoding_context = encoding_context;
+    context_storage.sws_context = sws_context;
+    Ok(StreamConfiguration::from(<EncodingCodecContext as AsRef<AVCodecContext>>::as_ref(&context_storage.encoding_context)))
+}
+
+/// Where the render thread's packets go once they are encoded, and what steers the encoder.
+struct RenderOutput {
+    play_gate: PlayGate,
+    spooler: PlaySpooler,
+    bitrate_controller: Option<BitrateController>,
+    encoder_settings: EncoderSettings,
+    /// Sent again whenever the output size changes.
+    network_config: NetworkConfiguration,
+    bandwidth: Bandwidth,
+    stream: Sender<NetworkPacket>,
+}
+
+impl RenderOutput {
+    /// Every packet is spooled, then counted against the backlog while it waits for the uplink.
+    fn send(&mut self, outgoing: Vec<NetworkPacket>) {
+        for network_packet in outgoing {
+            self.spooler.record(&network_packet);
+            let bytes = payload_bytes(&network_packet);
+            if self.stream.send(network_packet).is_ok() {
+                self.bandwidth.queued(bytes);
+            }
+        }
+    }
+
+    fn adapt(&mut self, context_storage: &mut CodecStorage) {
+        let recording = self.play_gate.is_recording();
+        let adjustment = match self.bitrate_controller {
+            Some(ref mut controller) => controller.evaluate(recording),
+            None => return,
+        };
+        let bit_rate = self.bitrate_controller.as_ref().map(|controller| controller.bit_rate());
+        match adjustment {
+            Some(Adjustment::BitRate(bit_rate)) => {
+                info!("Retargeting the encoder to {} bit/s, {} bytes waiting to be sent", bit_rate, self.bandwidth.backlog());
+                context_storage.encoding_context.set_bit_rate(bit_rate);
+            },
+            Some(Adjustment::Resolution(width, height)) => {
+                match resize_output(context_storage, &self.encoder_settings, width, height, bit_rate) {
+                    Ok(stream_configuration) => {
+                        info!("Switched the output to {}x{}", width, height);
+                        // the pre-roll was encoded at the old size and cannot start a clip at the new one
+                        self.play_gate.discard_buffer();
+                        self.network_config.stream_configuration = stream_configuration;
+                        let configuration = NetworkPacket::JSONPayload(self.network_config.clone());
+                        self.send(vec![configuration]);
+                    },
+                    Err(e) => error!("Could not switch the output to {}x{}: {}", width, height, e),
+                }
+            },
+            None => {},
+        }
+    }
 }
 
-fn spawn_thread(mut context_storage: CodecStorage, stream: Sender<NetworkPacket>, packet_rx: Receiver<PacketMessage>, png_sender: Sender<Arc<Vec<u8>>>) -> JoinHandle<Sender<Arc<Vec<u8>>>> {
+fn spawn_thread(mut context_storage: CodecStorage, mut frame_rate: FrameRateConverter, mut output: RenderOutput, client_id: String, packet_rx: Receiver<PacketMessage>, png_sender: Sender<Arc<Vec<u8>>>) -> JoinHandle<()> {
     thread::spawn(move || {
-        let mut time = 0;
+        set_log_field(CLIENT_FIELD, client_id);
+        // the first frame becomes pts 0, so its read time anchors every later capture time
+        let mut capture_start = None;
+        let mut force_keyframe = false;
+        let frame_duration = output.encoder_settings.frame_duration();
         for item in packet_rx.iter() {
-            match item {
-                PacketMessage::Packet(packet) => {
-                    let conv_pkt_attempt = transcode_packet(&mut context_storage, &png_sender, packet, time);
-                    if let Ok(conv_pkt) = conv_pkt_attempt {
-                        let _ = stream.send(conv_pkt);
-                        time = time + 1;
-                    } else {
-                        println!("failed to conv pkt: {:?}", conv_pkt_attempt);
-                        break;
+            let outgoing = match item {
+                PacketMessage::Packet(packet, read_at) => {
+                    let capture_start = *capture_start.get_or_insert(read_at);
+                    match transcode_packet(&mut context_storage, &mut frame_rate, &png_sender, packet, capture_start, frame_duration, &mut force_keyframe) {
+                        Ok(pkts) => output.play_gate.push(pkts),
+                        Err(ref e) if output.play_gate.is_recording() => {
+                            error!("failed to conv pkt: {:?}", e);
+                            let _ = output.stream.send(NetworkPacket::Acknowledgement(ClientAcknowledgement::RecordingFailed));
+                            output.play_gate.end_play()
+                        },
+                        Err(e) => {
+                            warn!("failed to conv pkt: {:?}", e);
+                            Vec::new()
+                        },
                     }
                 },
-                PacketMessage::Flush => { break; }
-            }
+                PacketMessage::Encoded(pkts) => output.play_gate.push(pkts),
+                PacketMessage::StartPlay(play_id) => {
+                    output.spooler.start_play(play_id);
+                    let (outgoing, needs_keyframe) = output.play_gate.start();
+                    force_keyframe = needs_keyframe;
+                    outgoing
+                },
+                PacketMessage::StopPlay(stopped_at) => output.play_gate.stop(stopped_at),
+                PacketMessage::Shutdown => break,
+            };
+            output.send(outgoing);
+            output.adapt(&mut context_storage);
         }
-        println!("flushing packets");
-        let null_pkt_attempt = context_storage.encoding_context.encode_null_frame();
-        if let Ok(null_pkt) = null_pkt_attempt {
-            println!("sending null pkt of len {}", null_pkt.len());
-            let _ = stream.send(NetworkPacket::PacketStream(null_pkt.into_iter().map(|x: Packet| DataPacket::from(x)).collect()));
-        } else {
-            println!("error sending null pkt");
+
+        debug!("flushing packets");
+        let mut outgoing = Vec::new();
+        match context_storage.encoding_context.encode_null_frame() {
+            Ok(null_pkt) => {
+                let time_base = frame_rate.get_output_time_base();
+                outgoing.extend(output.play_gate.push(null_pkt.into_iter().map(|x| video_data_packet(x, capture_start.unwrap_or(0), time_base, frame_duration)).collect()));
+            },
+            Err(e) => warn!("error sending null pkt: {:?}", e),
         }
-        println!("finished sending");
-        let _ = stream.send(NetworkPacket::PayloadEnd);
-        png_sender
+        outgoing.extend(output.play_gate.end_play());
+        output.send(outgoing);
+        debug!("finished sending");
     })
 }
 
-fn transcode_packet(contexts: &mut CodecStorage, png_sender: &Sender<Arc<Vec<u8>>>, packet: Packet, frame_loc: i64) -> Result<NetworkPacket, UnsafeError> {
+fn transcode_packet(contexts: &mut CodecStorage, frame_rate: &mut FrameRateConverter, png_sender: &Sender<Arc<Vec<u8>>>, packet: Packet, capture_start: i64, frame_duration: i64, force_keyframe: &mut bool) -> Result<Vec<DataPacket>, UnsafeError> {
     let mut raw_frame: Frame = try!(contexts.decoding_context.decode_packet(&packet));
 
-    let mut scaled_frame: Frame = contexts.sws_context.change_pixel_format(&mut raw_frame, 32, frame_loc)?;
-    let png_frame: Frame = contexts.png_sws_context.change_pixel_format(&mut scaled_frame, 32, frame_loc)?;
-    println!("current frame pts: {}", scaled_frame.pts);
+    let capture_pts = raw_frame.capture_timestamp();
 
+    let png_frame: Frame = contexts.png_sws_context.scale_frame(&mut raw_frame, 32, capture_pts)?;
     match contexts.png_context.encode_png_frame(&png_frame) {
         Ok(e) => { let _ = png_sender.send(Arc::new(e)); },
-        Err(e) => println!("{:?}", e),
+        Err(e) => warn!("Could not encode the preview frame: {:?}", e),
     }
 
+    // the frame is encoded once per output tick it covers: not at all if it came in early, repeatedly to fill a gap
+    let output_pts = frame_rate.convert(capture_pts);
+    let mut pkts = Vec::new();
+    if output_pts.start < output_pts.end {
+        let scaled_frame: Frame = contexts.sws_context.scale_frame(&mut raw_frame, 32, output_pts.start)?;
+        trace!("current frame pts: {}..{}", output_pts.start, output_pts.end);
+        for pts in output_pts {
+            let mut frame = scaled_frame.duplicate();
+            frame.pts = pts;
+            if *force_keyframe {
+                // a play with no pre-roll has to start on a keyframe of its own
+                frame.pict_type = AVPictureType::AV_PICTURE_TYPE_I;
+                *force_keyframe = false;
+            }
+            pkts.extend(try!(contexts.encoding_context.encode_frame(frame)));
+        }
+    }
 
-    let pkts = try!(contexts.encoding_context.encode_frame(scaled_frame));
-    Ok(NetworkPacket::PacketStream(pkts.into_iter().map(|x: Packet| DataPacket::from(x)).collect()))
+    let time_base = frame_rate.get_output_time_base();
+    Ok(pkts.into_iter().map(|x| video_data_packet(x, capture_start, time_base, frame_duration)).collect())
+}
+
+/// Video encoders leave the duration unset, so every packet is taken to last one frame; without it
+/// the server's clip would come up a frame short.
+fn video_data_packet(pkt: Packet, capture_start: i64, time_base: Rational, frame_duration: i64) -> DataPacket {
+    let mut data_packet = DataPacket::from(pkt);
+    data_packet.stamp_capture_time(capture_start, time_base);
+    if data_packet.duration == 0 {
+        data_packet.duration = frame_duration;
+    }
+    data_packet
 }
\ No newline at end of file
@@ -0,0 +1,218 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use client::ClientError;
+use ffmpeg_common::unsafe_code::{UnsafeError, StreamConfiguration};
+use ffmpeg_common::networking::{NetworkPacket, wall_clock_micros};
+
+const PACKETS_FILE: &'static str = "packets.bin";
+const INDEX_FILE: &'static str = "index.json";
+
+/// What is known about one spooled play without reading its packets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpoolIndex {
+    pub play_id: i64,
+    /// Data packets spooled so far. The server reports its own count when it stores the clip.
+    pub packets: u64,
+    /// Wall-clock microseconds the play ended at, or `None` while it is still being recorded.
+    pub completed_at: Option<i64>,
+    /// The video stream the play was encoded as, which may differ from the current one after a resolution change.
+    #[serde(default)]
+    pub stream_configuration: Option<StreamConfiguration>,
+}
+
+/// Every play is written to `<spool directory>/<play id>/` as wire frames while it is sent, with a
+/// small JSON index next to it. A play is deleted once the server confirms it stored every packet.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    directory: PathBuf,
+}
+
+impl Spool {
+    /// Plays left unfinished by an earlier run are closed off with whatever made it to disk.
+    pub fn open(directory: &Path) -> Result<Spool, ClientError> {
+        fs::create_dir_all(directory)?;
+        let spool = Spool { directory: directory.to_owned() };
+        for mut index in spool.indices()? {
+            if index.completed_at.is_none() {
+                index.packets = spool.count_packets(index.play_id)?;
+                // old enough to be replayed as soon as the server is reachable
+                index.completed_at = Some(0);
+                spool.write_index(&index)?;
+                info!("Recovered {} spooled packets of play {}", index.packets, index.play_id);
+            }
+        }
+        Ok(spool)
+    }
+
+    pub fn begin(&self, play_id: i64, stream_configuration: StreamConfiguration) -> Result<SpoolWriter, ClientError> {
+        fs::create_dir_all(self.play_directory(play_id))?;
+        let index = SpoolIndex { play_id: play_id, packets: 0, completed_at: None, stream_configuration: Some(stream_configuration) };
+        self.write_index(&index)?;
+        let packets = File::create(self.play_directory(play_id).join(PACKETS_FILE))?;
+        Ok(SpoolWriter { spool: self.clone(), index: index, packets: BufWriter::new(packets) })
+    }
+
+    /// Finished plays the server has not confirmed yet, that ended before `ended_before`.
+    pub fn unconfirmed(&self, ended_before: i64) -> Vec<SpoolIndex> {
+        match self.indices() {
+            Ok(indices) => indices.into_iter().filter(|index| index.completed_at.map(|x| x <= ended_before).unwrap_or(false)).collect(),
+            Err(e) => {
+                warn!("Could not read the spool at {}: {}", self.directory.display(), e);
+                Vec::new()
+            },
+        }
+    }
+
+    pub fn read_packets(&self, play_id: i64) -> Result<BufReader<File>, ClientError> {
+        Ok(BufReader::new(File::open(self.play_directory(play_id).join(PACKETS_FILE))?))
+    }
+
+    /// The server stored `packets` packets of a play. The spooled copy is only dropped if none went missing.
+    pub fn confirm(&self, play_id: i64, packets: u64) -> bool {
+        let index = match self.read_index(&self.play_directory(play_id)) {
+            Ok(index) => index,
+            // plays that never got a packet are not spooled
+            Err(_) => return false,
+        };
+        if index.completed_at.is_none() || index.packets != packets {
+            warn!("The server stored {} of {} spooled packets of play {}, it will be sent again", packets, index.packets, play_id);
+            return false;
+        }
+        match fs::remove_dir_all(self.play_directory(play_id)) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Could not remove spooled play {}: {}", play_id, e);
+                false
+            },
+        }
+    }
+
+    fn play_directory(&self, play_id: i64) -> PathBuf {
+        self.directory.join(play_id.to_string())
+    }
+
+    fn indices(&self) -> Result<Vec<SpoolIndex>, ClientError> {
+        let mut indices = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            match self.read_index(&path) {
+                Ok(index) => indices.push(index),
+                Err(e) => warn!("Skipping spool entry {}: {}", path.display(), e),
+            }
+        }
+        Ok(indices)
+    }
+
+    fn read_index(&self, play_directory: &Path) -> Result<SpoolIndex, ClientError> {
+        let file = File::open(play_directory.join(INDEX_FILE))?;
+        Ok(serde_json::from_reader(file).map_err(UnsafeError::from)?)
+    }
+
+    /// The index is replaced in one rename, so a crash never leaves half of one behind.
+    fn write_index(&self, index: &SpoolIndex) -> Result<(), ClientError> {
+        let play_directory = self.play_directory(index.play_id);
+        let temporary = play_directory.join(String::from(INDEX_FILE) + ".tmp");
+        {
+            let mut file = File::create(&temporary)?;
+            file.write_all(&serde_json::to_vec(index).map_err(UnsafeError::from)?)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temporary, play_directory.join(INDEX_FILE))?;
+        Ok(())
+    }
+
+    /// Counts the data packets that can still be read back, ignoring a frame cut short by a crash.
+    fn count_packets(&self, play_id: i64) -> Result<u64, ClientError> {
+        let mut reader = self.read_packets(play_id)?;
+        let mut count = 0;
+        loop {
+            match NetworkPacket::read_from(&mut reader) {
+                Ok(NetworkPacket::PacketStream(ref pkts)) => count += pkts.len() as u64,
+                Ok(_) => {},
+                Err(_) => break,
+            }
+        }
+        Ok(count)
+    }
+}
+
+pub struct SpoolWriter {
+    spool: Spool,
+    index: SpoolIndex,
+    packets: BufWriter<File>,
+}
+
+impl SpoolWriter {
+    pub fn append(&mut self, network_packet: &NetworkPacket) -> Result<(), ClientError> {
+        if let NetworkPacket::PacketStream(ref pkts) = *network_packet {
+            network_packet.write_to(&mut self.packets)?;
+            self.index.packets += pkts.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), ClientError> {
+        self.packets.flush()?;
+        self.index.completed_at = Some(wall_clock_micros());
+        self.spool.write_index(&self.index)
+    }
+}
+
+/// Follows the packets leaving the play gate and writes each play's share of them to the spool.
+pub struct PlaySpooler {
+    spool: Spool,
+    stream_configuration: StreamConfiguration,
+    next_play: Option<i64>,
+    current: Option<SpoolWriter>,
+}
+
+impl PlaySpooler {
+    pub fn new(spool: Spool, stream_configuration: StreamConfiguration) -> PlaySpooler {
+        PlaySpooler {
+            spool: spool,
+            stream_configuration: stream_configuration,
+            next_play: None,
+            current: None,
+        }
+    }
+
+    /// The next play the gate lets packets through for. The end of the previous play may still be on its way.
+    pub fn start_play(&mut self, play_id: i64) {
+        self.next_play = Some(play_id);
+    }
+
+    pub fn record(&mut self, network_packet: &NetworkPacket) {
+        match *network_packet {
+            NetworkPacket::PacketStream(_) => {
+                if self.current.is_none() {
+                    if let Some(play_id) = self.next_play.take() {
+                        match self.spool.begin(play_id, self.stream_configuration) {
+                            Ok(writer) => self.current = Some(writer),
+                            Err(e) => error!("Could not spool play {}: {}", play_id, e),
+                        }
+                    }
+                }
+                let result = match self.current {
+                    Some(ref mut writer) => writer.append(network_packet),
+                    None => Ok(()),
+                };
+                if let Err(e) = result {
+                    error!("Stopped spooling the current play: {}", e);
+                    self.current = None;
+                }
+            },
+            NetworkPacket::PayloadEnd => {
+                if let Some(Err(e)) = self.current.take().map(|writer| writer.finish()) {
+                    error!("Could not finish spooling the current play: {}", e);
+                }
+            },
+            NetworkPacket::JSONPayload(ref configuration) => self.stream_configuration = configuration.stream_configuration,
+            _ => {},
+        }
+    }
+}
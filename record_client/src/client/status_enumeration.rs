@@ -2,7 +2,8 @@ use std::marker::{Send, Sync};
 
 #[derive(Debug, PartialEq)]
 pub enum ClientStatusFlag {
-    StartRecording,
+    /// Carries the server's id for the play, which the spool files it under.
+    StartRecording(i64),
     StopRecording,
     ServerQuit,
 }
@@ -0,0 +1,299 @@
+use std::io::BufWriter;
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use client::{Spool, SpoolIndex, Bandwidth, payload_bytes};
+use ffmpeg_common::networking::{Connection, NetworkPacket, NetworkConfiguration, VIDEO_STREAM_INDEX, HEARTBEAT_INTERVAL_SECS, PROTOCOL_VERSION, wall_clock_micros};
+use ffmpeg_common::unsafe_code::{DataPacket, StreamConfiguration};
+
+/// How often the uplink wakes up to look after the connection while nothing is being sent.
+const UPLINK_POLL_MILLIS: u64 = 250;
+/// A finished play the server has not confirmed after this many seconds is sent again from the spool.
+const SPOOL_CONFIRM_SECS: i64 = 10;
+
+pub enum UplinkControl {
+    /// A new connection to the server, which has not finished its handshake yet. Our hello goes out first.
+    Connected(Connection),
+    /// The wire protocol version the server picked from our hello.
+    Version(u8),
+    /// Our answer to the server's team key challenge. Until it is sent the server takes nothing else.
+    Authenticate(Vec<u8>),
+    /// The server finished the handshake and takes any packet now.
+    Ready,
+    Disconnected,
+}
+
+/// Owns the sending half of the connection to the server. The camera keeps running while the client
+/// is offline and what it sends in the meantime is dropped; plays are replayed from the spool instead.
+/// When the link cannot keep up, whole GOPs are left out rather than letting the backlog grow; the
+/// server's packet count then comes up short and the play is replayed in full from the spool.
+pub struct Uplink {
+    connection: Option<BufWriter<Connection>>,
+    /// The wire protocol version agreed on for the current connection.
+    version: u8,
+    authenticated: bool,
+    ready: bool,
+    configuration: Option<NetworkConfiguration>,
+    spool: Spool,
+    bandwidth: Bandwidth,
+    /// Set at a keyframe and kept until the next one, so a GOP is either sent whole or not at all.
+    dropping: bool,
+    /// Plays already replayed over this connection, so one the server cannot store is not sent over and over.
+    replayed: HashSet<i64>,
+    last_write: Instant,
+    next_replay_check: Instant,
+}
+
+impl Uplink {
+    pub fn new(spool: Spool, bandwidth: Bandwidth) -> Uplink {
+        Uplink {
+            connection: None,
+            version: PROTOCOL_VERSION,
+            authenticated: false,
+            ready: false,
+            configuration: None,
+            spool: spool,
+            bandwidth: bandwidth,
+            dropping: false,
+            replayed: HashSet::new(),
+            last_write: Instant::now(),
+            next_replay_check: Instant::now(),
+        }
+    }
+
+    /// Runs until every sender of `packets` is gone.
+    pub fn run(mut self, packets: Receiver<NetworkPacket>, control: Receiver<UplinkControl>) {
+        loop {
+            while let Ok(instruction) = control.try_recv() {
+                self.handle(instruction);
+            }
+            match packets.recv_timeout(Duration::from_millis(UPLINK_POLL_MILLIS)) {
+                Ok(item) => self.send(item),
+                Err(RecvTimeoutError::Timeout) => {},
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if self.ready && self.last_write.elapsed() >= Duration::from_secs(HEARTBEAT_INTERVAL_SECS) {
+                self.write(NetworkPacket::Heartbeat);
+            }
+            self.replay_unconfirmed();
+        }
+    }
+
+    fn handle(&mut self, instruction: UplinkControl) {
+        match instruction {
+            UplinkControl::Connected(stream) => {
+                self.connection = Some(BufWriter::new(stream));
+                self.version = PROTOCOL_VERSION;
+                self.authenticated = false;
+                self.ready = false;
+                self.replayed.clear();
+                self.write(NetworkPacket::hello());
+            },
+            UplinkControl::Version(version) => self.version = version,
+            UplinkControl::Authenticate(answer) => {
+                self.write(NetworkPacket::Authentication(answer));
+                self.authenticated = self.connection.is_some();
+                // the server expects the configuration right after the answer
+                if let Some(configuration) = self.configuration.clone() {
+                    self.write(NetworkPacket::JSONPayload(configuration));
+                }
+            },
+            UplinkControl::Ready => {
+                self.ready = true;
+                self.next_replay_check = Instant::now();
+            },
+            UplinkControl::Disconnected => {
+                self.connection = None;
+                self.authenticated = false;
+                self.ready = false;
+            },
+        }
+    }
+
+    fn send(&mut self, mut item: NetworkPacket) {
+        self.bandwidth.dequeued(payload_bytes(&item));
+        if let NetworkPacket::PacketStream(ref mut pkts) = item {
+            self.drop_behind(pkts);
+            if pkts.is_empty() {
+                return;
+            }
+        }
+        let part_of_handshake = match item {
+            NetworkPacket::JSONPayload(ref configuration) => {
+                self.configuration = Some(configuration.clone());
+                true
+            },
+            NetworkPacket::ClockSync(ref mut sample) => {
+                // the reply may have queued behind video, so it is stamped again just before it goes out
+                sample.client_send = wall_clock_micros();
+                true
+            },
+            _ => false,
+        };
+        if (part_of_handshake && self.authenticated) || self.ready {
+            self.write(item);
+        }
+    }
+
+    /// Leaves out video packets until the next keyframe once the backlog is past its limit. Audio
+    /// is small and always sent, so the server's clip keeps its sound.
+    fn drop_behind(&mut self, pkts: &mut Vec<DataPacket>) {
+        let mut dropping = self.dropping;
+        let bandwidth = &self.bandwidth;
+        pkts.retain(|pkt| {
+            if pkt.stream_index != VIDEO_STREAM_INDEX {
+                return true;
+            }
+            if pkt.is_keyframe() {
+                let behind = bandwidth.falling_behind();
+                if behind != dropping {
+                    if behind {
+                        warn!("The link is {} bytes behind, leaving out video until it catches up", bandwidth.backlog());
+                    } else {
+                        info!("The link caught up, sending video again");
+                    }
+                }
+                dropping = behind;
+            }
+            !dropping
+        });
+        self.dropping = dropping;
+    }
+
+    fn write(&mut self, item: NetworkPacket) {
+        let failed = match self.connection {
+            Some(ref mut connection) => item.write_versioned(connection, self.version).is_err(),
+            None => return,
+        };
+        if failed {
+            // the reading side notices as well, and reconnects
+            self.connection = None;
+            self.authenticated = false;
+            self.ready = false;
+        } else {
+            self.last_write = Instant::now();
+        }
+    }
+
+    fn replay_unconfirmed(&mut self) {
+        if !self.ready || Instant::now() < self.next_replay_check {
+            return;
+        }
+        self.next_replay_check = Instant::now() + Duration::from_secs(HEARTBEAT_INTERVAL_SECS);
+        let ended_before = wall_clock_micros() - SPOOL_CONFIRM_SECS * 1_000_000;
+        for index in self.spool.unconfirmed(ended_before) {
+            if self.ready && self.replayed.insert(index.play_id) {
+                self.replay(&index);
+            }
+        }
+    }
+
+    fn replay(&mut self, index: &SpoolIndex) {
+        let mut reader = match self.spool.read_packets(index.play_id) {
+            Ok(reader) => reader,
+            Err(e) => {
+                error!("Could not read spooled play {}: {}", index.play_id, e);
+                return;
+            },
+        };
+        info!("Replaying {} spooled packets of play {}", index.packets, index.play_id);
+        // a play recorded before a resolution change has to be announced at the size it was encoded at
+        let configuration = self.configuration.clone();
+        let recorded_as = match (configuration.clone(), index.stream_configuration) {
+            (Some(mut configuration), Some(stream_configuration)) if !same_size(&configuration, &stream_configuration) => {
+                configuration.stream_configuration = stream_configuration;
+                Some(configuration)
+            },
+            _ => None,
+        };
+        let resized = recorded_as.is_some();
+        if let Some(recorded_as) = recorded_as {
+            self.write(NetworkPacket::JSONPayload(recorded_as));
+        }
+        self.write(NetworkPacket::Replay(index.play_id));
+        while let Ok(network_packet) = NetworkPacket::read_from(&mut reader) {
+            if !self.ready {
+                return;
+            }
+            self.write(network_packet);
+        }
+        self.write(NetworkPacket::PayloadEnd);
+        if let (true, Some(configuration)) = (resized, configuration) {
+            self.write(NetworkPacket::JSONPayload(configuration));
+        }
+    }
+}
+
+fn same_size(configuration: &NetworkConfiguration, stream_configuration: &StreamConfiguration) -> bool {
+    configuration.stream_configuration.width == stream_configuration.width && configuration.stream_configuration.height == stream_configuration.height
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use uuid::Uuid;
+    use ffmpeg_common::networking::AUDIO_STREAM_INDEX;
+
+    const DROP_THRESHOLD: usize = 1000;
+
+    fn packet(stream_index: i32, capture_time: i64, keyframe: bool) -> DataPacket {
+        DataPacket {
+            packet: vec![0; 4],
+            pts: capture_time,
+            dts: capture_time,
+            capture_time: capture_time,
+            duration: 0,
+            stream_index: stream_index,
+            flags: if keyframe { DataPacket::KEYFRAME_FLAG } else { 0 },
+        }
+    }
+
+    fn video(capture_time: i64, keyframe: bool) -> DataPacket {
+        packet(VIDEO_STREAM_INDEX, capture_time, keyframe)
+    }
+
+    fn audio(capture_time: i64) -> DataPacket {
+        packet(AUDIO_STREAM_INDEX, capture_time, false)
+    }
+
+    /// Runs `pkts` through the uplink's GOP dropping and returns the capture times it kept.
+    fn kept(uplink: &mut Uplink, mut pkts: Vec<DataPacket>) -> Vec<i64> {
+        uplink.drop_behind(&mut pkts);
+        pkts.iter().map(|pkt| pkt.capture_time).collect()
+    }
+
+    fn with_uplink<F: FnOnce(&mut Uplink, &Bandwidth)>(test: F) {
+        let directory = env::temp_dir().join(format!("uplink-{}", Uuid::new_v4()));
+        let bandwidth = Bandwidth::new();
+        bandwidth.set_drop_threshold(DROP_THRESHOLD);
+        let mut uplink = Uplink::new(Spool::open(&directory).unwrap(), bandwidth.clone());
+        test(&mut uplink, &bandwidth);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_gop_started_while_behind_is_dropped_up_to_the_next_keyframe() {
+        with_uplink(|uplink, bandwidth| {
+            bandwidth.queued(DROP_THRESHOLD + 1);
+            assert_eq!(kept(uplink, vec![video(0, true), video(1, false), audio(2), video(3, false)]), vec![2]);
+
+            // caught up in the middle of the GOP, which still goes unsent
+            bandwidth.dequeued(DROP_THRESHOLD + 1);
+            assert_eq!(kept(uplink, vec![video(4, false), audio(5), video(6, true), video(7, false)]), vec![5, 6, 7]);
+        });
+    }
+
+    #[test]
+    fn a_gop_started_in_time_is_sent_whole() {
+        with_uplink(|uplink, bandwidth| {
+            assert_eq!(kept(uplink, vec![video(0, true), video(1, false)]), vec![0, 1]);
+
+            // fell behind in the middle of the GOP, which is still sent to its end
+            bandwidth.queued(DROP_THRESHOLD + 1);
+            assert_eq!(kept(uplink, vec![video(2, false), audio(3), video(4, true), video(5, false)]), vec![2, 3]);
+        });
+    }
+}
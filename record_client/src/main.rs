@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate log;
 
 extern crate iron;
 extern crate router;
@@ -18,8 +20,6 @@ extern crate base64;
 extern crate rand;
 extern crate ffmpeg_common;
 
-use std::net::{SocketAddr};
-use std::str::FromStr;
 use std::env;
 use std::path::Path;
 use std::fs::File;
@@ -27,6 +27,7 @@ use std::fs::File;
 mod client;
 use client::{ClientError, ClientConfiguration};
 use client::client_struct::{Client};
+use ffmpeg_common::logging::init_logging;
 
 fn main() {
     let result = run_client();
@@ -40,7 +41,7 @@ fn run_client() -> Result<(), ClientError> {
         Err(e) => return Err(ClientError::from(e)),
     };    
     let config_path = Path::new(&configuration_location);
-    let client_config = 
+    let mut client_config = 
         if config_path.exists() {
             let fs = File::open(config_path)?;
             ClientConfiguration::from(fs)?
@@ -50,12 +51,15 @@ fn run_client() -> Result<(), ClientError> {
             cfg.write_to(fs)?;
             cfg
         };
+    if client_config.assign_client_id() {
+        let fs = File::create(config_path)?;
+        client_config.write_to(fs)?;
+    }
+    init_logging(client_config.get_logging_settings())?;
 
-    let mut client = Client::new(client_config.get_name(), (SocketAddr::from_str("127.0.0.1:8000")?, SocketAddr::from_str("127.0.0.1:4000")?, SocketAddr::from_str("127.0.0.1:8070")?))?;
+    let mut client = Client::new(&client_config)?;
 
     let sender = client.get_web_handler_ref().get_sender();
 
-    let _ = client.stream_handler(client_config.get_camera_settings().clone(), sender);
-
-    Ok(())
+    client.run(&client_config, sender)
 }
@@ -0,0 +1,49 @@
+// The API wants the control panel key the server prints when it starts.
+const KEY_STORAGE = 'sports_record_control_panel_key'
+
+export function getKey () {
+  return window.localStorage.getItem(KEY_STORAGE) || ''
+}
+
+export function setKey (key) {
+  window.localStorage.setItem(KEY_STORAGE, key)
+}
+
+export class UnauthorizedError extends Error {}
+
+// Resolves with the `result` of an ApiResponse, or rejects with its error.
+export function api (method, path, body) {
+  const options = {
+    method: method,
+    headers: { 'Authorization': 'Bearer ' + getKey() }
+  }
+  if (body !== undefined) {
+    options.headers['Content-Type'] = 'application/json'
+    options.body = JSON.stringify(body)
+  }
+  return fetch(path, options).then(res => {
+    if (res.status === 401) {
+      throw new UnauthorizedError('missing or invalid control panel key')
+    }
+    return res.json()
+  }).then(response => {
+    if (!response.success) {
+      throw new Error(response.error || 'the request failed')
+    }
+    return response.result
+  })
+}
+
+// The addresses of the cameras' own preview sockets.
+export function connectedCameras () {
+  return fetch('/dist/connected_servers.json').then(res => res.json())
+}
+
+export function formatDuration (micros) {
+  if (micros === null || micros === undefined) {
+    return '–'
+  }
+  const seconds = Math.round(micros / 1000000)
+  const rest = seconds % 60
+  return Math.floor(seconds / 60) + ':' + (rest < 10 ? '0' : '') + rest
+}
@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate log;
 
 extern crate iron;
 extern crate router;
@@ -27,6 +29,8 @@ use std::io::{stdin, BufRead};
 
 mod server;
 use server::{ServerError, ServerConfiguration, RecordingServer};
+use server::client_handling::ClientCommandResult;
+use ffmpeg_common::logging::init_logging;
 
 fn main() {
     println!("{:?}", run_server());
@@ -50,6 +54,7 @@ fn run_server() -> Result<(), ServerError> {
             cfg.write_to(fs)?;
             cfg
         }; 
+    init_logging(server_config.get_logging_settings())?;
     let server = RecordingServer::new(server_config)?;
 
     let mut messenger = server.get_client_handler();
@@ -64,10 +69,10 @@ fn run_server() -> Result<(), ServerError> {
         let unwrapped_line = try!(line);
         if unwrapped_line == "START" {
             println!("Starting recording");
-            let _ = messenger.start_recording();
+            report_command("Recording started", messenger.start_recording());
         } else if unwrapped_line == "STOP" {
             println!("Stopping recording");
-            let _ = messenger.stop_recording();
+            report_command("Recording stopped", messenger.stop_recording());
         } else if unwrapped_line == "CLEAN" {
             println!("Stopping server");
             let _ = messenger.clean_up();
@@ -91,6 +96,20 @@ fn run_server() -> Result<(), ServerError> {
 
 }
 
+/// Logs how many clients a recording command reached, or why it was refused.
+fn report_command(action: &str, result: Result<Vec<ClientCommandResult>, ServerError>) {
+    match result {
+        Ok(results) => {
+            let delivered = results.iter().filter(|result| result.delivered).count();
+            info!("{} on {}/{} clients", action, delivered, results.len());
+            for result in results.iter().filter(|result| !result.delivered) {
+                warn!("Could not reach client {}", result.client);
+            }
+        },
+        Err(e) => warn!("{}", e),
+    }
+}
+
 fn string_to_socket(ip: &str) -> Result<SocketAddr, AddrParseError> {
     SocketAddr::from_str(ip)
 }
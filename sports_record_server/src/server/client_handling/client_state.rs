@@ -0,0 +1,236 @@
+use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use ffmpeg_common::networking::{ClockOffset, ClockSample};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum ClientState {
+    Connecting,
+    Idle,
+    Recording,
+    Flushing,
+    Failed(String),
+    Disconnected,
+}
+
+impl ClientState {
+    /// The transition table. A client can fail or drop at any point; a failed client gets another
+    /// chance by going back to `Idle` when the next play starts, and a disconnected one only comes
+    /// back as a new connection with a state of its own.
+    pub fn can_become(&self, next: &ClientState) -> bool {
+        match (self, next) {
+            (_, &ClientState::Disconnected) => true,
+            (&ClientState::Disconnected, _) => false,
+            (_, &ClientState::Failed(_)) => true,
+            (&ClientState::Connecting, &ClientState::Idle) => true,
+            (&ClientState::Idle, &ClientState::Recording) => true,
+            (&ClientState::Recording, &ClientState::Flushing) => true,
+            (&ClientState::Flushing, &ClientState::Idle) => true,
+            (&ClientState::Failed(_), &ClientState::Idle) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The state of one client, shared between its `ClientThreadInformation` and the threads serving it.
+#[derive(Debug, Clone)]
+pub struct SharedClientState {
+    client: SocketAddr,
+    state: Arc<Mutex<ClientState>>,
+    clock: Arc<Mutex<ClockOffset>>,
+    last_seen: Arc<Mutex<Instant>>,
+    /// Payload bytes received since the last rate report, and when that report was.
+    received: Arc<Mutex<(u64, Instant)>>,
+}
+
+impl SharedClientState {
+    pub fn new(client: SocketAddr) -> SharedClientState {
+        SharedClientState {
+            client: client,
+            state: Arc::new(Mutex::new(ClientState::Connecting)),
+            clock: Arc::new(Mutex::new(ClockOffset::new())),
+            last_seen: Arc::new(Mutex::new(Instant::now())),
+            received: Arc::new(Mutex::new((0, Instant::now()))),
+        }
+    }
+
+    pub fn client(&self) -> SocketAddr {
+        self.client
+    }
+
+    pub fn get(&self) -> ClientState {
+        self.state.lock().expect("mutex poisoned").clone()
+    }
+
+    /// Moves to `new_state` if the transition table allows it from the current state.
+    pub fn set(&self, new_state: ClientState) {
+        let mut lock = self.state.lock().expect("mutex poisoned");
+        if *lock != new_state && lock.can_become(&new_state) {
+            info!("Client {} changed state: {:?} -> {:?}", self.client, *lock, new_state);
+            *lock = new_state;
+        }
+    }
+
+    pub fn add_clock_sample(&self, sample: &ClockSample, server_receive: i64) {
+        self.clock.lock().expect("mutex poisoned").add_sample(sample, server_receive);
+    }
+
+    /// How far the client's clock is ahead of ours in microseconds, once at least one exchange has completed.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock.lock().expect("mutex poisoned").offset()
+    }
+
+    /// Converts a capture time stamped by the client into server wall-clock microseconds.
+    pub fn to_server_time(&self, client_time: i64) -> i64 {
+        self.clock.lock().expect("mutex poisoned").to_server_time(client_time)
+    }
+
+    /// Records that something, if only a heartbeat, just arrived from the client.
+    pub fn touch(&self) {
+        *self.last_seen.lock().expect("mutex poisoned") = Instant::now();
+    }
+
+    /// How long it has been since anything arrived from the client.
+    pub fn silent_for(&self) -> Duration {
+        self.last_seen.lock().expect("mutex poisoned").elapsed()
+    }
+
+    pub fn add_received(&self, bytes: usize) {
+        self.received.lock().expect("mutex poisoned").0 += bytes as u64;
+    }
+
+    /// The payload bytes per second received since the last call, which the client adapts its bitrate to.
+    pub fn take_receive_rate(&self) -> u64 {
+        let mut lock = self.received.lock().expect("mutex poisoned");
+        let (bytes, since) = *lock;
+        *lock = (0, Instant::now());
+        let elapsed = since.elapsed();
+        let millis = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1_000_000) as u64;
+        if millis == 0 {
+            0
+        } else {
+            bytes * 1000 / millis
+        }
+    }
+
+    /// Only moves to `new_state` if the client is currently in `expected`.
+    pub fn transition(&self, expected: ClientState, new_state: ClientState) -> bool {
+        let mut lock = self.state.lock().expect("mutex poisoned");
+        if *lock == expected && expected.can_become(&new_state) {
+            info!("Client {} changed state: {:?} -> {:?}", self.client, *lock, new_state);
+            *lock = new_state;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Takes a failed client back to `Idle`, whatever it failed with. Returns whether it had failed.
+    pub fn recover(&self) -> bool {
+        let mut lock = self.state.lock().expect("mutex poisoned");
+        match *lock {
+            ClientState::Failed(_) => {
+                info!("Client {} changed state: {:?} -> {:?}", self.client, *lock, ClientState::Idle);
+                *lock = ClientState::Idle;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientStatus {
+    pub client: SocketAddr,
+    /// The identity the camera keeps across reconnects.
+    pub client_id: String,
+    pub state: ClientState,
+    /// Where the web UI can watch this client's camera while it is sending.
+    pub live_playlist: String,
+    /// The estimated offset of the client's clock from the server's, in microseconds.
+    pub clock_offset: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn failed() -> ClientState {
+        ClientState::Failed(String::from("could not send START"))
+    }
+
+    fn shared(state: ClientState) -> SharedClientState {
+        let shared = SharedClientState::new(SocketAddr::from(([127, 0, 0, 1], 41234)));
+        *shared.state.lock().unwrap() = state;
+        shared
+    }
+
+    #[test]
+    fn follows_a_play() {
+        assert!(ClientState::Connecting.can_become(&ClientState::Idle));
+        assert!(ClientState::Idle.can_become(&ClientState::Recording));
+        assert!(ClientState::Recording.can_become(&ClientState::Flushing));
+        assert!(ClientState::Flushing.can_become(&ClientState::Idle));
+    }
+
+    #[test]
+    fn skips_no_steps() {
+        assert!(!ClientState::Connecting.can_become(&ClientState::Recording));
+        assert!(!ClientState::Idle.can_become(&ClientState::Flushing));
+        assert!(!ClientState::Recording.can_become(&ClientState::Idle));
+        assert!(!ClientState::Flushing.can_become(&ClientState::Recording));
+    }
+
+    #[test]
+    fn fails_and_disconnects_from_anywhere() {
+        for state in vec![ClientState::Connecting, ClientState::Idle, ClientState::Recording, ClientState::Flushing, failed()] {
+            assert!(state.can_become(&failed()), "{:?} cannot fail", state);
+            assert!(state.can_become(&ClientState::Disconnected), "{:?} cannot disconnect", state);
+        }
+    }
+
+    #[test]
+    fn recovers_only_through_idle() {
+        assert!(failed().can_become(&ClientState::Idle));
+        assert!(!failed().can_become(&ClientState::Recording));
+        assert!(!failed().can_become(&ClientState::Flushing));
+    }
+
+    #[test]
+    fn stays_disconnected() {
+        for state in vec![ClientState::Connecting, ClientState::Idle, ClientState::Recording, ClientState::Flushing, failed()] {
+            assert!(!ClientState::Disconnected.can_become(&state), "a disconnected client became {:?}", state);
+        }
+    }
+
+    #[test]
+    fn transitions_only_from_the_expected_state() {
+        let state = shared(ClientState::Idle);
+        assert!(!state.transition(ClientState::Recording, ClientState::Flushing));
+        assert_eq!(state.get(), ClientState::Idle);
+        assert!(state.transition(ClientState::Idle, ClientState::Recording));
+        assert_eq!(state.get(), ClientState::Recording);
+    }
+
+    #[test]
+    fn ignores_transitions_missing_from_the_table() {
+        let state = shared(ClientState::Disconnected);
+        state.set(ClientState::Idle);
+        assert_eq!(state.get(), ClientState::Disconnected);
+        assert!(!state.transition(ClientState::Disconnected, ClientState::Recording));
+        assert_eq!(state.get(), ClientState::Disconnected);
+    }
+
+    #[test]
+    fn recovers_a_failed_client() {
+        let state = shared(failed());
+        assert!(!state.transition(ClientState::Idle, ClientState::Recording));
+        assert!(state.recover());
+        assert_eq!(state.get(), ClientState::Idle);
+        assert!(state.transition(ClientState::Idle, ClientState::Recording));
+        assert!(!state.recover());
+        assert_eq!(state.get(), ClientState::Recording);
+    }
+}
@@ -3,46 +3,63 @@ use std::thread;
 use std::thread::JoinHandle;
 use std::result::Result;
 use std::sync::{Arc, Weak, Mutex, MutexGuard};
-use std::sync::mpsc::{Sender, Receiver, channel, TryRecvError};
-use std::io::Write;
-use std::cell::Cell;
-use std::default::Default;
-
-use std::ffi::CString;
-
-use server::{ServerError, sql};
-use ffmpeg_common::unsafe_code::StreamConfiguration;
-
-use ffmpeg_common::unsafe_code::format::{FormatContext, OutputContext};
-use ffmpeg_common::unsafe_code::{EncodingCodecContext, Rational, CodecId, Packet, UnsafeError, UnsafeErrorKind};
-use ffmpeg_common::networking::{NetworkPacket, NetworkConfiguration};
+use std::sync::mpsc::{Sender, Receiver, channel, TryRecvError, RecvTimeoutError};
+use std::io::{Write, BufReader};
+use std::path::Path;
+use std::time::Duration;
+use std::fs;
+
+use server::{ServerError, ServerErrorKind, sql};
+use ffmpeg_common::unsafe_code::{StreamConfiguration, AudioStreamConfiguration, AudioEncodingContext, CodecVariant};
+
+use ffmpeg_common::unsafe_code::{EncodingCodecContext, Packet, UnsafeError, UnsafeErrorKind};
+use ffmpeg_common::networking::{NetworkPacket, NetworkConfiguration, ClientAcknowledgement, AUDIO_STREAM_INDEX, CLOCK_SYNC_ROUNDS, wall_clock_micros, negotiate_version};
+use ffmpeg_common::networking::{HEARTBEAT_INTERVAL_SECS, HEARTBEAT_TIMEOUT_SECS};
+use ffmpeg_common::networking::{Connection, SslAcceptor, accept_tls, new_challenge, verify_challenge_answer};
+use ffmpeg_common::logging::{set_log_field, clear_log_field, CLIENT_FIELD, PLAY_FIELD};
+use server::client_handling::{ClientState, SharedClientState, ClientStatus, LiveStream, live_feed_name, LIVE_DIRECTORY, LIVE_PLAYLIST};
+use server::client_handling::{ClipOutput, clip_path, thumbnail_path, generate_thumbnail};
 
 use uuid::Uuid;
-use serde_json;
+use base64;
 use messenger_plus::stream::{DualMessenger};
-use messenger_plus::stream;
 
 use iron::typemap;
 
 use ffmpeg_sys::*;
 
+/// A client that has been silent this long loses its slot. Until then, a camera reconnecting
+/// with the same identity is put back in the slot it had.
+const CLIENT_DROP_SECS: u64 = 30;
+/// A new connection that has not finished its handshake this long after it was accepted is hung up on.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 20;
+
 #[derive(Clone)]
 pub struct ClientStream {
     current_clients: Arc<Mutex<Vec<ClientThreadInformation>>>,
     db_access: sql::DatabaseRef,
-    out_dir: String
+    out_dir: String,
+    team_key: String,
+    tls: Option<Arc<SslAcceptor>>,
 }
 
+/// A handle that does not keep the clients alive, for the web server and background threads.
 #[derive(Clone)]
 pub struct WeakClientStream {
     current_clients: Weak<Mutex<Vec<ClientThreadInformation>>>,
+    db_access: sql::DatabaseRef,
+    out_dir: String,
+    team_key: String,
+    tls: Option<Arc<SslAcceptor>>,
 }
 
 pub struct ClientThreadInformation {
     socket_addr: SocketAddr,
+    client_id: String,
     pub ws_url: SocketAddr,
     thread_handle: JoinHandle<()>,
     thread_channel: Sender<RecordingInstructions>,
+    state: SharedClientState,
 }
 
 #[derive(Debug)]
@@ -51,38 +68,175 @@ pub enum ClientIPInformation {
     Remove(SocketAddr),
 }
 
+/// Outcome of forwarding a command to a single client's thread.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientCommandResult {
+    pub client: SocketAddr,
+    pub delivered: bool,
+}
+
+impl ClientCommandResult {
+    fn new(client: SocketAddr, delivered: bool) -> ClientCommandResult {
+        ClientCommandResult {
+            client: client,
+            delivered: delivered,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum RecordingInstructions {
     StartRecording,
     StopRecording,
+    /// The client's clip of a play was written with this many packets.
+    ClipStored(i64, u64),
     Cleanup,
 }
 
 impl ClientThreadInformation {
-    pub fn new(sock: SocketAddr, tcp_stream: TcpStream, db_ref: sql::DatabaseRef, out_dir: String) -> Result<ClientThreadInformation, UnsafeError> {
+    pub fn new(sock: SocketAddr, tcp_stream: Connection, team_key: &str, db_ref: sql::DatabaseRef, out_dir: String) -> Result<ClientThreadInformation, UnsafeError> {
+        let state = SharedClientState::new(sock);
+        // applies to every clone of the socket, so a client that goes quiet is noticed even mid-handshake
+        tcp_stream.set_read_timeout(Some(Duration::from_secs(HEARTBEAT_TIMEOUT_SECS)))?;
         let stream = tcp_stream.try_clone()?;
-        println!("Attempting to retrieve stream configuration from client {}", stream.peer_addr()?);
-        let mut read_channel: DualMessenger<TcpStream> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), stream);
+        let mut read_channel = BufReader::new(stream);
+        let mut sync_channel: DualMessenger<Connection> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), tcp_stream.try_clone()?);
 
-        let results = read_channel.read_next_message().map_err(|x| UnsafeError::from(x))?;
-        let stream_config = serde_json::from_slice::<NetworkPacket>(results.as_ref()).map_err(|x| UnsafeError::from(x))?;
-        let unwrapped_config = match stream_config {
+        let version = negotiate_protocol(sock, &tcp_stream, &mut sync_channel, &mut read_channel)?;
+        debug!("Speaking wire protocol version {} with {}", version, sock);
+        authenticate_client(sock, &tcp_stream, &mut sync_channel, &mut read_channel, team_key)?;
+
+        debug!("Waiting for the stream configuration of {}", sock);
+        let unwrapped_config = match NetworkPacket::read_from(&mut read_channel)? {
             NetworkPacket::JSONPayload(e) => e,
             _ => return Err(UnsafeError::new(UnsafeErrorKind::OpenInput(1000))),
         };
-        println!("Retreived stream configuration from client {}", tcp_stream.peer_addr()?);
-        println!("{:?}", unwrapped_config);
+        set_log_field(CLIENT_FIELD, &unwrapped_config.client_id);
+        info!("Received the stream configuration of {}", sock);
+        debug!("{:?}", unwrapped_config);
+
+        sync_client_clock(&mut sync_channel, &mut read_channel, &state)?;
+        info!("Clock offset: {:?}us", state.clock_offset());
+        // the client holds back everything but its clock sync replies until it gets this
+        sync_channel.write(b"READY")?;
 
         let (send, recv) = channel();
+        let thread_send = send.clone();
         let ws_sock = unwrapped_config.websocket_address.clone(); 
+        let client_id = unwrapped_config.client_id.clone();
+        state.set(ClientState::Idle);
+        let thread_state = state.clone();
         let thread_handle = thread::spawn(move || {
-            let val = client_write_handler(tcp_stream, recv, db_ref, out_dir, unwrapped_config);
-            println!("{:?}", val);
+            set_log_field(CLIENT_FIELD, &unwrapped_config.client_id);
+            match client_write_handler(tcp_stream, read_channel, recv, thread_send, db_ref, out_dir, unwrapped_config, thread_state) {
+                Ok(_) => debug!("Client thread finished"),
+                Err(e) => error!("Client thread failed: {}", e),
+            }
         });
-        Ok(ClientThreadInformation { socket_addr: sock, thread_handle: thread_handle, thread_channel: send, ws_url: ws_sock })
+        Ok(ClientThreadInformation { socket_addr: sock, client_id: client_id, thread_handle: thread_handle, thread_channel: send, ws_url: ws_sock, state: state })
+    }
+
+    pub fn get_socket_addr(&self) -> SocketAddr {
+        self.socket_addr
+    }
+
+    pub fn get_client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn get_state(&self) -> ClientState {
+        self.state.get()
+    }
+}
+
+/// Asks the client to stamp a clock sync reply. The reply is read by whichever thread owns the read channel.
+fn request_clock_sync(write_channel: &mut DualMessenger<Connection>) -> Result<(), UnsafeError> {
+    write_channel.write(format!("SYNC {}", wall_clock_micros()).as_bytes())?;
+    Ok(())
+}
+
+/// Runs a few clock sync exchanges before the client is handed to its threads, so its
+/// clips can be lined up with the other angles from the very first play.
+fn sync_client_clock(write_channel: &mut DualMessenger<Connection>, read_channel: &mut BufReader<Connection>, state: &SharedClientState) -> Result<(), UnsafeError> {
+    for _ in 0..CLOCK_SYNC_ROUNDS {
+        request_clock_sync(write_channel)?;
+        match NetworkPacket::read_from(read_channel)? {
+            NetworkPacket::ClockSync(sample) => state.add_clock_sample(&sample, wall_clock_micros()),
+            other => {
+                warn!("Expected a clock sync reply from {}, got {:?}", state.client(), other);
+                return Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader));
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Reads the client's hello and tells it which wire protocol version to speak, the newest both
+/// sides understand. A client with no version in common is refused.
+fn negotiate_protocol(sock: SocketAddr, tcp_stream: &Connection, sync_channel: &mut DualMessenger<Connection>, read_channel: &mut BufReader<Connection>) -> Result<u8, UnsafeError> {
+    let negotiated = match NetworkPacket::read_from(read_channel) {
+        Ok(NetworkPacket::Hello(min_version, max_version)) => negotiate_version(min_version, max_version),
+        Ok(_) => Err(UnsafeError::new(UnsafeErrorKind::InvalidFrameHeader)),
+        Err(e) => Err(e),
+    };
+    match negotiated {
+        Ok(version) => {
+            sync_channel.write(format!("VERSION {}", version).as_bytes())?;
+            Ok(version)
+        },
+        Err(e) => {
+            match *e.kind() {
+                UnsafeErrorKind::InvalidFrameHeader | UnsafeErrorKind::UnsupportedProtocolVersion(_) => {
+                    warn!("Refusing client {}: incompatible wire protocol", sock);
+                    refuse_client(tcp_stream, b"REFUSE");
+                },
+                _ => {},
+            }
+            Err(e)
+        },
     }
 }
 
+/// Challenges a new client with random bytes, which it has to answer with an HMAC keyed with the
+/// team key before it may send anything else. A client that answers wrong, or speaks an incompatible
+/// wire protocol, is told why and hung up on.
+fn authenticate_client(sock: SocketAddr, tcp_stream: &Connection, sync_channel: &mut DualMessenger<Connection>, read_channel: &mut BufReader<Connection>, team_key: &str) -> Result<(), UnsafeError> {
+    let challenge = new_challenge()?;
+    sync_channel.write(format!("AUTH {}", base64::encode(&challenge)).as_bytes())?;
+
+    let answer = match NetworkPacket::read_from(read_channel) {
+        Ok(NetworkPacket::Authentication(answer)) => answer,
+        // anything else fails the check below
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            match *e.kind() {
+                UnsafeErrorKind::InvalidFrameHeader | UnsafeErrorKind::UnsupportedProtocolVersion(_) => {
+                    warn!("Refusing client {}: incompatible wire protocol", sock);
+                    refuse_client(tcp_stream, b"REFUSE");
+                },
+                _ => {},
+            }
+            return Err(e);
+        },
+    };
+
+    if !verify_challenge_answer(team_key.as_bytes(), &challenge, &answer)? {
+        warn!("Rejecting client {}: it did not answer the challenge with the team key", sock);
+        refuse_client(tcp_stream, b"UNAUTHORIZED");
+        return Err(UnsafeError::new(UnsafeErrorKind::Unauthenticated));
+    }
+    Ok(())
+}
+
+/// Tells a peer why it is being dropped, e.g. `REFUSE` for an incompatible wire protocol, then closes the connection.
+fn refuse_client(tcp_stream: &Connection, reason: &[u8]) {
+    if let Ok(write_stream) = tcp_stream.try_clone() {
+        let mut write_channel: DualMessenger<Connection> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), write_stream);
+        let _ = write_channel.write(reason);
+    }
+    let _ = tcp_stream.shutdown(Shutdown::Both);
+}
+
 impl Drop for ClientThreadInformation {
     fn drop(&mut self) {
         let _ = self.thread_channel.send(RecordingInstructions::Cleanup);
@@ -91,66 +245,154 @@ impl Drop for ClientThreadInformation {
 
 impl ClientStream {
 
-    pub fn new(db_ref: sql::DatabaseRef, out_dir: String) -> Result<ClientStream, ServerError> {
+    /// Clients are only accepted if they know `team_key`, and have to speak TLS when an acceptor is given.
+    pub fn new(db_ref: sql::DatabaseRef, out_dir: String, team_key: String, tls: Option<SslAcceptor>) -> Result<ClientStream, ServerError> {
         let stream = ClientStream {
             current_clients: Arc::new(Mutex::new(vec![])),
             db_access: db_ref,
             out_dir: out_dir,
+            team_key: team_key,
+            tls: tls.map(Arc::new),
         };
+        let weak = stream.get_weak();
+        thread::spawn(move || drop_silent_clients(weak));
         Ok(stream)
     }
 
+    /// Runs the handshake of a new connection on a thread of its own, so a peer that never finishes
+    /// it cannot hold up the connections accepted after it.
+    pub fn accept_client(&self, info: TcpStream) {
+        let socket_addr = match info.peer_addr() {
+            Ok(addr) => addr,
+            Err(e) => {
+                warn!("Dropping a connection without a peer address: {}", e);
+                return;
+            },
+        };
+        let watchdog_stream = match info.try_clone() {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Dropping client {}: {}", socket_addr, e);
+                return;
+            },
+        };
+
+        let (done_send, done_recv) = channel::<()>();
+        let mut client_stream = self.clone();
+        thread::spawn(move || {
+            set_log_field(CLIENT_FIELD, socket_addr);
+            if let Err(e) = client_stream.add_client(info) {
+                warn!("Could not add client {}: {}", socket_addr, e);
+            }
+            let _ = done_send.send(());
+        });
+        // the read timeout only bounds each read, this bounds the whole handshake
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = done_recv.recv_timeout(Duration::from_secs(HANDSHAKE_TIMEOUT_SECS)) {
+                warn!("Client {} did not finish its handshake within {}s, hanging up", socket_addr, HANDSHAKE_TIMEOUT_SECS);
+                let _ = watchdog_stream.shutdown(Shutdown::Both);
+            }
+        });
+    }
+
+    /// Runs the handshake on the calling thread and only then takes a slot in `current_clients`.
     pub fn add_client(&mut self, info: TcpStream) -> Result<(), ServerError> {
         let socket_addr = try!(info.peer_addr());
+        let info = match self.tls {
+            Some(ref acceptor) => {
+                // a peer that stalls the TLS handshake gives up its connection like one that stalls ours
+                info.set_read_timeout(Some(Duration::from_secs(HEARTBEAT_TIMEOUT_SECS)))?;
+                match accept_tls(acceptor, info) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Rejecting client {}: {}", socket_addr, e);
+                        return Err(ServerError::from(e));
+                    },
+                }
+            },
+            None => Connection::from(info),
+        };
+        let client = ClientThreadInformation::new(socket_addr, info, &self.team_key, self.db_access.clone(), self.out_dir.clone())?;
         let mut lock = self.current_clients.lock().unwrap();
-        lock.push(ClientThreadInformation::new(socket_addr, info, self.db_access.clone(), self.out_dir.clone())?);
+        let existing = lock.iter().position(|item| item.client_id == client.client_id);
+        match existing {
+            Some(index) => {
+                info!("Reconnected from {}, reattaching it to its slot", socket_addr);
+                // replacing the old entry drops it, which cleans up its threads
+                lock[index] = client;
+                // a client that missed the STOP while it was away ends its play now
+                let instruction = if self.db_access.currently_in_play() { RecordingInstructions::StartRecording } else { RecordingInstructions::StopRecording };
+                let _ = lock[index].thread_channel.send(instruction);
+            },
+            None => lock.push(client),
+        }
         Ok(())
     }
 
-    pub fn remove_client(&mut self, info: SocketAddr) {
+    pub fn remove_client(&mut self, info: SocketAddr) -> Option<ClientCommandResult> {
         let mut new_thread_list: Vec<ClientThreadInformation> = Vec::new();
+        let mut result = None;
 
         let mut lock = self.current_clients.lock().unwrap();
         for thread_info in lock.drain(0..) {
             if !(thread_info.socket_addr == info) {
                 new_thread_list.push(thread_info);
             } else {
-                let _ = thread_info.thread_channel.send(RecordingInstructions::Cleanup);
+                let sent = thread_info.thread_channel.send(RecordingInstructions::Cleanup);
+                result = Some(ClientCommandResult::new(thread_info.socket_addr, sent.is_ok()));
             }
         }
         lock.append(&mut new_thread_list);
+        result
     }
 
     pub fn get_client_view(&self) -> MutexGuard<Vec<ClientThreadInformation>> {
         self.current_clients.lock().expect("mutex poisoned")
     }
 
-    pub fn start_recording(&self) {
-        let _ = self.db_access.start_play();
-        self.send_command(RecordingInstructions::StartRecording);
+    pub fn get_database(&self) -> &sql::DatabaseRef {
+        &self.db_access
+    }
+
+    pub fn get_client_states(&self) -> Vec<ClientStatus> {
+        let lock = self.current_clients.lock().expect("mutex poisoned");
+        lock.iter().map(|item| ClientStatus { client: item.socket_addr, client_id: item.client_id.clone(), state: item.get_state(), live_playlist: format!("/{}/{}/{}", LIVE_DIRECTORY, live_feed_name(&item.client_id), LIVE_PLAYLIST), clock_offset: item.state.clock_offset() }).collect()
+    }
+
+    pub fn start_recording(&self) -> Result<Vec<ClientCommandResult>, ServerError> {
+        // fails if a play is already running
+        self.db_access.start_play()?;
+        Ok(self.send_command(RecordingInstructions::StartRecording))
     }
 
-    pub fn stop_recording(&self) {
-        self.db_access.end_play();
-        self.send_command(RecordingInstructions::StopRecording);
+    pub fn stop_recording(&self) -> Result<Vec<ClientCommandResult>, ServerError> {
+        if !self.db_access.end_play() {
+            return Err(ServerError::new(ServerErrorKind::NoRecordingInProgress));
+        }
+        Ok(self.send_command(RecordingInstructions::StopRecording))
     }
 
-    pub fn clean_up(&mut self) {
-        self.send_command(RecordingInstructions::Cleanup);
+    pub fn clean_up(&mut self) -> Vec<ClientCommandResult> {
+        let results = self.send_command(RecordingInstructions::Cleanup);
         let mut lock = self.current_clients.lock().unwrap();
         lock.clear();
+        results
     }
 
-    fn send_command(&self, current_command: RecordingInstructions) {
+    fn send_command(&self, current_command: RecordingInstructions) -> Vec<ClientCommandResult> {
         let lock = self.current_clients.lock().unwrap();
-        for item in &*lock {
-            let _ = item.thread_channel.send(current_command);
-        }
+        lock.iter().map(|item| {
+            ClientCommandResult::new(item.socket_addr, item.thread_channel.send(current_command).is_ok())
+        }).collect()
     }
 
     pub fn get_weak(&self) -> WeakClientStream {
         WeakClientStream {
-            current_clients: Arc::downgrade(&self.current_clients)
+            current_clients: Arc::downgrade(&self.current_clients),
+            db_access: self.db_access.clone(),
+            out_dir: self.out_dir.clone(),
+            team_key: self.team_key.clone(),
+            tls: self.tls.clone(),
         }
     }
 
@@ -160,146 +402,399 @@ impl WeakClientStream {
     pub fn get_client_view(&self) -> Option<Arc<Mutex<Vec<ClientThreadInformation>>>> {
         self.current_clients.upgrade()
     }
+
+    /// The client stream, unless the server has already shut it down.
+    pub fn upgrade(&self) -> Option<ClientStream> {
+        self.current_clients.upgrade().map(|current_clients| ClientStream {
+            current_clients: current_clients,
+            db_access: self.db_access.clone(),
+            out_dir: self.out_dir.clone(),
+            team_key: self.team_key.clone(),
+            tls: self.tls.clone(),
+        })
+    }
+}
+
+/// Drops clients that have stopped sending anything, for as long as the `ClientStream` is alive.
+fn drop_silent_clients(clients: WeakClientStream) {
+    loop {
+        thread::sleep(Duration::from_secs(HEARTBEAT_INTERVAL_SECS));
+        let current_clients = match clients.get_client_view() {
+            Some(current_clients) => current_clients,
+            None => break,
+        };
+        let mut lock = current_clients.lock().expect("mutex poisoned");
+        let (silent, mut alive): (Vec<ClientThreadInformation>, Vec<ClientThreadInformation>) = lock.drain(0..).partition(|item| {
+            item.state.silent_for() >= Duration::from_secs(CLIENT_DROP_SECS)
+        });
+        for item in silent.iter() {
+            info!("Dropping client {} ({}), nothing heard from it in {}s", item.client_id, item.socket_addr, CLIENT_DROP_SECS);
+        }
+        lock.append(&mut alive);
+    }
 }
 
 impl typemap::Key for WeakClientStream {
     type Value = WeakClientStream;
 }
 
-fn client_write_handler(stream: TcpStream, recv: Receiver<RecordingInstructions>, db_ref: sql::DatabaseRef, out_dir: String, cfg: NetworkConfiguration) -> Result<(), ServerError> {
+fn client_write_handler(stream: Connection, read_channel: BufReader<Connection>, recv: Receiver<RecordingInstructions>, send: Sender<RecordingInstructions>, db_ref: sql::DatabaseRef, out_dir: String, cfg: NetworkConfiguration, state: SharedClientState) -> Result<(), ServerError> {
 
     let mut currently_cleaning = false;
 
     let write_stream = try!(stream.try_clone());
-    let read_stream = try!(stream.try_clone());
     
-    let mut read_channel: DualMessenger<TcpStream> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), read_stream);
-    let mut write_channel: DualMessenger<TcpStream> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), write_stream);
+    let mut write_channel: DualMessenger<Connection> = DualMessenger::new(String::from("--"), String::from("boundary"), String::from("endboundary"), write_stream);
 
-    let mut stcth = LoopingThreadHandler::new(cfg.stream_configuration, read_channel, db_ref, out_dir);
+    let mut stcth = LoopingThreadHandler::new(cfg.stream_configuration, cfg.audio_configuration, cfg.client_id, read_channel, send, db_ref.clone(), out_dir, state.clone());
 
     while !currently_cleaning {
         loop {
-            let curr_instruction = recv.recv().unwrap();
+            let curr_instruction = match recv.recv_timeout(Duration::from_secs(HEARTBEAT_INTERVAL_SECS)) {
+                Ok(instruction) => instruction,
+                Err(RecvTimeoutError::Timeout) => {
+                    // the receiving thread gives up on a client that went quiet, so there is nobody left to ping
+                    if state.get() == ClientState::Disconnected || write_channel.write(b"PING").is_err() {
+                        RecordingInstructions::Cleanup
+                    } else {
+                        // clients with adaptive bitrate settle on what actually arrives here
+                        let rate = state.take_receive_rate();
+                        if rate > 0 {
+                            let _ = write_channel.write(format!("RATE {}", rate).as_bytes());
+                        }
+                        continue;
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => RecordingInstructions::Cleanup,
+            };
             match curr_instruction {
                 RecordingInstructions::StartRecording => {
-                    let _ = write_channel.write(b"START");
-                    stcth.start();
+                    // the play may already be over by the time the receiving thread gets to it,
+                    // so it is told which play to record rather than looking it up itself
+                    let play_id = match db_ref.current_play_id() {
+                        Some(id) => id,
+                        None => continue,
+                    };
+                    // a client that failed in an earlier play gets another chance with this one
+                    state.recover();
+                    // the receiving thread has to know about the play before the client answers START
+                    stcth.start(play_id);
+                    // the client spools the play under the server's id for it
+                    if let Err(e) = write_channel.write(format!("START {}", play_id).as_bytes()) {
+                        state.set(ClientState::Failed(format!("could not send START: {}", e)));
+                    }
+                    // keeps the estimate fresh as clocks drift over a game
+                    let _ = request_clock_sync(&mut write_channel);
                 },
                 RecordingInstructions::StopRecording => {
-                    let _ = write_channel.write(b"STOP");
+                    match write_channel.write(b"STOP") {
+                        Ok(_) => { state.transition(ClientState::Recording, ClientState::Flushing); },
+                        Err(e) => state.set(ClientState::Failed(format!("could not send STOP: {}", e))),
+                    }
                     stcth.stop();
                 }
+                RecordingInstructions::ClipStored(play_id, packets) => {
+                    // lets the client drop its spooled copy once it knows nothing went missing
+                    let _ = write_channel.write(format!("STORED {} {}", play_id, packets).as_bytes());
+                },
                 RecordingInstructions::Cleanup => {
                     currently_cleaning = true;
                     break;
                 }
             }
         }
-        println!("In clean-up loop");
+        debug!("In clean-up loop");
     }
-    println!("Cleaning up");
+    debug!("Cleaning up");
+    state.set(ClientState::Disconnected);
     let _ = stream.shutdown(Shutdown::Both);
     Ok(())
 }
 
-fn looping_recv_video(conf: StreamConfiguration, mut read_channel: DualMessenger<TcpStream>, instr_recv: Receiver<TranslatedRecordingInstructions>, db_ref: sql::DatabaseRef, out_dir: String) -> Result<(), ServerError> {
+fn looping_recv_video(mut conf: StreamConfiguration, audio_conf: Option<AudioStreamConfiguration>, client_id: String, mut read_channel: BufReader<Connection>, instr_recv: Receiver<TranslatedRecordingInstructions>, write_instructions: Sender<RecordingInstructions>, db_ref: sql::DatabaseRef, out_dir: String, state: SharedClientState) -> Result<(), ServerError> {
 
-    let mut currently_recv = false;
     let mut on_ending_payload = false;
+    let mut client_disconnected = false;
     let mut frames_read = 0;
-    let mut current_output_context = Cell::new(Option::None);
-    let stream_timebase = Cell::new(Rational::default());
-    let stream_index = Cell::new(0);
-
-
-    let encoding_context = EncodingCodecContext::create_encoding_context(CodecId::from(AVCodecID::AV_CODEC_ID_H264), conf.height, conf.width, conf.time_base, conf.gop_size, conf.max_b_frames)?;
-    println!("Created encoding context");
+    let mut live_clip: Option<ClipOutput> = None;
+    let mut live_play: Option<i64> = None;
+    let mut live_offset_pending = false;
+    // a spooled play the client is sending again, and the play it belongs to
+    let mut replay_clip: Option<(i64, ClipOutput)> = None;
+
+    let mut encoding_context = stream_encoding_context(&conf)?;
+    debug!("Created encoding context");
+    let audio_encoding_context = match audio_conf {
+        Some(ref audio) => Some(AudioEncodingContext::create_audio_encoding_context(audio.codec_id, audio.sample_rate, audio.channels, audio.bit_rate)?),
+        None => None,
+    };
+    // one feed for the whole connection, so viewers do not lose it between plays
+    let mut live_stream = open_live_stream(&out_dir, &client_id, &encoding_context, audio_encoding_context.as_ref());
 
     // internal loop
-    loop {
-        match instr_recv.try_recv() {
-            Ok(ref m) if m == &TranslatedRecordingInstructions::Start => {
-                currently_recv = true;
-                let uuid: String = Uuid::new_v4().simple().to_string();
-                (&db_ref).insert_clip(&uuid)?;
-                let file_path: String = out_dir.clone() + "/video_" + &uuid + ".mp4";
-                let mut format_context: OutputContext = FormatContext::new_output(CString::new(file_path.as_str()).unwrap());
-                println!("Created output context");
-                let pkt_stream = format_context.create_stream(&encoding_context);
-                println!("Created output video stream");
-                try!(format_context.open_video_file(file_path.as_ref()));
-                println!("Opened video file: {}", file_path.as_str());
-                try!(format_context.write_video_header());
-                println!("Wrote video header");
-                current_output_context.replace(Option::Some(format_context));
-                stream_index.replace(pkt_stream.index);
-                stream_timebase.replace(Rational::from(pkt_stream.time_base));
-                frames_read = 0;
-            },
-            Err(ref e) if (e != &TryRecvError::Empty) => {
-                eprintln!("An unexpected error occured within the server. Please restart the server and the client {:?}", e);
-                break;
-            }
-            _ => {},
-        }
+    'receiving: loop {
+        // reads even between plays, since the client's heartbeats are how we know it is still there
+        let res = NetworkPacket::read_from(&mut read_channel);
 
-        if currently_recv {
-            let res = read_channel.read_next_message();
-            match res {
-                Err(ref e) if e == &stream::Error::from(stream::ErrorKind::BufferEmpty) => {
-                    println!("Read {} messages from stream, now reached EOS.", frames_read);
-                    currently_recv = false;
-                    on_ending_payload = true;
+        // instructions are queued before the client is told about them, so they take effect
+        // before whatever the client sent in response
+        loop {
+            match instr_recv.try_recv() {
+                Ok(TranslatedRecordingInstructions::Start(play_id)) => {
+                    // the client never ended the last play; what it sent of it is kept, and the
+                    // spooled copy it replays later takes its place
+                    if let Some(clip) = live_clip.take() {
+                        warn!("Play {} started before the client ended the last one", play_id);
+                        close_clip(clip, &db_ref, &out_dir)?;
+                    }
+                    let uuid: String = Uuid::new_v4().simple().to_string();
+                    db_ref.insert_clip(play_id, &uuid, &client_id)?;
+                    live_play = Some(play_id);
+                    set_log_field(PLAY_FIELD, play_id);
+                    live_clip = Some(ClipOutput::open(&out_dir, uuid, &encoding_context, audio_encoding_context.as_ref())?);
+                    live_offset_pending = true;
+                    frames_read = 0;
+
+                    if live_stream.is_none() {
+                        live_stream = open_live_stream(&out_dir, &client_id, &encoding_context, audio_encoding_context.as_ref());
+                    }
+                    if let Some(ref mut live) = live_stream {
+                        live.start_play();
+                    }
                 },
-                Ok(v) => {
-                    frames_read = frames_read + 1;
-                    let data_packet_attempt = serde_json::from_slice::<NetworkPacket>(v.as_slice()).map_err(|x| UnsafeError::from(x));
-                    match data_packet_attempt {
-                        Ok(network_packet) => {
-                            match network_packet {
-                                NetworkPacket::PacketStream(pkts) => {
-                                    for mut pkt in pkts.into_iter().map(|x| Packet::from(x)) {
-                                        println!("Recieved packet from client with pts {}", pkt.pts);
-                                        pkt.rescale_to(Rational::new(1,30), stream_timebase.get());
-                                        let format_context = current_output_context.get_mut().as_mut().expect("desync");
-                                        let _ = format_context.write_video_frame(stream_index.get(), pkt)?;
-                                    }
-                                },
-                                NetworkPacket::PayloadEnd => {
-                                    println!("Received EOP Indicator");
-                                    on_ending_payload = true;
-                                    currently_recv = false;
-                                    continue;
-                                },
-                                _ => eprintln!("Unexpected Network Packet Type!"),
+                Ok(TranslatedRecordingInstructions::Stop) => {
+                    // a clip the client sent nothing for by the time it was told to stop was never
+                    // started on its side; should anything of it still arrive, the client replays
+                    // the play from its spool
+                    let empty = live_clip.as_ref().map(|clip| clip.packets() == 0).unwrap_or(false);
+                    if empty {
+                        if let Some(clip) = live_clip.take() {
+                            info!("Nothing arrived for play {:?}, dropping its clip", live_play);
+                            if let Err(e) = db_ref.remove_clip(clip.uuid()) {
+                                warn!("Could not remove the empty clip {}: {}", clip.uuid(), e);
                             }
-                        },
-                        Err(e) => eprintln!("{:?}, {:?}", e, v.as_slice()),
+                            clip.discard();
+                        }
+                        live_play = None;
+                        live_offset_pending = false;
+                        clear_log_field(PLAY_FIELD);
                     }
                 },
+                Err(TryRecvError::Empty) => break,
                 Err(e) => {
-                    return Err(ServerError::from(UnsafeError::from(e)));
+                    error!("An unexpected error occured within the server. Please restart the server and the client {:?}", e);
+                    break 'receiving;
+                },
+            }
+        }
+
+        match res {
+            Err(ref e) if e.is_end_of_stream() || e.is_truncated() || e.is_timed_out() => {
+                if e.is_timed_out() {
+                    warn!("Stopped sending heartbeats");
+                } else if e.is_truncated() {
+                    warn!("The connection was cut off in the middle of a frame");
                 }
+                info!("Read {} messages from stream, now reached EOS.", frames_read);
+                on_ending_payload = live_clip.is_some();
+                client_disconnected = true;
+            },
+            Ok(network_packet) => {
+                state.touch();
+                match network_packet {
+                    NetworkPacket::PacketStream(pkts) => {
+                        frames_read = frames_read + 1;
+                        state.add_received(pkts.iter().map(|pkt| pkt.packet.len()).sum());
+                        for data_pkt in pkts {
+                            let capture_time = data_pkt.capture_time;
+                            let pkt = Packet::from(data_pkt);
+                            trace!("Received packet with pts {}", pkt.pts);
+                            // audio is dropped by the clip when the client did not describe an audio stream
+                            let packet_timebase = if pkt.stream_index == AUDIO_STREAM_INDEX {
+                                audio_conf.map(|audio| audio.time_base).unwrap_or(conf.time_base)
+                            } else {
+                                conf.time_base
+                            };
+
+                            if let Some((_, ref mut clip)) = replay_clip {
+                                clip.write_packet(pkt, capture_time, packet_timebase)?;
+                                continue;
+                            }
+
+                            // a broken live feed should never cost us the recording itself
+                            let live_error = match live_stream {
+                                Some(ref mut live) => live.write_packet(pkt.clone(), packet_timebase).err(),
+                                None => None,
+                            };
+                            if let Some(e) = live_error {
+                                warn!("Live feed stopped, restarting it with the next play: {}", e);
+                                live_stream = None;
+                            }
+                            // packets that arrive after the play's file was closed have nowhere else to go
+                            let clip = match live_clip {
+                                Some(ref mut clip) => clip,
+                                None => continue,
+                            };
+                            clip.write_packet(pkt, capture_time, packet_timebase)?;
+                            // the first video packet marks where this angle starts relative to the play
+                            if live_offset_pending {
+                                if let Some(captured_at) = clip.first_capture_time() {
+                                    live_offset_pending = false;
+                                    if let Err(e) = db_ref.set_clip_start_time(clip.uuid(), state.to_server_time(captured_at)) {
+                                        warn!("Could not store the start offset of clip {}: {}", clip.uuid(), e);
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    NetworkPacket::Replay(play_id) => {
+                        info!("Replaying its spooled copy of play {}", play_id);
+                        if let Some((_, unfinished)) = replay_clip.take() {
+                            unfinished.discard();
+                        }
+                        let uuid: String = Uuid::new_v4().simple().to_string();
+                        replay_clip = Some((play_id, ClipOutput::open(&out_dir, uuid, &encoding_context, audio_encoding_context.as_ref())?));
+                    },
+                    NetworkPacket::PayloadEnd => {
+                        debug!("Received EOP Indicator");
+                        match replay_clip.take() {
+                            Some((play_id, clip)) => store_replayed_clip(play_id, clip, &client_id, &db_ref, &out_dir, &state, &write_instructions)?,
+                            None => on_ending_payload = live_clip.is_some(),
+                        }
+                    },
+                    NetworkPacket::JSONPayload(configuration) => {
+                        // the client changed its output size between plays, or around a replay of an
+                        // older one; clips already open keep the size they were opened with
+                        conf = configuration.stream_configuration;
+                        encoding_context = stream_encoding_context(&conf)?;
+                        info!("The client now sends {}x{}", conf.width, conf.height);
+                        // the feed's segments have to change size too; it carries on in the same playlist
+                        finish_live_stream(live_stream.take());
+                        live_stream = open_live_stream(&out_dir, &client_id, &encoding_context, audio_encoding_context.as_ref());
+                    },
+                    NetworkPacket::Heartbeat => {},
+                    NetworkPacket::ClockSync(sample) => state.add_clock_sample(&sample, wall_clock_micros()),
+                    NetworkPacket::Acknowledgement(ack) => {
+                        match ack {
+                            ClientAcknowledgement::RecordingStarted => { state.transition(ClientState::Idle, ClientState::Recording); },
+                            ClientAcknowledgement::RecordingStopped => { state.transition(ClientState::Recording, ClientState::Flushing); },
+                            ClientAcknowledgement::RecordingFailed => state.set(ClientState::Failed(String::from("the client reported that it could not record"))),
+                        }
+                    },
+                    _ => warn!("Unexpected Network Packet Type!"),
+                }
+            },
+            Err(e) => {
+                return Err(ServerError::from(e));
             }
         }
 
         if on_ending_payload {
-            let mut temp_context = current_output_context.replace(Option::None);
-            let format_context = temp_context.as_mut().expect("desync");
-            println!("Current read ended, {} frames read.", frames_read);
-            try!(format_context.write_null_video_frame());
-            try!(format_context.write_video_trailer());
-            println!("Wrote video trailer and null video frame");
+            if let Some(clip) = live_clip.take() {
+                info!("Current read ended, {} frames read.", frames_read);
+                let packets = close_clip(clip, &db_ref, &out_dir)?;
+                match live_play.take() {
+                    Some(play_id) if !client_disconnected => { let _ = write_instructions.send(RecordingInstructions::ClipStored(play_id, packets)); },
+                    _ => {},
+                }
+                clear_log_field(PLAY_FIELD);
+            }
             on_ending_payload = false;
+            state.transition(ClientState::Flushing, ClientState::Idle);
+        }
+
+        if client_disconnected {
+            // the client replays the whole play again after it reconnects
+            if let Some((_, unfinished)) = replay_clip.take() {
+                unfinished.discard();
+            }
+            state.set(ClientState::Disconnected);
+            break;
+        }
+    }
+    finish_live_stream(live_stream);
+    Ok(())
+}
+
+/// Finishes a live clip and records what the library needs of it. Returns how many packets it got.
+fn close_clip(clip: ClipOutput, db_ref: &sql::DatabaseRef, out_dir: &str) -> Result<u64, ServerError> {
+    let packets = clip.packets();
+    let uuid = clip.uuid().to_owned();
+    let duration = clip.duration();
+    try!(clip.finish());
+    record_duration(db_ref, &uuid, duration);
+    generate_thumbnail(out_dir, &uuid, db_ref);
+    Ok(packets)
+}
+
+fn open_live_stream(out_dir: &str, client_id: &str, encoding_context: &EncodingCodecContext, audio_encoding_context: Option<&AudioEncodingContext>) -> Option<LiveStream> {
+    match LiveStream::open(Path::new(out_dir), client_id, encoding_context, audio_encoding_context) {
+        Ok(live) => Some(live),
+        Err(e) => { warn!("Could not start the live feed: {}", e); None },
+    }
+}
+
+fn finish_live_stream(live_stream: Option<LiveStream>) {
+    if let Some(Err(e)) = live_stream.map(|live| live.finish()) {
+        warn!("Could not finish the live feed: {}", e);
+    }
+}
+
+fn stream_encoding_context(conf: &StreamConfiguration) -> Result<EncodingCodecContext, ServerError> {
+    // the client picks the codec through its encoder profile
+    let codec_id = match conf.codec_id {
+        CodecVariant::Encoding(id) | CodecVariant::Decoding(id) => id,
+    };
+    Ok(EncodingCodecContext::create_encoding_context(codec_id, conf.height, conf.width, conf.time_base, conf.frame_rate, conf.gop_size, conf.max_b_frames)?)
+}
+
+fn record_duration(db_ref: &sql::DatabaseRef, uuid: &str, duration: Option<i64>) {
+    if let Some(duration) = duration {
+        if let Err(e) = db_ref.set_clip_duration(uuid, duration) {
+            warn!("Could not store the duration of clip {}: {}", uuid, e);
         }
     }
+}
+
+/// Swaps a finished replay in for whatever the client managed to send of that play live.
+fn store_replayed_clip(play_id: i64, clip: ClipOutput, client_id: &str, db_ref: &sql::DatabaseRef, out_dir: &str, state: &SharedClientState, write_instructions: &Sender<RecordingInstructions>) -> Result<(), ServerError> {
+    let uuid = clip.uuid().to_owned();
+    let packets = clip.packets();
+    let first_capture_time = clip.first_capture_time();
+    let duration = clip.duration();
+    try!(clip.finish());
+
+    match db_ref.replace_play_clips(play_id, client_id, &uuid) {
+        Ok(replaced) => {
+            for old_uuid in replaced {
+                if let Err(e) = fs::remove_file(clip_path(out_dir, &old_uuid)) {
+                    warn!("Could not remove clip {}, replaced by a replay: {}", old_uuid, e);
+                }
+                // most partial clips got one, but not all of them
+                let _ = fs::remove_file(thumbnail_path(out_dir, &old_uuid));
+            }
+            if let Some(captured_at) = first_capture_time {
+                if let Err(e) = db_ref.set_clip_start_time(&uuid, state.to_server_time(captured_at)) {
+                    warn!("Could not store the start offset of clip {}: {}", uuid, e);
+                }
+            }
+            info!("Stored the replay of play {} as clip {}", play_id, uuid);
+            record_duration(db_ref, &uuid, duration);
+            generate_thumbnail(out_dir, &uuid, db_ref);
+            let _ = write_instructions.send(RecordingInstructions::ClipStored(play_id, packets));
+        },
+        Err(e) => {
+            error!("Could not store the replay of play {}: {}", play_id, e);
+            let _ = fs::remove_file(clip_path(out_dir, &uuid));
+        },
+    }
     Ok(())
 }
 
 #[derive(Debug, PartialEq)]
 enum TranslatedRecordingInstructions {
-    Start,
+    /// Record the play with this id.
+    Start(i64),
     Stop,
 }
 
@@ -310,11 +805,18 @@ struct LoopingThreadHandler {
 }
 
 impl LoopingThreadHandler {
-    fn new(conf: StreamConfiguration, read_channel: DualMessenger<TcpStream>, db_ref: sql::DatabaseRef, out_dir: String) -> LoopingThreadHandler {
+    fn new(conf: StreamConfiguration, audio_conf: Option<AudioStreamConfiguration>, client_id: String, read_channel: BufReader<Connection>, write_instructions: Sender<RecordingInstructions>, db_ref: sql::DatabaseRef, out_dir: String, state: SharedClientState) -> LoopingThreadHandler {
         let (send, recv) = channel();
         let rec_vid_thread = thread::spawn(move || {
-            let x = looping_recv_video(conf, read_channel, recv, db_ref, out_dir);
-            println!("{:?}", x);
+            set_log_field(CLIENT_FIELD, &client_id);
+            let x = looping_recv_video(conf, audio_conf, client_id, read_channel, recv, write_instructions, db_ref, out_dir, state.clone());
+            match x {
+                Ok(_) => debug!("Receiving thread finished"),
+                Err(ref e) => {
+                    error!("Receiving thread failed: {}", e);
+                    state.set(ClientState::Failed(e.to_string()));
+                },
+            }
             x
         });
         LoopingThreadHandler {
@@ -324,8 +826,8 @@ impl LoopingThreadHandler {
         }
     }
 
-    fn start(&mut self) {
-        let _ = self.instr_tun.send(TranslatedRecordingInstructions::Start);
+    fn start(&mut self, play_id: i64) {
+        let _ = self.instr_tun.send(TranslatedRecordingInstructions::Start(play_id));
     }
 
     fn stop(&mut self) {
@@ -0,0 +1,125 @@
+use std::cmp;
+use std::ffi::CString;
+use std::fs;
+
+use server::ServerError;
+
+use ffmpeg_common::unsafe_code::format::{FormatContext, OutputContext};
+use ffmpeg_common::unsafe_code::{EncodingCodecContext, AudioEncodingContext, Rational, Packet, Dictionary};
+use ffmpeg_common::networking::AUDIO_STREAM_INDEX;
+
+use ffmpeg_sys::*;
+
+/// Clips are written as fragmented MP4: every keyframe starts a new fragment and the moov atom
+/// is written up front, so a clip can be watched while it is still recording and survives a crash.
+const CLIP_MUXER_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("movflags", "frag_keyframe+empty_moov+default_base_moof"),
+];
+
+pub fn clip_path(out_dir: &str, uuid: &str) -> String {
+    String::from(out_dir) + "/video_" + uuid + ".mp4"
+}
+
+/// One clip file being written from a client's packets.
+pub struct ClipOutput {
+    uuid: String,
+    path: String,
+    format_context: OutputContext,
+    stream_index: i32,
+    stream_timebase: Rational,
+    audio_stream: Option<(i32, Rational)>,
+    packets: u64,
+    first_capture_time: Option<i64>,
+    /// The first video pts and the end of the last video packet, in the stream's time base.
+    video_span: Option<(i64, i64)>,
+}
+
+impl ClipOutput {
+    pub fn open(out_dir: &str, uuid: String, encoding_context: &EncodingCodecContext, audio_encoding_context: Option<&AudioEncodingContext>) -> Result<ClipOutput, ServerError> {
+        let file_path = clip_path(out_dir, &uuid);
+        let mut format_context: OutputContext = FormatContext::new_output(CString::new(file_path.as_str()).unwrap());
+        debug!("Created output context");
+        let pkt_stream = format_context.create_stream(encoding_context);
+        debug!("Created output video stream");
+        let pkt_audio_stream = audio_encoding_context.map(|ctx| format_context.create_stream(ctx));
+        try!(format_context.open_video_file(file_path.as_ref()));
+        info!("Opened video file: {}", file_path.as_str());
+        try!(format_context.write_video_header_with_options(Dictionary::from_pairs(CLIP_MUXER_OPTIONS)?));
+        debug!("Wrote video header");
+
+        Ok(ClipOutput {
+            uuid: uuid,
+            path: file_path,
+            format_context: format_context,
+            stream_index: pkt_stream.index,
+            stream_timebase: Rational::from(pkt_stream.time_base),
+            audio_stream: pkt_audio_stream.map(|x| (x.index, Rational::from(x.time_base))),
+            packets: 0,
+            first_capture_time: None,
+            video_span: None,
+        })
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    /// How many packets the client sent for this clip, including any that could not be muxed.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// The client's capture time of the first video packet, once one has arrived.
+    pub fn first_capture_time(&self) -> Option<i64> {
+        self.first_capture_time
+    }
+
+    /// How much video the clip holds in microseconds, once a video packet has arrived.
+    pub fn duration(&self) -> Option<i64> {
+        self.video_span.map(|(start, end)| unsafe {
+            av_rescale_q(end - start, self.stream_timebase.into(), Rational::new(1, 1_000_000).into())
+        })
+    }
+
+    /// Audio and video arrive with their own time bases and are interleaved by the muxer.
+    pub fn write_packet(&mut self, mut pkt: Packet, capture_time: i64, packet_timebase: Rational) -> Result<(), ServerError> {
+        self.packets += 1;
+        let (index, timebase) = if pkt.stream_index == AUDIO_STREAM_INDEX {
+            match self.audio_stream {
+                Some(audio) => audio,
+                None => return Ok(()),
+            }
+        } else {
+            if self.first_capture_time.is_none() && capture_time != 0 {
+                self.first_capture_time = Some(capture_time);
+            }
+            (self.stream_index, self.stream_timebase)
+        };
+        pkt.rescale_to(packet_timebase, timebase);
+        if index == self.stream_index && pkt.pts != AV_NOPTS_VALUE {
+            let end = pkt.pts + pkt.duration;
+            self.video_span = match self.video_span {
+                Some((start, last)) => Some((cmp::min(start, pkt.pts), cmp::max(last, end))),
+                None => Some((pkt.pts, end)),
+            };
+        }
+        self.format_context.write_video_frame(index, pkt)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<(), ServerError> {
+        try!(self.format_context.write_null_video_frame());
+        try!(self.format_context.write_video_trailer());
+        debug!("Wrote video trailer and null video frame");
+        Ok(())
+    }
+
+    /// Gives up on the clip and deletes what was written of it.
+    pub fn discard(self) {
+        let path = self.path.clone();
+        drop(self);
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Could not remove the unfinished clip {}: {}", path, e);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::cmp;
+use std::path::{Path, PathBuf};
+use std::ffi::CString;
+use std::fs;
+
+use server::ServerError;
+use server::export::{microseconds, rescale};
+
+use ffmpeg_common::unsafe_code::format::{FormatContext, OutputContext};
+use ffmpeg_common::unsafe_code::{EncodingCodecContext, AudioEncodingContext, Rational, Packet, Dictionary};
+use ffmpeg_common::networking::AUDIO_STREAM_INDEX;
+
+use ffmpeg_sys::*;
+
+/// Live feeds are written to `<output directory>/live/<feed name>/`.
+pub const LIVE_DIRECTORY: &'static str = "live";
+pub const LIVE_PLAYLIST: &'static str = "index.m3u8";
+
+/// A short rolling window keeps the feed close to real time; old segments are deleted as it rolls.
+/// The playlist never gets an end-of-list, and a feed that is reopened, after a reconnect or a
+/// change of output size, carries on from the playlist already on disk.
+const HLS_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("hls_time", "2"),
+    ("hls_list_size", "6"),
+    ("hls_flags", "delete_segments+omit_endlist+append_list"),
+];
+
+/// The directory name a client's live feed is published under: its client id, with anything that
+/// does not belong in a URL replaced by `_`.
+pub fn live_feed_name(client_id: &str) -> String {
+    let name: String = client_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+    if name.is_empty() { String::from("_") } else { name }
+}
+
+pub fn live_feed_directory(out_dir: &Path, client_id: &str) -> PathBuf {
+    out_dir.join(LIVE_DIRECTORY).join(live_feed_name(client_id))
+}
+
+/// Remuxes a client's incoming packets into a rolling HLS playlist alongside the per-play MP4.
+/// It stays open for as long as the client is connected, so viewers keep one feed across plays.
+pub struct LiveStream {
+    format_context: OutputContext,
+    stream_index: i32,
+    stream_timebase: Rational,
+    audio_stream: Option<(i32, Rational)>,
+    /// Every play starts again at 0, so its packets are moved to follow on from the last one written.
+    /// Both are in microseconds.
+    offset: i64,
+    end: i64,
+    rebase_pending: bool,
+}
+
+impl LiveStream {
+    pub fn open(out_dir: &Path, client_id: &str, encoding_context: &EncodingCodecContext, audio_encoding_context: Option<&AudioEncodingContext>) -> Result<LiveStream, ServerError> {
+        let feed_dir = live_feed_directory(out_dir, client_id);
+        fs::create_dir_all(&feed_dir)?;
+        let playlist = feed_dir.join(LIVE_PLAYLIST);
+        let segments = feed_dir.join("segment%d.ts");
+
+        // the hls muxer opens the playlist and segment files itself, so no AVIO file is opened here
+        let mut format_context = FormatContext::new_output_with_format("hls", CString::new(playlist.to_string_lossy().as_ref()).unwrap())?;
+        let pkt_stream = format_context.create_stream(encoding_context);
+        let audio_stream = audio_encoding_context.map(|ctx| format_context.create_stream(ctx));
+        let mut options = Dictionary::from_pairs(HLS_OPTIONS)?;
+        options.set("hls_segment_filename", &segments.to_string_lossy())?;
+        format_context.write_video_header_with_options(options)?;
+        info!("Started live feed for {} at {}", client_id, playlist.display());
+
+        Ok(LiveStream {
+            format_context: format_context,
+            stream_index: pkt_stream.index,
+            stream_timebase: Rational::from(pkt_stream.time_base),
+            audio_stream: audio_stream.map(|x| (x.index, Rational::from(x.time_base))),
+            offset: 0,
+            end: 0,
+            rebase_pending: true,
+        })
+    }
+
+    /// The next packet written belongs to a new play, whose timestamps start over.
+    pub fn start_play(&mut self) {
+        self.rebase_pending = true;
+    }
+
+    pub fn write_packet(&mut self, mut pkt: Packet, packet_timebase: Rational) -> Result<(), ServerError> {
+        let (index, timebase) = if pkt.stream_index == AUDIO_STREAM_INDEX {
+            match self.audio_stream {
+                Some(audio) => audio,
+                None => return Ok(()),
+            }
+        } else {
+            (self.stream_index, self.stream_timebase)
+        };
+
+        let timestamp = if pkt.dts != AV_NOPTS_VALUE { pkt.dts } else { pkt.pts };
+        if timestamp != AV_NOPTS_VALUE {
+            let start = rescale(timestamp, packet_timebase, microseconds());
+            if self.rebase_pending {
+                self.offset = self.end - start;
+                self.rebase_pending = false;
+            }
+            let duration = rescale(pkt.duration, packet_timebase, microseconds());
+            self.end = cmp::max(self.end, start + self.offset + cmp::max(duration, 1));
+        }
+        let shift = rescale(self.offset, microseconds(), packet_timebase);
+        if pkt.pts != AV_NOPTS_VALUE {
+            pkt.pts += shift;
+        }
+        if pkt.dts != AV_NOPTS_VALUE {
+            pkt.dts += shift;
+        }
+
+        pkt.rescale_to(packet_timebase, timebase);
+        self.format_context.write_video_frame(index, pkt)?;
+        Ok(())
+    }
+
+    /// Flushes the muxer once the client has gone. The playlist is left open for when it comes back.
+    pub fn finish(mut self) -> Result<(), ServerError> {
+        self.format_context.write_null_video_frame()?;
+        self.format_context.write_video_trailer()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_url_safe_client_id() {
+        assert_eq!(live_feed_name("camera-1_a"), "camera-1_a");
+    }
+
+    #[test]
+    fn sanitises_a_client_id_into_one_directory_name() {
+        assert_eq!(live_feed_name("end zone/1"), "end_zone_1");
+        assert_eq!(live_feed_name(".."), "__");
+        assert_eq!(live_feed_name(""), "_");
+    }
+
+    #[test]
+    fn names_a_feed_directory_a_live_path_accepts() {
+        use server::web::body_writer::live_path;
+        for client_id in vec!["", "..", "end zone/1"] {
+            let name = live_feed_name(client_id);
+            assert!(live_path(Path::new("clips"), &name, LIVE_PLAYLIST).is_some(), "{:?} gave {:?}", client_id, name);
+        }
+    }
+}
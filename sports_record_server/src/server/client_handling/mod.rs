@@ -1,3 +1,11 @@
 pub use self::client_stream::*;
+pub use self::client_state::*;
+pub use self::live_stream::*;
+pub use self::clip_output::*;
+pub use self::thumbnail::*;
 
-mod client_stream;
\ No newline at end of file
+mod client_stream;
+mod client_state;
+mod live_stream;
+mod clip_output;
+mod thumbnail;
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::cmp;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Write;
+use std::thread;
+
+use server::{ServerError, ServerErrorKind, sql};
+use server::client_handling::clip_path;
+
+use ffmpeg_common::unsafe_code::format::{FormatContext, InputContext};
+use ffmpeg_common::unsafe_code::sws::{SWSContext, SWSImageDefinition, ScalingAlgorithm};
+use ffmpeg_common::unsafe_code::{UnsafeError, UnsafeErrorKind, EncodingCodecContext, DecodingCodecContext, Rational};
+use ffmpeg_common::networking::VIDEO_STREAM_INDEX;
+
+use ffmpeg_sys::*;
+
+/// Thumbnails are scaled to this width, keeping the clip's aspect ratio.
+const THUMBNAIL_WIDTH: i32 = 320;
+/// The decoder may hold a few packets back before its first frame; a clip that yields none by now has none.
+const THUMBNAIL_MAX_PACKETS: usize = 120;
+const AVERROR_EAGAIN: i32 = -11;
+
+/// The file name a clip's thumbnail is written under, next to the clip itself.
+pub fn thumbnail_file_name(uuid: &str) -> String {
+    String::from("thumb_") + uuid + ".png"
+}
+
+pub fn thumbnail_path(out_dir: &str, uuid: &str) -> String {
+    String::from(out_dir) + "/" + &thumbnail_file_name(uuid)
+}
+
+/// Writes the thumbnail of a finished clip on a thread of its own, so the client's next play is
+/// not held up, and records it once the file is complete.
+pub fn generate_thumbnail(out_dir: &str, uuid: &str, db_ref: &sql::DatabaseRef) {
+    let out_dir = out_dir.to_owned();
+    let uuid = uuid.to_owned();
+    let db_ref = db_ref.clone();
+    thread::spawn(move || {
+        let result = write_thumbnail(&clip_path(&out_dir, &uuid), &thumbnail_path(&out_dir, &uuid))
+            .and_then(|_| db_ref.set_clip_thumbnail(&uuid, &thumbnail_file_name(&uuid)));
+        match result {
+            Ok(_) => debug!("Wrote the thumbnail of clip {}", uuid),
+            Err(e) => warn!("Could not write a thumbnail of clip {}: {}", uuid, e),
+        }
+    });
+}
+
+/// Decodes the first frame of the clip, which is always the keyframe it starts on, into a PNG.
+fn write_thumbnail(clip: &str, thumbnail: &str) -> Result<(), ServerError> {
+    let input_format = InputContext::create_input_format(CString::new("mp4").unwrap());
+    let mut input_context = FormatContext::new_input(input_format, CString::new(clip).unwrap())?;
+    let mut stream = input_context.find_input_stream(VIDEO_STREAM_INDEX as usize).ok_or(UnsafeError::new(UnsafeErrorKind::FindInputStream))?;
+    let mut decoding_context = DecodingCodecContext::create_decoding_context_from_av_stream(&mut stream)?;
+    let time_base = Rational::from(stream.time_base);
+
+    let mut frame = None;
+    for _ in 0..THUMBNAIL_MAX_PACKETS {
+        let packet = match input_context.next_packet() {
+            Some(packet) => packet,
+            None => break,
+        };
+        if packet.stream_index != VIDEO_STREAM_INDEX {
+            continue;
+        }
+        match decoding_context.decode_packet(&packet) {
+            Ok(decoded) => {
+                frame = Some(decoded);
+                break;
+            },
+            Err(e) => {
+                let needs_more = match *e.kind() {
+                    UnsafeErrorKind::ReceiveFrame(AVERROR_EAGAIN) => true,
+                    _ => false,
+                };
+                if !needs_more {
+                    return Err(ServerError::from(e));
+                }
+            },
+        }
+    }
+    let mut frame = frame.ok_or(ServerError::new(ServerErrorKind::NoThumbnailFrame))?;
+
+    let (width, height, pix_fmt) = {
+        let context: &AVCodecContext = decoding_context.as_ref();
+        (context.width, context.height, context.pix_fmt)
+    };
+    if width <= 0 || height <= 0 {
+        return Err(ServerError::new(ServerErrorKind::NoThumbnailFrame));
+    }
+    // encoders and scalers both want even dimensions
+    let thumbnail_height = cmp::max(2, (height as i64 * THUMBNAIL_WIDTH as i64 / width as i64) as i32 & !1);
+
+    let mut sws_context = SWSContext::new_scaled(
+        SWSImageDefinition::new(height, width, pix_fmt),
+        SWSImageDefinition::new(thumbnail_height, THUMBNAIL_WIDTH, AVPixelFormat::AV_PIX_FMT_RGB24),
+        ScalingAlgorithm::default()
+    )?;
+    let mut png_context = EncodingCodecContext::create_png_context(thumbnail_height, THUMBNAIL_WIDTH, time_base)?;
+    let scaled = sws_context.scale_frame(&mut frame, 32, 0)?;
+    let png = png_context.encode_png_frame(&scaled)?;
+
+    let mut file = File::create(thumbnail)?;
+    file.write_all(&png)?;
+    file.sync_all()?;
+    Ok(())
+}
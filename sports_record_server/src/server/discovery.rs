@@ -0,0 +1,30 @@
+use std::net::{UdpSocket, SocketAddr, Ipv4Addr};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use ffmpeg_common::networking::DiscoveryBeacon;
+
+use server::ServerError;
+
+const BEACON_INTERVAL_MS: u64 = 1000;
+
+/// Multicasts a signed `DiscoveryBeacon` once a second so clients without a configured server address can find us.
+pub fn start_discovery_beacon(multicast_ip: Ipv4Addr, discovery_port: u16, team_key: &str, beacon: DiscoveryBeacon) -> Result<JoinHandle<()>, ServerError> {
+    let udp = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+    udp.set_multicast_ttl_v4(1)?;
+
+    let destination = SocketAddr::from((multicast_ip, discovery_port));
+    let payload = beacon.to_signed_bytes(team_key.as_bytes())?;
+
+    let handle = thread::Builder::new().name("discovery_beacon_thread".to_string()).spawn(move || {
+        loop {
+            if let Err(e) = udp.send_to(&payload, destination) {
+                warn!("Failed to send discovery beacon: {}", e);
+            }
+            thread::sleep(Duration::from_millis(BEACON_INTERVAL_MS));
+        }
+    })?;
+
+    Ok(handle)
+}
@@ -25,6 +25,13 @@ pub enum ServerErrorKind {
     EnvVarError(env::VarError),
     ServerConfigError(ServerConfigurationError),
 
+    RecordingInProgress,
+    NoRecordingInProgress,
+    NotFound,
+    UnsupportedSchemaVersion(i32),
+    ForeignKeysUnavailable,
+    NoThumbnailFrame,
+    NothingToExport,
 }
 
 impl fmt::Display for ServerErrorKind {
@@ -39,6 +46,13 @@ impl fmt::Display for ServerErrorKind {
             &ServerErrorKind::RecvError(ref err) => err.fmt(fmter),
             &ServerErrorKind::EnvVarError(ref err) => err.fmt(fmter),
             &ServerErrorKind::ServerConfigError(ref err) => err.fmt(fmter),
+            &ServerErrorKind::RecordingInProgress => write!(fmter, "A play is already being recorded"),
+            &ServerErrorKind::NoRecordingInProgress => write!(fmter, "No play is currently being recorded"),
+            &ServerErrorKind::NotFound => write!(fmter, "The requested game or play does not exist"),
+            &ServerErrorKind::UnsupportedSchemaVersion(ref v) => write!(fmter, "The database has schema version {}, which this server does not understand", v),
+            &ServerErrorKind::ForeignKeysUnavailable => write!(fmter, "SQLite refused to enable foreign key enforcement"),
+            &ServerErrorKind::NoThumbnailFrame => write!(fmter, "No frame of the clip could be decoded for a thumbnail"),
+            &ServerErrorKind::NothingToExport => write!(fmter, "There are no clips to export"),
         }
     }
 }
@@ -52,6 +66,10 @@ impl ServerError {
     pub fn new(type_of_err: ServerErrorKind) -> ServerError {
         ServerError { error_type: type_of_err }
     }
+
+    pub fn kind(&self) -> &ServerErrorKind {
+        &self.error_type
+    }
 }
 
 impl fmt::Display for ServerError {
@@ -0,0 +1,235 @@
+use std::cmp;
+use std::ffi::CString;
+
+use server::{ServerError, ServerErrorKind};
+use server::client_handling::ClipOutput;
+use server::export::{draw_label, LABEL_HEIGHT, microseconds, rescale};
+
+use ffmpeg_common::unsafe_code::format::{FormatContext, InputContext};
+use ffmpeg_common::unsafe_code::sws::{SWSContext, SWSImageDefinition, ScalingAlgorithm};
+use ffmpeg_common::unsafe_code::{EncodingCodecContext, DecodingCodecContext, CodecId, CodecOptions, Frame, Rational, UnsafeError, UnsafeErrorKind};
+use ffmpeg_common::networking::VIDEO_STREAM_INDEX;
+
+use ffmpeg_sys::*;
+
+/// Each angle is scaled to fit a tile of this size, keeping its aspect ratio.
+const TILE_WIDTH: i32 = 640;
+const TILE_HEIGHT: i32 = 360;
+const FRAME_RATE: i32 = 30;
+const GOP_SIZE: i32 = 30;
+/// Composites are rendered once and kept, so they get a slower preset than the cameras use live.
+const ENCODER_OPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("preset", "medium"),
+    ("crf", "23"),
+];
+const BLACK: [u8; 3] = [16, 128, 128];
+
+/// One angle of a composite: the clip's file, the name drawn over its tile and where it starts in the play.
+pub struct CompositeInput {
+    pub path: String,
+    pub label: String,
+    /// Microseconds from the start of the play to the clip's first frame, 0 when it is not known.
+    pub start_offset: i64,
+}
+
+/// An angle being decoded. It always shows the latest frame that is due, and keeps showing its
+/// last frame once it ran out, until every angle has.
+struct Angle {
+    input: InputContext,
+    decoder: DecodingCodecContext,
+    scaler: SWSContext,
+    time_base: Rational,
+    start_offset: i64,
+    label: String,
+    /// Where the tile goes in the composite, centred in its cell.
+    position: (i32, i32),
+    /// The scaled picture currently shown, black until the angle's first frame is due.
+    tile: Frame,
+    /// The next decoded frame and when it is due, in microseconds from the start of the play.
+    next: Option<(i64, Frame)>,
+    first_timestamp: Option<i64>,
+    last_due: i64,
+    exhausted: bool,
+}
+
+impl Angle {
+    fn open(input: &CompositeInput, cell: (i32, i32)) -> Result<Angle, ServerError> {
+        let input_format = InputContext::create_input_format(CString::new("mp4").unwrap());
+        let mut input_context = FormatContext::new_input(input_format, CString::new(input.path.as_str()).unwrap())?;
+        let mut stream = input_context.find_input_stream(VIDEO_STREAM_INDEX as usize).ok_or(UnsafeError::new(UnsafeErrorKind::FindInputStream))?;
+        let decoder = DecodingCodecContext::create_decoding_context_from_av_stream(&mut stream)?;
+
+        let (width, height, pix_fmt) = {
+            let context: &AVCodecContext = decoder.as_ref();
+            (context.width, context.height, context.pix_fmt)
+        };
+        if width <= 0 || height <= 0 {
+            return Err(ServerError::from(UnsafeError::new(UnsafeErrorKind::FindInputStream)));
+        }
+        let (tile_width, tile_height) = fit_in_tile(width, height);
+        let scaler = SWSContext::new_scaled(
+            SWSImageDefinition::new(height, width, pix_fmt),
+            SWSImageDefinition::new(tile_height, tile_width, AVPixelFormat::AV_PIX_FMT_YUV420P),
+            ScalingAlgorithm::default()
+        )?;
+        let mut tile = Frame::new_video(tile_width, tile_height, AVPixelFormat::AV_PIX_FMT_YUV420P)?;
+        tile.fill_yuv420p(0, 0, tile_width, tile_height, BLACK);
+
+        Ok(Angle {
+            time_base: Rational::from(stream.time_base),
+            input: input_context,
+            decoder: decoder,
+            scaler: scaler,
+            start_offset: input.start_offset,
+            label: input.label.clone(),
+            position: (cell.0 + (TILE_WIDTH - tile_width) / 2, cell.1 + (TILE_HEIGHT - tile_height) / 2),
+            tile: tile,
+            next: None,
+            first_timestamp: None,
+            last_due: input.start_offset,
+            exhausted: false,
+        })
+    }
+
+    /// Moves on to the latest frame due by `time`, skipping any in between.
+    fn show_until(&mut self, time: i64) -> Result<(), ServerError> {
+        let mut due = None;
+        loop {
+            if self.next.is_none() {
+                self.next = self.decode_next()?;
+            }
+            let is_due = match self.next {
+                Some((at, _)) => at <= time,
+                None => false,
+            };
+            if !is_due {
+                break;
+            }
+            due = self.next.take().map(|(_, frame)| frame);
+        }
+        if let Some(mut frame) = due {
+            self.scaler.scale_into(&mut frame, &mut self.tile)?;
+        }
+        Ok(())
+    }
+
+    fn finished(&self) -> bool {
+        self.exhausted && self.next.is_none()
+    }
+
+    fn decode_next(&mut self) -> Result<Option<(i64, Frame)>, ServerError> {
+        loop {
+            if let Some(frame) = self.decoder.receive_frame()? {
+                let timestamp = frame.capture_timestamp();
+                if timestamp != AV_NOPTS_VALUE {
+                    let first = *self.first_timestamp.get_or_insert(timestamp);
+                    self.last_due = self.start_offset + rescale(timestamp - first, self.time_base, microseconds());
+                }
+                return Ok(Some((self.last_due, frame)));
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+            match self.input.next_packet() {
+                Some(packet) => if packet.stream_index == VIDEO_STREAM_INDEX {
+                    self.decoder.send_packet(&packet)?;
+                },
+                None => {
+                    self.decoder.send_end_of_stream()?;
+                    self.exhausted = true;
+                },
+            }
+        }
+    }
+}
+
+/// Renders the angles of a play side by side in a grid, lined up by their start offsets, with
+/// each camera's name under its tile and `title` in the corner, and writes it as a new clip.
+/// Returns how long the composite runs in microseconds.
+/// `progress` is called for every input that could not be opened, then for the rest once the composite is written.
+pub fn render_composite<F: FnMut(usize, bool)>(inputs: &[CompositeInput], title: &str, out_dir: &str, uuid: &str, mut progress: F) -> Result<Option<i64>, ServerError> {
+    let columns = (1usize..).find(|&columns| columns * columns >= inputs.len()).unwrap_or(1);
+    let rows = cmp::max(1, (inputs.len() + columns - 1) / columns);
+    let width = columns as i32 * TILE_WIDTH;
+    let height = rows as i32 * TILE_HEIGHT;
+
+    let mut angles = Vec::new();
+    let mut opened = Vec::new();
+    for (position, input) in inputs.iter().enumerate() {
+        let cell = ((position % columns) as i32 * TILE_WIDTH, (position / columns) as i32 * TILE_HEIGHT);
+        match Angle::open(input, cell) {
+            Ok(angle) => {
+                angles.push(angle);
+                opened.push(position);
+            },
+            Err(e) => {
+                warn!("Leaving {} out of the composite: {}", input.path, e);
+                progress(position, false);
+            },
+        }
+    }
+    if angles.is_empty() {
+        return Err(ServerError::new(ServerErrorKind::NothingToExport));
+    }
+
+    let time_base = Rational::new(1, FRAME_RATE);
+    let mut options = CodecOptions::new();
+    for &(key, value) in ENCODER_OPTIONS {
+        options.set(key, value);
+    }
+    let mut encoder = EncodingCodecContext::create_encoding_context_with_options(CodecId::from(AVCodecID::AV_CODEC_ID_H264), height, width, time_base, Rational::new(FRAME_RATE, 1), GOP_SIZE, 0, &options)?;
+    let mut clip = ClipOutput::open(out_dir, uuid.to_owned(), &encoder, None)?;
+    info!("Rendering a composite of {} angles at {}x{} as clip {}", angles.len(), width, height, uuid);
+
+    let result = write_frames(&mut angles, &mut encoder, &mut clip, time_base, width, height, title);
+    match result {
+        Ok(_) => {
+            let duration = clip.duration();
+            clip.finish()?;
+            for position in opened {
+                progress(position, true);
+            }
+            Ok(duration)
+        },
+        Err(e) => {
+            clip.discard();
+            Err(e)
+        },
+    }
+}
+
+fn write_frames(angles: &mut [Angle], encoder: &mut EncodingCodecContext, clip: &mut ClipOutput, time_base: Rational, width: i32, height: i32, title: &str) -> Result<(), ServerError> {
+    // the composite starts with whichever angle started first
+    let start = angles.iter().map(|angle| angle.start_offset).min().unwrap_or(0);
+    let mut pts = 0;
+    while !angles.iter().all(Angle::finished) {
+        let time = start + rescale(pts, time_base, microseconds());
+        let mut canvas = Frame::new_video(width, height, AVPixelFormat::AV_PIX_FMT_YUV420P)?;
+        canvas.fill_yuv420p(0, 0, width, height, BLACK);
+        for angle in angles.iter_mut() {
+            angle.show_until(time)?;
+            let (x, y) = angle.position;
+            canvas.draw_yuv420p(&angle.tile, x, y);
+            draw_label(&mut canvas, x, y + angle.tile.height - LABEL_HEIGHT, &angle.label);
+        }
+        draw_label(&mut canvas, 0, 0, title);
+        canvas.pts = pts;
+        for packet in encoder.encode_frame(canvas)? {
+            clip.write_packet(packet, 0, time_base)?;
+        }
+        pts += 1;
+    }
+    for packet in encoder.encode_null_frame()? {
+        clip.write_packet(packet, 0, time_base)?;
+    }
+    Ok(())
+}
+
+/// The largest even size with the frame's aspect ratio that fits in a tile.
+fn fit_in_tile(width: i32, height: i32) -> (i32, i32) {
+    if width as i64 * TILE_HEIGHT as i64 > height as i64 * TILE_WIDTH as i64 {
+        (TILE_WIDTH, cmp::max(2, (height as i64 * TILE_WIDTH as i64 / width as i64) as i32 & !1))
+    } else {
+        (cmp::max(2, (width as i64 * TILE_HEIGHT as i64 / height as i64) as i32 & !1), TILE_HEIGHT)
+    }
+}
@@ -0,0 +1,211 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use server::{ServerError, ServerErrorKind, sql};
+use server::sql::ExportClip;
+use server::client_handling::{clip_path, thumbnail_path, generate_thumbnail};
+use server::export::{ExportInput, CompositeInput, concatenate, render_composite};
+
+use iron::typemap;
+use uuid::Uuid;
+
+/// Exports are written to `<output directory>/exports/`.
+pub const EXPORT_DIRECTORY: &'static str = "exports";
+
+pub fn export_path(out_dir: &str, file: &str) -> String {
+    String::from(out_dir) + "/" + EXPORT_DIRECTORY + "/export_" + file + ".mp4"
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExportRequest {
+    /// Every clip one camera recorded during a game, with a chapter per play.
+    Game { game_id: i64, client_id: String },
+    /// Every angle of one play one after the other, with a chapter per camera.
+    Play { play_id: i64 },
+    /// Every angle of one play at once in a grid, re-encoded and stored as a derived clip of the play.
+    Composite { play_id: i64 },
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum ExportState {
+    Running,
+    Finished,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportJob {
+    pub id: usize,
+    pub request: ExportRequest,
+    pub state: ExportState,
+    pub clips_done: usize,
+    pub clips_total: usize,
+    /// Clips that could not be read, or whose streams differ from the first clip's so they cannot be remuxed with it.
+    pub skipped: Vec<String>,
+    /// Where the file can be downloaded once the job finished.
+    pub download: Option<String>,
+}
+
+/// Export jobs started since the server started. Each one runs on a thread of its own; the jobs
+/// themselves are only kept in memory, the files they write stay in the export directory.
+#[derive(Clone)]
+pub struct ExportJobs {
+    jobs: Arc<Mutex<Vec<ExportJob>>>,
+    db_ref: sql::DatabaseRef,
+    out_dir: String,
+}
+
+impl typemap::Key for ExportJobs {
+    type Value = ExportJobs;
+}
+
+impl ExportJobs {
+    pub fn new(db_ref: sql::DatabaseRef, out_dir: String) -> ExportJobs {
+        ExportJobs {
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            db_ref: db_ref,
+            out_dir: out_dir,
+        }
+    }
+
+    /// Looks up the clips right away, so a request for a game or play that does not exist fails here.
+    pub fn start(&self, request: ExportRequest) -> Result<ExportJob, ServerError> {
+        let clips = match request {
+            ExportRequest::Game { game_id, ref client_id } => self.db_ref.game_clips(game_id, client_id)?,
+            ExportRequest::Play { play_id } | ExportRequest::Composite { play_id } => self.db_ref.play_clips(play_id)?,
+        };
+        if clips.is_empty() {
+            return Err(ServerError::new(ServerErrorKind::NothingToExport));
+        }
+        fs::create_dir_all(Path::new(&self.out_dir).join(EXPORT_DIRECTORY))?;
+
+        let job = {
+            let mut lock = self.jobs.lock().expect("mutex poisoned");
+            let job = ExportJob {
+                id: lock.len() + 1,
+                request: request.clone(),
+                state: ExportState::Running,
+                clips_done: 0,
+                clips_total: clips.len(),
+                skipped: Vec::new(),
+                download: None,
+            };
+            lock.push(job.clone());
+            job
+        };
+
+        let jobs = self.clone();
+        let id = job.id;
+        thread::spawn(move || jobs.run(id, request, clips));
+        Ok(job)
+    }
+
+    pub fn list(&self) -> Vec<ExportJob> {
+        self.jobs.lock().expect("mutex poisoned").clone()
+    }
+
+    pub fn get(&self, id: usize) -> Option<ExportJob> {
+        self.jobs.lock().expect("mutex poisoned").iter().find(|job| job.id == id).cloned()
+    }
+
+    fn run(&self, id: usize, request: ExportRequest, clips: Vec<ExportClip>) {
+        let progress = |position: usize, written: bool| {
+            self.update(id, |job| {
+                job.clips_done = position + 1;
+                if !written {
+                    job.skipped.push(clips[position].uuid.clone());
+                }
+            });
+        };
+        let result = match request {
+            ExportRequest::Composite { play_id } => self.composite(play_id, &clips, progress),
+            _ => self.export_file(&request, &clips, progress),
+        };
+        match result {
+            Ok(download) => {
+                info!("Export {} finished: {}", id, download);
+                self.update(id, |job| {
+                    job.state = ExportState::Finished;
+                    job.download = Some(download);
+                });
+            },
+            Err(e) => {
+                error!("Export {} failed: {}", id, e);
+                self.update(id, |job| job.state = ExportState::Failed(e.to_string()));
+            },
+        }
+    }
+
+    /// Remuxes the clips into a file in the export directory and returns where it is downloaded.
+    fn export_file<F: FnMut(usize, bool)>(&self, request: &ExportRequest, clips: &[ExportClip], progress: F) -> Result<String, ServerError> {
+        let inputs: Vec<ExportInput> = clips.iter().enumerate().map(|(position, clip)| ExportInput {
+            path: clip_path(&self.out_dir, &clip.uuid),
+            title: chapter_title(request, position, clip),
+        }).collect();
+        let file = Uuid::new_v4().simple().to_string();
+        let path = export_path(&self.out_dir, &file);
+        if let Err(e) = concatenate(&inputs, &path, progress) {
+            let _ = fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(format!("/{}/{}", EXPORT_DIRECTORY, file))
+    }
+
+    /// Renders the composite next to the recorded clips and stores it as the play's derived clip,
+    /// replacing one rendered before. Returns where it is watched.
+    fn composite<F: FnMut(usize, bool)>(&self, play_id: i64, clips: &[ExportClip], progress: F) -> Result<String, ServerError> {
+        let inputs: Vec<CompositeInput> = clips.iter().enumerate().map(|(position, clip)| CompositeInput {
+            path: clip_path(&self.out_dir, &clip.uuid),
+            label: angle_title(position, clip),
+            start_offset: clip.start_offset.unwrap_or(0),
+        }).collect();
+        let uuid = Uuid::new_v4().simple().to_string();
+        // render_composite cleans up after itself when it fails
+        let duration = render_composite(&inputs, &format!("Play {}", play_id), &self.out_dir, &uuid, progress)?;
+
+        let replaced = match self.db_ref.replace_derived_clip(play_id, &uuid) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                let _ = fs::remove_file(clip_path(&self.out_dir, &uuid));
+                return Err(e);
+            },
+        };
+        for old_uuid in replaced {
+            if let Err(e) = fs::remove_file(clip_path(&self.out_dir, &old_uuid)) {
+                warn!("Could not remove composite {}, replaced by a new one: {}", old_uuid, e);
+            }
+            let _ = fs::remove_file(thumbnail_path(&self.out_dir, &old_uuid));
+        }
+        if let Some(duration) = duration {
+            if let Err(e) = self.db_ref.set_clip_duration(&uuid, duration) {
+                warn!("Could not store the duration of composite {}: {}", uuid, e);
+            }
+        }
+        generate_thumbnail(&self.out_dir, &uuid, &self.db_ref);
+        Ok(format!("/videos/{}", uuid))
+    }
+
+    fn update<F: FnOnce(&mut ExportJob)>(&self, id: usize, change: F) {
+        let mut lock = self.jobs.lock().expect("mutex poisoned");
+        if let Some(job) = lock.iter_mut().find(|job| job.id == id) {
+            change(job);
+        }
+    }
+}
+
+fn chapter_title(request: &ExportRequest, position: usize, clip: &ExportClip) -> String {
+    match *request {
+        ExportRequest::Game { .. } => format!("Play {}", clip.play_id),
+        ExportRequest::Play { .. } | ExportRequest::Composite { .. } => angle_title(position, clip),
+    }
+}
+
+fn angle_title(position: usize, clip: &ExportClip) -> String {
+    match clip.client_id {
+        Some(ref client_id) => client_id.clone(),
+        None => format!("Angle {}", position + 1),
+    }
+}
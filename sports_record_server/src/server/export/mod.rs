@@ -0,0 +1,9 @@
+pub use self::composite::*;
+pub use self::export_job::*;
+pub use self::overlay::*;
+pub use self::remux::*;
+
+mod composite;
+mod export_job;
+mod overlay;
+mod remux;
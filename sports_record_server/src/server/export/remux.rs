@@ -0,0 +1,150 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::CString;
+
+use server::{ServerError, ServerErrorKind};
+
+use ffmpeg_common::unsafe_code::format::{FormatContext, InputContext, OutputContext, Stream};
+use ffmpeg_common::unsafe_code::Rational;
+
+use ffmpeg_sys::*;
+
+
+/// One clip of an export, and the chapter it becomes.
+pub struct ExportInput {
+    pub path: String,
+    pub title: String,
+}
+
+/// What a stream has to agree on with the first clip's for its packets to be remuxed into the same track.
+#[derive(Debug, PartialEq)]
+struct StreamSignature {
+    codec_id: AVCodecID,
+    width: i32,
+    height: i32,
+    sample_rate: i32,
+    channels: i32,
+}
+
+impl<'a> From<&'a Stream> for StreamSignature {
+    fn from(stream: &'a Stream) -> StreamSignature {
+        unsafe {
+            let parameters = &*stream.codecpar;
+            StreamSignature {
+                codec_id: parameters.codec_id,
+                width: parameters.width,
+                height: parameters.height,
+                sample_rate: parameters.sample_rate,
+                channels: parameters.channels,
+            }
+        }
+    }
+}
+
+struct Output {
+    context: OutputContext,
+    signatures: Vec<StreamSignature>,
+    time_bases: Vec<Rational>,
+}
+
+/// Writes `inputs` one after the other into a single MP4 at `path` without re-encoding, with a
+/// chapter per input. The first clip that opens decides the streams; a clip whose streams differ,
+/// e.g. after a camera changed its resolution, cannot share a track and is left out.
+/// `progress` is called after every input with its index and whether it was written.
+pub fn concatenate<F: FnMut(usize, bool)>(inputs: &[ExportInput], path: &str, mut progress: F) -> Result<(), ServerError> {
+    let mut output: Option<Output> = None;
+    // where the next clip starts in the export
+    let mut offset = 0;
+
+    for (position, input) in inputs.iter().enumerate() {
+        let mut input_context = match open_input(&input.path) {
+            Ok(context) => context,
+            Err(e) => {
+                warn!("Leaving {} out of the export: {}", input.path, e);
+                progress(position, false);
+                continue;
+            },
+        };
+        let streams: Vec<Stream> = (0..input_context.nb_streams as usize).filter_map(|i| input_context.find_input_stream(i)).collect();
+        let signatures: Vec<StreamSignature> = streams.iter().map(StreamSignature::from).collect();
+
+        if output.is_none() {
+            output = Some(open_output(path, &streams, signatures)?);
+        } else if output.as_ref().map(|output| output.signatures != signatures).unwrap_or(false) {
+            warn!("Leaving {} out of the export, its streams differ from the first clip's", input.path);
+            progress(position, false);
+            continue;
+        }
+        let output = output.as_mut().expect("the output was just opened");
+
+        let input_time_bases: Vec<Rational> = streams.iter().map(|stream| Rational::from(stream.time_base)).collect();
+        let mut start: Option<i64> = None;
+        let mut end = 0;
+        while let Some(mut pkt) = input_context.next_packet() {
+            let index = pkt.stream_index as usize;
+            if index >= input_time_bases.len() {
+                continue;
+            }
+            let (from, to) = (input_time_bases[index], output.time_bases[index]);
+            if pkt.dts == AV_NOPTS_VALUE {
+                pkt.dts = pkt.pts;
+            }
+            if pkt.dts == AV_NOPTS_VALUE {
+                continue;
+            }
+            // the clip's first packet sets where it starts, so every stream keeps its place relative to the others
+            let clip_start = *start.get_or_insert(rescale(pkt.dts, from, microseconds()));
+            let pts = if pkt.pts == AV_NOPTS_VALUE { pkt.dts } else { pkt.pts };
+            let shift = offset - clip_start;
+            end = cmp::max(end, rescale(pts + pkt.duration, from, microseconds()) - clip_start);
+            pkt.pts = rescale(rescale(pts, from, microseconds()) + shift, microseconds(), to);
+            pkt.dts = rescale(rescale(pkt.dts, from, microseconds()) + shift, microseconds(), to);
+            pkt.duration = rescale(pkt.duration, from, to);
+            pkt.pos = -1;
+            output.context.write_video_frame(index as i32, pkt)?;
+        }
+
+        output.context.add_chapter(position as i32, microseconds(), offset, offset + end, &input.title)?;
+        offset += end;
+        progress(position, true);
+    }
+
+    match output {
+        Some(mut output) => {
+            output.context.write_null_video_frame()?;
+            output.context.write_video_trailer()?;
+            Ok(())
+        },
+        None => Err(ServerError::new(ServerErrorKind::NothingToExport)),
+    }
+}
+
+fn open_input(path: &str) -> Result<InputContext, ServerError> {
+    let input_format = InputContext::create_input_format(CString::new("mp4").unwrap());
+    Ok(FormatContext::new_input(input_format, CString::new(path).unwrap())?)
+}
+
+fn open_output(path: &str, streams: &[Stream], signatures: Vec<StreamSignature>) -> Result<Output, ServerError> {
+    let mut context: OutputContext = FormatContext::new_output(CString::new(path).unwrap());
+    let mut output_streams = Vec::new();
+    for stream in streams {
+        output_streams.push(context.copy_stream(stream)?);
+    }
+    context.open_video_file(path)?;
+    context.write_video_header()?;
+    // the muxer may have picked its own time bases while writing the header
+    let time_bases = output_streams.iter().map(|stream| Rational::from(stream.time_base)).collect();
+    info!("Exporting to {}", path);
+    Ok(Output { context: context, signatures: signatures, time_bases: time_bases })
+}
+
+/// Timestamps are carried between clips in microseconds, whatever time base each stream uses.
+pub fn microseconds() -> Rational {
+    Rational::new(1, 1_000_000)
+}
+
+pub fn rescale(value: i64, from: Rational, to: Rational) -> i64 {
+    unsafe {
+        av_rescale_q(value, from.into(), to.into())
+    }
+}
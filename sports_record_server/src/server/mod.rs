@@ -3,6 +3,8 @@ mod recording_server;
 mod web;
 mod sql;
 mod server_configuration;
+mod discovery;
+mod export;
 
 pub mod client_handling;
 
@@ -4,16 +4,19 @@ use std::result::Result;
 use std::path::Path;
 use std::fs::File;
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
 use server::client_handling::*;
 use server::web;
-use server::{ ServerError, ServerErrorKind, sql };
+use server::{ ServerError, ServerErrorKind, sql, discovery };
+use server::export::ExportJobs;
 
 use ffmpeg_common::unsafe_code::init_av;
+use ffmpeg_common::networking::{DiscoveryBeacon, tls_acceptor};
 
 use rusqlite::Connection;
 use iron::prelude::*;
-use iron::Listening;
+use iron::{Listening, Handler};
 use router::Router;
 use rand;
 use rand::Rng;
@@ -25,12 +28,15 @@ pub struct RecordingServer {
     iron_server: Listening,
     client_handler: ClientStream,
     control_panel_key: String,
+    session_code: u32,
+    discovery_handle: JoinHandle<()>,
 }
 
 impl RecordingServer {
 
     pub fn new(server_conf: ServerConfiguration) -> Result<RecordingServer, ServerError> {
-        let tcp = TcpListener::bind(server_conf.get_clip_server_port())?;
+        let team_key = server_conf.get_team_key()?.to_owned();
+        let tcp = TcpListener::bind(server_conf.get_clip_server_addr())?;
         let db_loc = Path::new(server_conf.get_output_directory());
         let db_loc = db_loc.join(server_conf.get_database_name());
 
@@ -40,8 +46,17 @@ impl RecordingServer {
 
         let database = sql::DatabaseRef::new(&db_loc)?;
 
+        let tls = match server_conf.get_tls_settings() {
+            Some(tls_conf) => {
+                info!("Clients have to connect over TLS");
+                Some(tls_acceptor(tls_conf.get_certificate_chain(), tls_conf.get_private_key())?)
+            },
+            None => None,
+        };
+
         init_av();
-        let client_stream = try!(ClientStream::new(database, server_conf.get_output_directory().to_str().unwrap().to_owned()));
+        let export_jobs = ExportJobs::new(database.clone(), server_conf.get_output_directory().to_str().unwrap().to_owned());
+        let client_stream = try!(ClientStream::new(database, server_conf.get_output_directory().to_str().unwrap().to_owned(), team_key.clone(), tls));
 
 
         let mut router = Router::new();
@@ -51,12 +66,54 @@ impl RecordingServer {
         let weak_client = client_stream.get_weak();
         asset_chain.link_before(move |req: &mut Request| { req.extensions.insert::<WeakClientStream>(weak_client.clone()); Ok(()) } );
         router.get("/dist/:query", asset_chain, "asset_handling");
-        router.get("/videos/:query", web::web_handler::individual_video_handler, "query");
+        let mut video_chain = Chain::new(web::web_handler::individual_video_handler);
+        let clip_dir = server_conf.get_output_directory().to_owned();
+        video_chain.link_before(move |req: &mut Request| { req.extensions.insert::<web::body_writer::ClipDirectory>(clip_dir.clone()); Ok(()) } );
+        router.get("/videos/:query", video_chain, "query");
 
-        let iron_serv_res = Iron::new(router).http(server_conf.get_web_server_port());
+        let mut thumbnail_chain = Chain::new(web::web_handler::thumbnail_handler);
+        let thumbnail_dir = server_conf.get_output_directory().to_owned();
+        thumbnail_chain.link_before(move |req: &mut Request| { req.extensions.insert::<web::body_writer::ClipDirectory>(thumbnail_dir.clone()); Ok(()) } );
+        router.get("/thumbnails/:query", thumbnail_chain, "thumbnail");
+
+        let mut live_chain = Chain::new(web::web_handler::live_stream_handler);
+        let live_dir = server_conf.get_output_directory().to_owned();
+        live_chain.link_before(move |req: &mut Request| { req.extensions.insert::<web::body_writer::ClipDirectory>(live_dir.clone()); Ok(()) } );
+        router.get("/live/:feed/:file", live_chain, "live");
 
         let mut rng = rand::thread_rng();
         let ascii_chars: String = rng.gen_ascii_chars().take(20).fold(String::from(""), |mut init: String, item: char| { init.push(item); init });
+        info!("Control panel key: {}", ascii_chars);
+
+        // exports can hold a whole game, so they are only handed out with the control panel key
+        let mut export_download_chain = Chain::new(web::web_handler::export_download_handler);
+        export_download_chain.link_before(web::api_handler::ApiKeyCheck::new(&ascii_chars));
+        let export_dir = server_conf.get_output_directory().to_owned();
+        export_download_chain.link_before(move |req: &mut Request| { req.extensions.insert::<web::body_writer::ClipDirectory>(export_dir.clone()); Ok(()) } );
+        router.get("/exports/:query", export_download_chain, "export_download");
+
+        router.post("/api/recording/start", api_chain(web::api_handler::start_recording_handler, &client_stream, &ascii_chars), "api_recording_start");
+        router.post("/api/recording/stop", api_chain(web::api_handler::stop_recording_handler, &client_stream, &ascii_chars), "api_recording_stop");
+        router.get("/api/recording", api_chain(web::api_handler::recording_status_handler, &client_stream, &ascii_chars), "api_recording_status");
+        router.get("/api/clients", api_chain(web::api_handler::client_states_handler, &client_stream, &ascii_chars), "api_client_states");
+        router.delete("/api/clients/:addr", api_chain(web::api_handler::remove_client_handler, &client_stream, &ascii_chars), "api_remove_client");
+        router.get("/api/games", api_chain(web::api_handler::list_games_handler, &client_stream, &ascii_chars), "api_list_games");
+        router.post("/api/games", api_chain(web::api_handler::create_game_handler, &client_stream, &ascii_chars), "api_create_game");
+        router.post("/api/games/:id/resume", api_chain(web::api_handler::resume_game_handler, &client_stream, &ascii_chars), "api_resume_game");
+        router.post("/api/games/:id/close", api_chain(web::api_handler::close_game_handler, &client_stream, &ascii_chars), "api_close_game");
+        router.get("/api/games/:id/plays", api_chain(web::api_handler::list_plays_handler, &client_stream, &ascii_chars), "api_list_plays");
+        router.get("/api/library", api_chain(web::api_handler::library_handler, &client_stream, &ascii_chars), "api_library");
+        router.put("/api/plays/:id", api_chain(web::api_handler::play_metadata_handler, &client_stream, &ascii_chars), "api_play_metadata");
+        router.post("/api/exports", export_chain(web::api_handler::start_export_handler, &client_stream, &export_jobs, &ascii_chars), "api_start_export");
+        router.get("/api/exports", export_chain(web::api_handler::list_exports_handler, &client_stream, &export_jobs, &ascii_chars), "api_list_exports");
+        router.get("/api/exports/:id", export_chain(web::api_handler::export_status_handler, &client_stream, &export_jobs, &ascii_chars), "api_export_status");
+
+        let iron_serv_res = Iron::new(router).http(server_conf.get_web_server_port());
+        let session_code: u32 = rng.gen();
+
+        let beacon = DiscoveryBeacon::new(session_code, server_conf.get_team_name(), server_conf.get_clip_server_addr().port());
+        let ip_settings = server_conf.get_ip_settings();
+        let discovery_handle = discovery::start_discovery_beacon(ip_settings.get_multicast_ip(), ip_settings.get_discovery_port(), &team_key, beacon)?;
 
         match iron_serv_res {
             Ok(item) => return Ok(RecordingServer { 
@@ -64,30 +121,57 @@ impl RecordingServer {
                 iron_server: item, 
                 client_handler: client_stream,
                 control_panel_key: ascii_chars,
+                session_code: session_code,
+                discovery_handle: discovery_handle,
             }),
             Err(_) => return Err(ServerError::new(ServerErrorKind::IronError)),
         }
 
     }
 
+    pub fn get_control_panel_key(&self) -> &str {
+        &self.control_panel_key
+    }
+
+    pub fn get_session_code(&self) -> u32 {
+        self.session_code
+    }
+
     pub fn get_client_handler(&self) -> ClientStream {
         self.client_handler.clone()
     }
 
     pub fn start_handling_requests(&self) {
         let listener = self.listener.clone();
-        let mut ip_sender = self.client_handler.clone();
+        let ip_sender = self.client_handler.clone();
         thread::spawn(move || {
             for stream in listener.incoming() {
                 match stream {
                     Ok(stream) => {
-                            println!("Received Client: {:?}", stream.peer_addr());
-                            let _ = ip_sender.add_client(stream);
+                            debug!("Received Client: {:?}", stream.peer_addr());
+                            ip_sender.accept_client(stream);
                     }
-                    Err(e) => println!("An error occurred: {}", e), 
+                    Err(e) => warn!("An error occurred: {}", e), 
                 }
             }
         });
     }
 
-}
\ No newline at end of file
+}
+
+/// Wraps an API handler so it is only reachable with the control panel key and can reach the client stream.
+fn api_chain<H: Handler>(handler: H, client_stream: &ClientStream, key: &str) -> Chain {
+    let mut chain = Chain::new(handler);
+    let api_client = client_stream.get_weak();
+    chain.link_before(web::api_handler::ApiKeyCheck::new(key));
+    chain.link_before(move |req: &mut Request| { req.extensions.insert::<WeakClientStream>(api_client.clone()); Ok(()) } );
+    chain
+}
+
+/// An API chain that can also reach the export jobs.
+fn export_chain<H: Handler>(handler: H, client_stream: &ClientStream, export_jobs: &ExportJobs, key: &str) -> Chain {
+    let mut chain = api_chain(handler, client_stream, key);
+    let jobs = export_jobs.clone();
+    chain.link_before(move |req: &mut Request| { req.extensions.insert::<ExportJobs>(jobs.clone()); Ok(()) } );
+    chain
+}
@@ -10,11 +10,16 @@ use std::ffi::CString;
 use std::default::Default;
 use std::net::SocketAddr;
 
+use ffmpeg_common::logging::LogConfiguration;
+use ffmpeg_common::networking::{is_team_key_set, PLACEHOLDER_TEAM_KEY};
+
 #[derive(Debug)]
 pub enum ServerConfigurationError {
     TOMLDEError(toml::de::Error),
     TOMLSERError(toml::ser::Error),
     IOError(io::Error),
+    /// `team_key` is missing or still the placeholder, so any client could join.
+    TeamKeyNotSet,
 }
 
 impl fmt::Display for ServerConfigurationError {
@@ -23,6 +28,7 @@ impl fmt::Display for ServerConfigurationError {
             ServerConfigurationError::TOMLDEError(ref e) => e.fmt(f),
             ServerConfigurationError::TOMLSERError(ref e) => e.fmt(f),
             ServerConfigurationError::IOError(ref e) => e.fmt(f),
+            ServerConfigurationError::TeamKeyNotSet => write!(f, "team_key is not set; pick a secret key, give it to every camera and restart"),
         }
     } 
 }
@@ -58,11 +64,20 @@ impl From<toml::ser::Error> for ServerConfigurationError {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfiguration {
     team_name: String,
+    /// Older configurations do not have one; the server refuses to start until it is set.
+    #[serde(default)]
+    team_key: Option<String>,
 
     output_directory: PathBuf,
     database_name: String,
 
-    ip_configuration: IpConfiguration
+    ip_configuration: IpConfiguration,
+
+    #[serde(default)]
+    logging: LogConfiguration,
+
+    #[serde(default)]
+    tls: Option<TlsConfiguration>,
 }
 
 impl ServerConfiguration {
@@ -81,10 +96,25 @@ impl ServerConfiguration {
         &self.team_name
     }
 
+    pub fn get_team_key(&self) -> Result<&str, ServerConfigurationError> {
+        match self.team_key {
+            Some(ref key) if is_team_key_set(key) => Ok(key.as_str()),
+            _ => Err(ServerConfigurationError::TeamKeyNotSet),
+        }
+    }
+
     pub fn get_ip_settings(&self) -> &IpConfiguration {
         &self.ip_configuration
     }
 
+    pub fn get_clip_server_addr(&self) -> SocketAddr {
+        self.ip_configuration.get_clip_server_ip()
+    }
+
+    pub fn get_web_server_port(&self) -> SocketAddr {
+        self.ip_configuration.get_web_server_ip()
+    }
+
     pub fn get_output_directory(&self) -> &Path {
         &self.output_directory
     }
@@ -93,17 +123,31 @@ impl ServerConfiguration {
         &self.database_name
     }
 
+    pub fn get_logging_settings(&self) -> &LogConfiguration {
+        &self.logging
+    }
+
+    /// The clip port only speaks TLS when this is set.
+    pub fn get_tls_settings(&self) -> Option<&TlsConfiguration> {
+        self.tls.as_ref()
+    }
+
 }
 
 impl Default for ServerConfiguration {
     fn default() -> Self {
         ServerConfiguration {
             team_name: String::from("TEAM_NAME"),
+            team_key: Some(String::from(PLACEHOLDER_TEAM_KEY)),
 
             output_directory: PathBuf::from("./out/"),
             database_name: String::from("primary_database.db"),
 
             ip_configuration: IpConfiguration::default(),
+
+            logging: LogConfiguration::default(),
+
+            tls: None,
         }
     }
 }
@@ -112,10 +156,28 @@ impl Default for ServerConfiguration {
 pub struct IpConfiguration {
     clip_server_listen_ip: net::SocketAddr,
     web_server_listen_ip: net::SocketAddr,
-    multicast_ip: net::SocketAddr,
+    multicast_ip: net::Ipv4Addr,
     discovery_port: u16,
 }
 
+impl IpConfiguration {
+    pub fn get_clip_server_ip(&self) -> SocketAddr {
+        self.clip_server_listen_ip.clone()
+    }
+
+    pub fn get_web_server_ip(&self) -> SocketAddr {
+        self.web_server_listen_ip.clone()
+    }
+
+    pub fn get_multicast_ip(&self) -> net::Ipv4Addr {
+        self.multicast_ip.clone()
+    }
+
+    pub fn get_discovery_port(&self) -> u16 {
+        self.discovery_port
+    }
+}
+
 impl Default for IpConfiguration {
     fn default() -> Self {
         IpConfiguration {
@@ -125,4 +187,21 @@ impl Default for IpConfiguration {
             multicast_ip: net::Ipv4Addr::new(224, 0, 0, 12),
         }
     }
-}
\ No newline at end of file
+}
+
+/// PEM files the clip server presents to clients.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TlsConfiguration {
+    certificate_chain: PathBuf,
+    private_key: PathBuf,
+}
+
+impl TlsConfiguration {
+    pub fn get_certificate_chain(&self) -> &Path {
+        &self.certificate_chain
+    }
+
+    pub fn get_private_key(&self) -> &Path {
+        &self.private_key
+    }
+}
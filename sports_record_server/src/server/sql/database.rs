@@ -1,16 +1,20 @@
 use std::path;
 use std::fs::File;
-use std::sync::{Arc, Mutex, atomic};
+use std::sync::{Arc, Mutex, MutexGuard, atomic};
 use rusqlite;
 
-use server::ServerError;
+use server::{ServerError, ServerErrorKind};
+use server::sql::{Game, Play, Clip, PlayMetadata, NewGame, GamePlays, RecordingStatus, ExportClip};
+use server::sql::migrations;
+
+use ffmpeg_common::networking::wall_clock_micros;
 
 #[derive(Clone)]
 pub struct DatabaseRef {
     location: path::PathBuf,
     db_ref: Arc<Mutex<rusqlite::Connection>>,
     current_play_num: Arc<atomic::AtomicUsize>,
-    current_game_num: Arc<usize>,
+    current_game_num: Arc<Mutex<Option<i64>>>,
     in_transaction: Arc<atomic::AtomicBool>,
 }
 
@@ -20,54 +24,129 @@ impl DatabaseRef {
             let _ = try!(File::create(loc));
         }
 
-        let connection = try!(rusqlite::Connection::open(loc.to_owned()));
-
-        connection.execute("PRAGMA foriegn_keys = 1", &[])?;
-        connection.execute("CREATE TABLE IF NOT EXISTS games (id INTEGER PRIMARY KEY ASC, date TEXT)", &[])?;
-        connection.execute("CREATE TABLE IF NOT EXISTS plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, FOREIGN KEY(game_id) REFERENCES games(id))", &[])?;
-        connection.execute("CREATE TABLE IF NOT EXISTS clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id))", &[])?;
+        let mut connection = try!(rusqlite::Connection::open(loc.to_owned()));
+        migrations::migrate(&mut connection)?;
 
-        connection.execute("INSERT INTO games (date) VALUES (date('now'))", &[])?;
-        let current_game_num: u32 = connection.query_row("SELECT id FROM games WHERE date = date('now') ORDER BY id DESC", &[], |ref row| row.get(0)).unwrap_or(1);
-        let current_play_num: u32 = connection.query_row("SELECT id FROM plays ORDER BY id DESC", &[], |ref row| row.get(0)).map(|x: u32| x + 1).unwrap_or(1);
+        // pick up where we left off instead of starting a new game every boot
+        let current_game_num: Option<i64> = connection.query_row("SELECT id FROM games WHERE closed = 0 ORDER BY id DESC", &[], |ref row| row.get(0)).ok();
 
-        
         Ok(
             DatabaseRef {
                 location: loc.to_owned(),
                 db_ref: Arc::new(Mutex::new(connection)),
-                current_play_num: Arc::new(atomic::AtomicUsize::new(current_play_num as usize)),
-                current_game_num: Arc::new(current_game_num as usize),
+                current_play_num: Arc::new(atomic::AtomicUsize::new(0)),
+                current_game_num: Arc::new(Mutex::new(current_game_num)),
                 in_transaction: Arc::new(atomic::AtomicBool::new(false)),
             }
         )
     }
 
-    pub fn start_play(&self) -> rusqlite::Result<()> {
+    /// Starts a play in the current game, creating an unnamed game for today if none is open.
+    /// Fails with `RecordingInProgress` if a play is already running; the check and the start happen
+    /// under the connection's lock, so two callers can never both start one.
+    pub fn start_play(&self) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
         if self.in_transaction.load(atomic::Ordering::SeqCst) {
-            panic!("Attempted to start a play when one was already started");
+            return Err(ServerError::new(ServerErrorKind::RecordingInProgress));
         }
 
-        let lock = self.db_ref.lock().expect("mutex is poisoned");
-        lock.execute("INSERT INTO plays (game_id) VALUES (?)", &[&(*self.current_game_num as u32)])?;
+        let game_id = match self.get_current_game_id() {
+            Some(id) => id,
+            None => self.insert_current_game(&lock, &NewGame { name: None, opponent: None })?,
+        };
+
+        lock.execute("INSERT INTO plays (game_id, started_at) VALUES (?, ?)", &[&game_id, &wall_clock_micros()])?;
+        self.current_play_num.store(lock.last_insert_rowid() as usize, atomic::Ordering::SeqCst);
         self.in_transaction.store(true, atomic::Ordering::SeqCst);
         Ok(())
     }
 
-    pub fn insert_clip(&self, uuid: &str) -> rusqlite::Result<()> {
-        if !self.in_transaction.load(atomic::Ordering::SeqCst) {
-            panic!("Attempted to add a clip when there was no play started");
+    /// Adds a clip to `play_id`, which the caller looked up when the play started, since the
+    /// play may well have ended by the time the clip is opened.
+    pub fn insert_clip(&self, play_id: i64, uuid: &str, client_id: &str) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let plays: i64 = lock.query_row("SELECT COUNT(*) FROM plays WHERE id = ?", &[&play_id], |row| row.get(0))?;
+        if plays == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
         }
+        lock.execute("INSERT INTO clips (uuid, play_id, client_id) VALUES (?, ?, ?)", &[&uuid, &play_id, &client_id])?;
+        Ok(())
+    }
 
+    /// Forgets a clip that never received anything.
+    pub fn remove_clip(&self, uuid: &str) -> Result<(), ServerError> {
         let lock = self.db_ref.lock().expect("mutex is poisoned");
-        let actual_play_id = self.current_play_num.load(atomic::Ordering::SeqCst);
-        lock.execute("INSERT INTO clips (uuid, play_id) VALUES (?, ?)", &[&uuid, &(actual_play_id as u32)])?;
+        lock.execute("DELETE FROM clips WHERE uuid = ?", &[&uuid])?;
+        Ok(())
+    }
+
+    /// Stores a complete clip of an earlier play, sent again from a client's spool, in place of
+    /// whatever that client managed to send live. Returns the uuids of the clips it replaced.
+    pub fn replace_play_clips(&self, play_id: i64, client_id: &str, uuid: &str) -> Result<Vec<String>, ServerError> {
+        let mut lock = self.db_ref.lock().expect("mutex is poisoned");
+        let plays: i64 = lock.query_row("SELECT COUNT(*) FROM plays WHERE id = ?", &[&play_id], |row| row.get(0))?;
+        if plays == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
+        let tx = lock.transaction()?;
+        let replaced = {
+            let mut stmt = tx.prepare("SELECT uuid FROM clips WHERE play_id = ? AND client_id = ?")?;
+            let uuids = stmt.query_map(&[&play_id, &client_id], |row| row.get(0))?.collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            uuids
+        };
+        tx.execute("DELETE FROM clips WHERE play_id = ? AND client_id = ?", &[&play_id, &client_id])?;
+        tx.execute("INSERT INTO clips (uuid, play_id, client_id) VALUES (?, ?, ?)", &[&uuid, &play_id, &client_id])?;
+        tx.commit()?;
+        Ok(replaced)
+    }
+
+    /// Stores a clip the server rendered from a play's angles, in place of the one rendered before.
+    /// Returns the uuids of the clips it replaced.
+    pub fn replace_derived_clip(&self, play_id: i64, uuid: &str) -> Result<Vec<String>, ServerError> {
+        let mut lock = self.db_ref.lock().expect("mutex is poisoned");
+        let tx = lock.transaction()?;
+        let replaced = {
+            let mut stmt = tx.prepare("SELECT uuid FROM clips WHERE play_id = ? AND derived = 1")?;
+            let uuids = stmt.query_map(&[&play_id], |row| row.get(0))?.collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            uuids
+        };
+        tx.execute("DELETE FROM clips WHERE play_id = ? AND derived = 1", &[&play_id])?;
+        tx.execute("INSERT INTO clips (uuid, play_id, derived) VALUES (?, ?, 1)", &[&uuid, &play_id])?;
+        tx.commit()?;
+        Ok(replaced)
+    }
+
+    /// Records when a clip's first frame was captured, in server wall-clock microseconds.
+    /// It is stored as an offset from the start of its play so angles of the same play can be lined up.
+    pub fn set_clip_start_time(&self, uuid: &str, captured_at: i64) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let changed = lock.execute("UPDATE clips SET start_offset = ? - (SELECT started_at FROM plays WHERE plays.id = clips.play_id) WHERE uuid = ?", &[&captured_at, &uuid])?;
+        if changed == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
+        Ok(())
+    }
+
+    pub fn set_clip_duration(&self, uuid: &str, duration: i64) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let changed = lock.execute("UPDATE clips SET duration = ? WHERE uuid = ?", &[&duration, &uuid])?;
+        if changed == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
+        Ok(())
+    }
 
+    /// Records the thumbnail written for a clip, as a file name in the output directory.
+    pub fn set_clip_thumbnail(&self, uuid: &str, file_name: &str) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let changed = lock.execute("UPDATE clips SET thumbnail = ? WHERE uuid = ?", &[&file_name, &uuid])?;
+        if changed == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
         Ok(())
     }
 
     pub fn end_play(&self) -> bool {
-        self.current_play_num.fetch_add(1, atomic::Ordering::SeqCst);
         self.in_transaction.swap(false, atomic::Ordering::SeqCst)
     }
 
@@ -75,4 +154,203 @@ impl DatabaseRef {
         self.in_transaction.load(atomic::Ordering::SeqCst)
     }
 
-}
\ No newline at end of file
+    pub fn current_play_id(&self) -> Option<i64> {
+        if self.currently_in_play() {
+            Some(self.current_play_num.load(atomic::Ordering::SeqCst) as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn recording_status(&self) -> RecordingStatus {
+        RecordingStatus {
+            recording: self.currently_in_play(),
+            play_id: self.current_play_id(),
+            game_id: self.get_current_game_id(),
+        }
+    }
+
+    pub fn get_current_game_id(&self) -> Option<i64> {
+        *self.current_game_num.lock().expect("mutex is poisoned")
+    }
+
+    /// The game can only change between plays. Only callable while holding the connection's lock,
+    /// which `start_play` holds while it checks for and starts a play, so the two cannot interleave.
+    fn set_current_game_id(&self, _lock: &MutexGuard<rusqlite::Connection>, id: Option<i64>) -> Result<(), ServerError> {
+        if self.currently_in_play() {
+            return Err(ServerError::new(ServerErrorKind::RecordingInProgress));
+        }
+        *self.current_game_num.lock().expect("mutex is poisoned") = id;
+        Ok(())
+    }
+
+    /// Inserts a game dated today and makes it the current game. Checks for a play first, so no game
+    /// is left behind when it cannot be; none can start while the lock is held.
+    fn insert_current_game(&self, lock: &MutexGuard<rusqlite::Connection>, new_game: &NewGame) -> Result<i64, ServerError> {
+        if self.currently_in_play() {
+            return Err(ServerError::new(ServerErrorKind::RecordingInProgress));
+        }
+        lock.execute("INSERT INTO games (date, name, opponent) VALUES (date('now'), ?, ?)", &[&new_game.name, &new_game.opponent])?;
+        let id = lock.last_insert_rowid();
+        self.set_current_game_id(lock, Some(id))?;
+        Ok(id)
+    }
+
+    /// Creates a game dated today and makes it the game new plays are recorded into.
+    pub fn create_game(&self, new_game: NewGame) -> Result<Game, ServerError> {
+        let id = {
+            let lock = self.db_ref.lock().expect("mutex is poisoned");
+            self.insert_current_game(&lock, &new_game)?
+        };
+        self.get_game(id)
+    }
+
+    /// Reopens an existing game and records new plays into it.
+    pub fn resume_game(&self, id: i64) -> Result<Game, ServerError> {
+        self.get_game(id)?;
+        {
+            let lock = self.db_ref.lock().expect("mutex is poisoned");
+            // reopened first, so a failed update never leaves a closed game as the current one
+            lock.execute("UPDATE games SET closed = 0 WHERE id = ?", &[&id])?;
+            self.set_current_game_id(&lock, Some(id))?;
+        }
+        self.get_game(id)
+    }
+
+    pub fn close_game(&self, id: i64) -> Result<Game, ServerError> {
+        self.get_game(id)?;
+        {
+            let lock = self.db_ref.lock().expect("mutex is poisoned");
+            if self.get_current_game_id() == Some(id) {
+                self.set_current_game_id(&lock, None)?;
+            }
+            lock.execute("UPDATE games SET closed = 1 WHERE id = ?", &[&id])?;
+        }
+        self.get_game(id)
+    }
+
+    pub fn get_game(&self, id: i64) -> Result<Game, ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let game = lock.query_row("SELECT id, date, name, opponent, closed FROM games WHERE id = ?", &[&id], game_from_row);
+        match game {
+            Ok(game) => Ok(game),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(ServerError::new(ServerErrorKind::NotFound)),
+            Err(e) => Err(ServerError::from(e)),
+        }
+    }
+
+    pub fn list_games(&self) -> Result<Vec<Game>, ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let mut stmt = lock.prepare("SELECT id, date, name, opponent, closed FROM games ORDER BY id ASC")?;
+        let games = stmt.query_map(&[], game_from_row)?.collect::<Result<Vec<Game>, rusqlite::Error>>()?;
+        Ok(games)
+    }
+
+    /// Lists every play of a game along with the clips recorded for it.
+    pub fn list_plays(&self, game_id: i64) -> Result<Vec<Play>, ServerError> {
+        self.get_game(game_id)?;
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let mut play_stmt = lock.prepare("SELECT id, game_id, down, distance, quarter, notes FROM plays WHERE game_id = ? ORDER BY id ASC")?;
+        let mut plays = play_stmt.query_map(&[&game_id], play_from_row)?.collect::<Result<Vec<Play>, rusqlite::Error>>()?;
+        for (position, play) in plays.iter_mut().enumerate() {
+            play.number = position as i64 + 1;
+        }
+
+        let mut clip_stmt = lock.prepare("SELECT id, uuid, start_offset, thumbnail, duration, derived FROM clips WHERE play_id = ? ORDER BY id ASC")?;
+        for play in plays.iter_mut() {
+            play.clips = clip_stmt.query_map(&[&play.id], clip_from_row)?.collect::<Result<Vec<Clip>, rusqlite::Error>>()?;
+        }
+        Ok(plays)
+    }
+
+    /// Every game, newest first, with its plays and their clips, for browsing the whole library at once.
+    pub fn library(&self) -> Result<Vec<GamePlays>, ServerError> {
+        let mut games = self.list_games()?;
+        games.reverse();
+        let mut library = Vec::new();
+        for game in games {
+            let plays = self.list_plays(game.id)?;
+            library.push(GamePlays { game: game, plays: plays });
+        }
+        Ok(library)
+    }
+
+    /// Every clip one camera recorded during a game, in play order.
+    pub fn game_clips(&self, game_id: i64, client_id: &str) -> Result<Vec<ExportClip>, ServerError> {
+        self.get_game(game_id)?;
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let mut stmt = lock.prepare("SELECT clips.uuid, clips.play_id, clips.client_id, clips.start_offset FROM clips JOIN plays ON plays.id = clips.play_id WHERE plays.game_id = ? AND clips.client_id = ? ORDER BY plays.id ASC, clips.id ASC")?;
+        let clips = stmt.query_map(&[&game_id, &client_id], export_clip_from_row)?.collect::<Result<Vec<ExportClip>, rusqlite::Error>>()?;
+        Ok(clips)
+    }
+
+    /// Every angle of a play, in the order the cameras' clips were stored. Clips the server derived
+    /// from them are left out.
+    pub fn play_clips(&self, play_id: i64) -> Result<Vec<ExportClip>, ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let plays: i64 = lock.query_row("SELECT COUNT(*) FROM plays WHERE id = ?", &[&play_id], |row| row.get(0))?;
+        if plays == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
+        let mut stmt = lock.prepare("SELECT uuid, play_id, client_id, start_offset FROM clips WHERE play_id = ? AND derived = 0 ORDER BY id ASC")?;
+        let clips = stmt.query_map(&[&play_id], export_clip_from_row)?.collect::<Result<Vec<ExportClip>, rusqlite::Error>>()?;
+        Ok(clips)
+    }
+
+    pub fn set_play_metadata(&self, play_id: i64, metadata: &PlayMetadata) -> Result<(), ServerError> {
+        let lock = self.db_ref.lock().expect("mutex is poisoned");
+        let changed = lock.execute("UPDATE plays SET down = ?, distance = ?, quarter = ?, notes = ? WHERE id = ?", &[&metadata.down, &metadata.distance, &metadata.quarter, &metadata.notes, &play_id])?;
+        if changed == 0 {
+            return Err(ServerError::new(ServerErrorKind::NotFound));
+        }
+        Ok(())
+    }
+
+}
+
+fn game_from_row(row: &rusqlite::Row) -> Game {
+    Game {
+        id: row.get(0),
+        date: row.get(1),
+        name: row.get(2),
+        opponent: row.get(3),
+        closed: row.get::<i32, i32>(4) != 0,
+    }
+}
+
+fn play_from_row(row: &rusqlite::Row) -> Play {
+    Play {
+        id: row.get(0),
+        game_id: row.get(1),
+        number: 0,
+        metadata: PlayMetadata {
+            down: row.get(2),
+            distance: row.get(3),
+            quarter: row.get(4),
+            notes: row.get(5),
+        },
+        clips: Vec::new(),
+    }
+}
+
+fn clip_from_row(row: &rusqlite::Row) -> Clip {
+    let uuid: String = row.get(1);
+    let thumbnail = row.get::<i32, Option<String>>(3).map(|_| format!("/thumbnails/{}", uuid));
+    Clip {
+        id: row.get(0),
+        uuid: uuid,
+        start_offset: row.get(2),
+        thumbnail: thumbnail,
+        duration: row.get(4),
+        derived: row.get::<i32, i32>(5) != 0,
+    }
+}
+
+fn export_clip_from_row(row: &rusqlite::Row) -> ExportClip {
+    ExportClip {
+        uuid: row.get(0),
+        play_id: row.get(1),
+        client_id: row.get(2),
+        start_offset: row.get(3),
+    }
+}
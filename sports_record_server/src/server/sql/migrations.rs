@@ -0,0 +1,290 @@
+use rusqlite;
+
+use server::{ServerError, ServerErrorKind};
+
+type Migration = fn(&rusqlite::Connection) -> rusqlite::Result<()>;
+
+/// Migration `n` upgrades a database from `user_version` n to n + 1.
+/// Only ever append to this list; released databases depend on the existing entries.
+const MIGRATIONS: &'static [Migration] = &[
+    initial_schema,
+    game_and_play_details,
+    clip_sync_offsets,
+    clip_clients,
+    clip_thumbnails,
+    clip_durations,
+    derived_clips,
+];
+
+/// The schema version a fully migrated database reports through `PRAGMA user_version`.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.len() as i32
+}
+
+pub fn schema_version(connection: &rusqlite::Connection) -> rusqlite::Result<i32> {
+    connection.query_row("PRAGMA user_version", &[], |row| row.get(0))
+}
+
+/// Turns on foreign key enforcement and upgrades the database to the latest schema in place.
+/// Each migration runs in its own transaction, so a failure leaves the database at the last good version.
+pub fn migrate(connection: &mut rusqlite::Connection) -> Result<i32, ServerError> {
+    enable_foreign_keys(connection)?;
+
+    let mut version = schema_version(connection)?;
+    if version < 0 || version > latest_version() {
+        return Err(ServerError::new(ServerErrorKind::UnsupportedSchemaVersion(version)));
+    }
+
+    while version < latest_version() {
+        let tx = connection.transaction()?;
+        MIGRATIONS[version as usize](&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", version + 1))?;
+        tx.commit()?;
+        info!("Migrated database schema from version {} to {}", version, version + 1);
+        version += 1;
+    }
+
+    Ok(version)
+}
+
+fn enable_foreign_keys(connection: &rusqlite::Connection) -> Result<(), ServerError> {
+    // has no effect inside a transaction, and sqlite ignores unknown pragmas, so check it actually took
+    connection.execute_batch("PRAGMA foreign_keys = ON")?;
+    let enabled: i32 = connection.query_row("PRAGMA foreign_keys", &[], |row| row.get(0))?;
+    if enabled != 1 {
+        return Err(ServerError::new(ServerErrorKind::ForeignKeysUnavailable));
+    }
+    Ok(())
+}
+
+/// Version 1: the original tables. Databases from before versioning already have these at `user_version` 0.
+fn initial_schema(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        CREATE TABLE IF NOT EXISTS games (id INTEGER PRIMARY KEY ASC, date TEXT);
+        CREATE TABLE IF NOT EXISTS plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+        CREATE TABLE IF NOT EXISTS clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+    ")
+}
+
+/// Version 2: game names, opponents and closing, plus per-play down and distance metadata.
+fn game_and_play_details(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column_if_missing(connection, "games", "name", "TEXT")?;
+    add_column_if_missing(connection, "games", "opponent", "TEXT")?;
+    add_column_if_missing(connection, "games", "closed", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(connection, "plays", "down", "INTEGER")?;
+    add_column_if_missing(connection, "plays", "distance", "INTEGER")?;
+    add_column_if_missing(connection, "plays", "quarter", "INTEGER")?;
+    add_column_if_missing(connection, "plays", "notes", "TEXT")?;
+    // every boot used to insert a game, most of which never got a play
+    connection.execute("UPDATE games SET closed = 1 WHERE id NOT IN (SELECT MAX(id) FROM games)", &[])?;
+    Ok(())
+}
+
+/// Version 3: when each play started and where each clip starts within it, so angles can be played in sync.
+/// Both are wall-clock microseconds on the server; clips recorded before this stay NULL.
+fn clip_sync_offsets(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        ALTER TABLE plays ADD COLUMN started_at INTEGER;
+        ALTER TABLE clips ADD COLUMN start_offset INTEGER;
+    ")
+}
+
+/// Version 4: which client recorded each clip, so a replayed play can replace that client's partial clips.
+fn clip_clients(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        ALTER TABLE clips ADD COLUMN client_id TEXT;
+    ")
+}
+
+/// Version 5: the thumbnail written next to each clip, as a file name in the output directory.
+/// Clips recorded before this, or whose thumbnail could not be decoded, stay NULL.
+fn clip_thumbnails(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        ALTER TABLE clips ADD COLUMN thumbnail TEXT;
+    ")
+}
+
+/// Version 6: how long each clip runs in microseconds, so the control panel can list it without opening the file.
+fn clip_durations(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        ALTER TABLE clips ADD COLUMN duration INTEGER;
+    ")
+}
+
+/// Version 7: marks clips the server rendered itself from a play's other clips, like the multi-angle
+/// composite, so they are listed with the play but never taken for a camera's angle.
+fn derived_clips(connection: &rusqlite::Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("
+        ALTER TABLE clips ADD COLUMN derived INTEGER NOT NULL DEFAULT 0;
+    ")
+}
+
+fn add_column_if_missing(connection: &rusqlite::Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<()> {
+    let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt.query_map(&[], |row| row.get::<i32, String>(1))?.collect::<Result<Vec<String>, rusqlite::Error>>()?;
+    if !columns.iter().any(|x| x == column) {
+        connection.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), &[])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    const SCHEMA_V0: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v0.sql"));
+    const SCHEMA_V1: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v1.sql"));
+    const SCHEMA_V2: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v2.sql"));
+    const SCHEMA_V3: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v3.sql"));
+    const SCHEMA_V4: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v4.sql"));
+    const SCHEMA_V5: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v5.sql"));
+    const SCHEMA_V6: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v6.sql"));
+    const SCHEMA_V7: &'static str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/schema_v7.sql"));
+
+    /// A database as written at every schema version, and that version.
+    const FIXTURES: &'static [(&'static str, i32)] = &[
+        (SCHEMA_V0, 0),
+        (SCHEMA_V1, 1),
+        (SCHEMA_V2, 2),
+        (SCHEMA_V3, 3),
+        (SCHEMA_V4, 4),
+        (SCHEMA_V5, 5),
+        (SCHEMA_V6, 6),
+        (SCHEMA_V7, 7),
+    ];
+
+    fn fixture(sql: &str) -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(sql).unwrap();
+        connection
+    }
+
+    fn columns(connection: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = connection.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        let names = stmt.query_map(&[], |row| row.get::<i32, String>(1)).unwrap();
+        names.map(|x| x.unwrap()).collect()
+    }
+
+    fn count(connection: &Connection, table: &str) -> i64 {
+        connection.query_row(&format!("SELECT COUNT(*) FROM {}", table), &[], |row| row.get(0)).unwrap()
+    }
+
+    /// Every row of `table`, each as the quoted values of `columns`.
+    fn rows(connection: &Connection, table: &str, columns: &[String]) -> Vec<String> {
+        let values = columns.iter().map(|x| format!("quote({})", x)).collect::<Vec<String>>().join(" || ',' || ");
+        let mut stmt = connection.prepare(&format!("SELECT {} FROM {} ORDER BY id", values, table)).unwrap();
+        let rows = stmt.query_map(&[], |row| row.get(0)).unwrap();
+        rows.map(|x| x.unwrap()).collect()
+    }
+
+    fn assert_latest_schema(connection: &Connection) {
+        assert_eq!(schema_version(connection).unwrap(), latest_version());
+        assert_eq!(columns(connection, "games"), vec!["id", "date", "name", "opponent", "closed"]);
+        assert_eq!(columns(connection, "plays"), vec!["id", "game_id", "down", "distance", "quarter", "notes", "started_at"]);
+        assert_eq!(columns(connection, "clips"), vec!["id", "uuid", "play_id", "start_offset", "client_id", "thumbnail", "duration", "derived"]);
+    }
+
+    #[test]
+    fn creates_fresh_database() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut connection).unwrap(), latest_version());
+        assert_latest_schema(&connection);
+    }
+
+    #[test]
+    fn upgrades_unversioned_database() {
+        let mut connection = fixture(SCHEMA_V0);
+        migrate(&mut connection).unwrap();
+        assert_latest_schema(&connection);
+        assert_eq!(count(&connection, "plays"), 2);
+        assert_eq!(count(&connection, "clips"), 3);
+
+        let open_games: Vec<i64> = {
+            let mut stmt = connection.prepare("SELECT id FROM games WHERE closed = 0").unwrap();
+            let ids = stmt.query_map(&[], |row| row.get(0)).unwrap();
+            ids.map(|x| x.unwrap()).collect()
+        };
+        assert_eq!(open_games, vec![2]);
+    }
+
+    #[test]
+    fn upgrades_version_one_database() {
+        let mut connection = fixture(SCHEMA_V1);
+        migrate(&mut connection).unwrap();
+        assert_latest_schema(&connection);
+        let uuid: String = connection.query_row("SELECT uuid FROM clips WHERE play_id = 1", &[], |row| row.get(0)).unwrap();
+        assert_eq!(uuid, "0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d");
+    }
+
+    #[test]
+    fn migrates_every_fixture_with_its_data() {
+        for &(sql, version) in FIXTURES {
+            let mut connection = fixture(sql);
+            assert_eq!(schema_version(&connection).unwrap(), version);
+            let before: Vec<(&str, Vec<String>, Vec<String>)> = ["games", "plays", "clips"].iter().map(|&table| {
+                let columns = columns(&connection, table);
+                let rows = rows(&connection, table, &columns);
+                assert!(!rows.is_empty(), "the version {} fixture has no {}", version, table);
+                (table, columns, rows)
+            }).collect();
+
+            migrate(&mut connection).unwrap();
+            assert_latest_schema(&connection);
+            for (table, columns, expected) in before {
+                assert_eq!(rows(&connection, table, &columns), expected, "{} of the version {} fixture", table, version);
+            }
+        }
+    }
+
+    #[test]
+    fn upgrades_version_two_database() {
+        let mut connection = fixture(SCHEMA_V2);
+        migrate(&mut connection).unwrap();
+        assert_latest_schema(&connection);
+        let (notes, closed): (String, i32) = connection.query_row("SELECT notes, closed FROM plays JOIN games ON games.id = plays.game_id", &[], |row| (row.get(0), row.get(1))).unwrap();
+        assert_eq!(notes, "Screen left");
+        assert_eq!(closed, 1);
+    }
+
+    #[test]
+    fn records_clips_as_camera_angles_by_default() {
+        let mut connection = fixture(SCHEMA_V6);
+        migrate(&mut connection).unwrap();
+        // cameras insert their clips without saying whether they are derived
+        connection.execute("INSERT INTO clips (uuid, play_id) VALUES ('recorded', 1)", &[]).unwrap();
+        let derived: i32 = connection.query_row("SELECT derived FROM clips WHERE uuid = 'recorded'", &[], |row| row.get(0)).unwrap();
+        assert_eq!(derived, 0);
+        assert!(connection.execute("INSERT INTO clips (uuid, play_id, derived) VALUES ('unknown', 1, NULL)", &[]).is_err());
+    }
+
+    #[test]
+    fn migrating_twice_is_a_no_op() {
+        let mut connection = fixture(SCHEMA_V0);
+        migrate(&mut connection).unwrap();
+        assert_eq!(migrate(&mut connection).unwrap(), latest_version());
+        assert_latest_schema(&connection);
+    }
+
+    #[test]
+    fn enforces_foreign_keys() {
+        let mut connection = fixture(SCHEMA_V1);
+        migrate(&mut connection).unwrap();
+        assert!(connection.execute("INSERT INTO plays (game_id) VALUES (99)", &[]).is_err());
+        assert!(connection.execute("INSERT INTO clips (uuid, play_id) VALUES ('orphan', 99)", &[]).is_err());
+        assert!(connection.execute("INSERT INTO clips (uuid, play_id) VALUES ('ok', 1)", &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_newer_database() {
+        let mut connection = fixture(SCHEMA_V2);
+        connection.execute_batch(&format!("PRAGMA user_version = {}", latest_version() + 1)).unwrap();
+        match migrate(&mut connection) {
+            Err(ref e) => match *e.kind() {
+                ServerErrorKind::UnsupportedSchemaVersion(v) => assert_eq!(v, latest_version() + 1),
+                _ => panic!("unexpected error: {}", e),
+            },
+            Ok(_) => panic!("migrated a database from a newer server"),
+        }
+    }
+}
@@ -1,3 +1,6 @@
 mod database;
+mod migrations;
+mod models;
 
-pub use self::database::*;
\ No newline at end of file
+pub use self::database::*;
+pub use self::models::*;
\ No newline at end of file
@@ -0,0 +1,70 @@
+#[derive(Debug, Clone, Serialize)]
+pub struct Game {
+    pub id: i64,
+    pub date: String,
+    pub name: Option<String>,
+    pub opponent: Option<String>,
+    pub closed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct PlayMetadata {
+    pub down: Option<i32>,
+    pub distance: Option<i32>,
+    pub quarter: Option<i32>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Play {
+    pub id: i64,
+    pub game_id: i64,
+    /// Where the play falls in its game, counting from 1.
+    pub number: i64,
+    pub metadata: PlayMetadata,
+    pub clips: Vec<Clip>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Clip {
+    pub id: i64,
+    pub uuid: String,
+    /// Microseconds from the start of the play to this clip's first frame, once known.
+    pub start_offset: Option<i64>,
+    /// Where the clip's thumbnail is served, once one was written.
+    pub thumbnail: Option<String>,
+    /// Microseconds of video in the clip, once it is finished.
+    pub duration: Option<i64>,
+    /// Rendered by the server from the play's other clips rather than recorded by a camera.
+    pub derived: bool,
+}
+
+/// A clip as an export job needs it: which file, and what it belongs to.
+#[derive(Debug, Clone)]
+pub struct ExportClip {
+    pub uuid: String,
+    pub play_id: i64,
+    pub client_id: Option<String>,
+    /// Microseconds from the start of the play to the clip's first frame, once known.
+    pub start_offset: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GamePlays {
+    pub game: Game,
+    pub plays: Vec<Play>,
+}
+
+/// Whether a play is being recorded right now, and where new plays go.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStatus {
+    pub recording: bool,
+    pub play_id: Option<i64>,
+    pub game_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewGame {
+    pub name: Option<String>,
+    pub opponent: Option<String>,
+}
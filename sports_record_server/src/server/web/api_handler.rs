@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use server::client_handling::{ClientStream, WeakClientStream, ClientCommandResult};
+use server::{ServerError, ServerErrorKind};
+use server::sql::{NewGame, PlayMetadata};
+use server::export::{ExportJobs, ExportRequest};
+
+use serde::Serialize;
+use serde_json;
+
+use ffmpeg_common::networking::secrets_match;
+
+use iron::prelude::*;
+use iron::headers::{ContentType, Authorization, Bearer};
+use iron::middleware::BeforeMiddleware;
+use iron::status;
+use router::Router;
+
+/// Rejects any API request that does not carry `Authorization: Bearer <control panel key>`.
+pub struct ApiKeyCheck {
+    key: String,
+}
+
+impl ApiKeyCheck {
+    pub fn new(key: &str) -> ApiKeyCheck {
+        ApiKeyCheck { key: key.to_owned() }
+    }
+}
+
+impl BeforeMiddleware for ApiKeyCheck {
+    fn before(&self, req: &mut Request) -> IronResult<()> {
+        let authorized = match req.headers.get::<Authorization<Bearer>>() {
+            Some(&Authorization(Bearer { ref token })) => secrets_match(self.key.as_bytes(), token.as_bytes()),
+            None => false,
+        };
+
+        if authorized {
+            Ok(())
+        } else {
+            warn!("Rejected unauthenticated API request from {}", req.remote_addr);
+            Err(IronError::new(ApiAuthError, (status::Unauthorized, json_body(&ApiResponse::<()>::error("missing or invalid control panel key")))))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ApiAuthError;
+
+impl fmt::Display for ApiAuthError {
+    fn fmt(&self, fmter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmter, "The request did not carry a valid control panel key")
+    }
+}
+
+impl Error for ApiAuthError {
+    fn description(&self) -> &str {
+        "ApiAuthError"
+    }
+}
+
+#[derive(Debug)]
+struct ShuttingDownError;
+
+impl fmt::Display for ShuttingDownError {
+    fn fmt(&self, fmter: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmter, "The client stream has already been shut down")
+    }
+}
+
+impl Error for ShuttingDownError {
+    fn description(&self) -> &str {
+        "ShuttingDownError"
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T: Serialize> {
+    pub success: bool,
+    pub error: Option<String>,
+    pub result: Option<T>,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn ok(item: T) -> ApiResponse<T> {
+        ApiResponse { success: true, error: None, result: Some(item) }
+    }
+
+    pub fn error(msg: &str) -> ApiResponse<T> {
+        ApiResponse { success: false, error: Some(msg.to_owned()), result: None }
+    }
+}
+
+fn json_body<T: Serialize>(item: &T) -> String {
+    serde_json::to_string(item).unwrap_or_else(|_| String::from("{\"success\":false}"))
+}
+
+pub fn json_response<T: Serialize>(code: status::Status, item: &T) -> Response {
+    let mut res = Response::with((code, json_body(item)));
+    res.headers.set(ContentType::json());
+    res
+}
+
+fn server_error_response(err: ServerError) -> Response {
+    let code = match *err.kind() {
+        ServerErrorKind::RecordingInProgress | ServerErrorKind::NoRecordingInProgress => status::Conflict,
+        ServerErrorKind::NotFound | ServerErrorKind::NothingToExport => status::NotFound,
+        _ => status::InternalServerError,
+    };
+    json_response(code, &ApiResponse::<()>::error(&err.to_string()))
+}
+
+fn command_response(result: Result<Vec<ClientCommandResult>, ServerError>) -> Response {
+    match result {
+        Ok(results) => json_response(status::Ok, &ApiResponse::ok(results)),
+        Err(e) => server_error_response(e),
+    }
+}
+
+fn result_response<T: Serialize>(result: Result<T, ServerError>) -> Response {
+    match result {
+        Ok(item) => json_response(status::Ok, &ApiResponse::ok(item)),
+        Err(e) => server_error_response(e),
+    }
+}
+
+fn id_param(req: &Request) -> Option<i64> {
+    req.extensions.get::<Router>().and_then(|q| q.find("id")).and_then(|x| x.parse().ok())
+}
+
+fn bad_id_response() -> Response {
+    json_response(status::BadRequest, &ApiResponse::<()>::error("expected a numeric id"))
+}
+
+/// The router only holds a weak handle, so API requests that arrive while the server shuts down get a 503.
+fn client_stream(req: &Request) -> IronResult<ClientStream> {
+    let weak = req.extensions.get::<WeakClientStream>().expect("failed to get client stream");
+    weak.upgrade().ok_or_else(|| IronError::new(ShuttingDownError, (status::ServiceUnavailable, json_body(&ApiResponse::<()>::error("the server is shutting down")))))
+}
+
+pub fn start_recording_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(command_response(client_stream(req)?.start_recording()))
+}
+
+pub fn stop_recording_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(command_response(client_stream(req)?.stop_recording()))
+}
+
+pub fn recording_status_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(json_response(status::Ok, &ApiResponse::ok(client_stream(req)?.get_database().recording_status())))
+}
+
+pub fn client_states_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(json_response(status::Ok, &ApiResponse::ok(client_stream(req)?.get_client_states())))
+}
+
+pub fn remove_client_handler(req: &mut Request) -> IronResult<Response> {
+    let addr = req.extensions.get::<Router>().and_then(|q| q.find("addr")).map(|x| SocketAddr::from_str(x));
+    match addr {
+        Some(Ok(addr)) => {
+            match client_stream(req)?.remove_client(addr) {
+                Some(result) => Ok(json_response(status::Ok, &ApiResponse::ok(result))),
+                None => Ok(json_response(status::NotFound, &ApiResponse::<()>::error("no client with that address is connected"))),
+            }
+        },
+        _ => Ok(json_response(status::BadRequest, &ApiResponse::<()>::error("expected a client address such as 10.0.0.5:41234"))),
+    }
+}
+
+pub fn list_games_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(result_response(client_stream(req)?.get_database().list_games()))
+}
+
+pub fn create_game_handler(req: &mut Request) -> IronResult<Response> {
+    let new_game: NewGame = match serde_json::from_reader(&mut req.body) {
+        Ok(item) => item,
+        Err(_) => return Ok(json_response(status::BadRequest, &ApiResponse::<()>::error("expected a JSON body such as {\"name\": \"Week 3\", \"opponent\": \"Central\"}"))),
+    };
+    Ok(result_response(client_stream(req)?.get_database().create_game(new_game)))
+}
+
+pub fn resume_game_handler(req: &mut Request) -> IronResult<Response> {
+    match id_param(req) {
+        Some(id) => Ok(result_response(client_stream(req)?.get_database().resume_game(id))),
+        None => Ok(bad_id_response()),
+    }
+}
+
+pub fn close_game_handler(req: &mut Request) -> IronResult<Response> {
+    match id_param(req) {
+        Some(id) => Ok(result_response(client_stream(req)?.get_database().close_game(id))),
+        None => Ok(bad_id_response()),
+    }
+}
+
+pub fn library_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(result_response(client_stream(req)?.get_database().library()))
+}
+
+pub fn list_plays_handler(req: &mut Request) -> IronResult<Response> {
+    match id_param(req) {
+        Some(id) => Ok(result_response(client_stream(req)?.get_database().list_plays(id))),
+        None => Ok(bad_id_response()),
+    }
+}
+
+pub fn play_metadata_handler(req: &mut Request) -> IronResult<Response> {
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(bad_id_response()),
+    };
+    let metadata: PlayMetadata = match serde_json::from_reader(&mut req.body) {
+        Ok(item) => item,
+        Err(_) => return Ok(json_response(status::BadRequest, &ApiResponse::<()>::error("expected a JSON body with down, distance, quarter and notes"))),
+    };
+    Ok(result_response(client_stream(req)?.get_database().set_play_metadata(id, &metadata).map(|_| metadata)))
+}
+
+fn export_jobs(req: &Request) -> ExportJobs {
+    req.extensions.get::<ExportJobs>().expect("failed to get export jobs").clone()
+}
+
+pub fn start_export_handler(req: &mut Request) -> IronResult<Response> {
+    let request: ExportRequest = match serde_json::from_reader(&mut req.body) {
+        Ok(item) => item,
+        Err(_) => return Ok(json_response(status::BadRequest, &ApiResponse::<()>::error("expected a JSON body such as {\"kind\": \"game\", \"game_id\": 3, \"client_id\": \"end-zone\"}, {\"kind\": \"play\", \"play_id\": 12} or {\"kind\": \"composite\", \"play_id\": 12}"))),
+    };
+    match export_jobs(req).start(request) {
+        Ok(job) => Ok(json_response(status::Accepted, &ApiResponse::ok(job))),
+        Err(e) => Ok(server_error_response(e)),
+    }
+}
+
+pub fn list_exports_handler(req: &mut Request) -> IronResult<Response> {
+    Ok(json_response(status::Ok, &ApiResponse::ok(export_jobs(req).list())))
+}
+
+pub fn export_status_handler(req: &mut Request) -> IronResult<Response> {
+    let id = match id_param(req) {
+        Some(id) => id,
+        None => return Ok(bad_id_response()),
+    };
+    match export_jobs(req).get(id as usize) {
+        Some(job) => Ok(json_response(status::Ok, &ApiResponse::ok(job))),
+        None => Ok(json_response(status::NotFound, &ApiResponse::<()>::error("no export job with that id"))),
+    }
+}
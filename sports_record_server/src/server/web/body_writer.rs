@@ -1,25 +1,31 @@
-use std::io::{ Write, Error, Read };
+use std::io::{ self, Write, Error, Read, Seek, SeekFrom, BufReader };
 use std::fs::{ File };
 use std::fs::read_dir;
 use std::path::{ Path, PathBuf };
 use std::net::SocketAddr;
 
 use server::client_handling::*;
+use server::export::EXPORT_DIRECTORY;
 
 use serde_json;
+use uuid::Uuid;
 
 use iron::response::*;
 use iron::modifier;
+use iron::typemap;
 
+/// Streams `length` bytes of a clip starting at `start`, so a single byte range can be served.
 #[derive(Debug)]
 pub struct VideoPageHttpWriter {
-    video_loc: PathBuf,
+    video_file: File,
+    start: u64,
+    length: u64,
 }
 
 impl VideoPageHttpWriter {
 
-    pub fn new(file_uuid: &str) -> VideoPageHttpWriter {
-        VideoPageHttpWriter { video_loc: Path::new("./output/").join(file_uuid) }
+    pub fn new(video_file: File, start: u64, length: u64) -> VideoPageHttpWriter {
+        VideoPageHttpWriter { video_file: video_file, start: start, length: length }
     }
 
 }
@@ -36,14 +42,67 @@ impl modifier::Modifier<Response> for VideoPageHttpWriter {
 impl WriteBody for VideoPageHttpWriter {
 
     fn write_body(&mut self, res: &mut Write) -> Result<(), Error> {
-        for byte in try!(File::open(&self.video_loc)).bytes() {
-            let _ = res.write(&[try!(byte)]);
-        }
+        self.video_file.seek(SeekFrom::Start(self.start))?;
+        let mut reader = BufReader::with_capacity(VIDEO_BUFFER_SIZE, (&self.video_file).take(self.length));
+        io::copy(&mut reader, res)?;
         Ok(())
     }
 
 }
 
+const VIDEO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The configured output directory, handed to the video handler through the request extensions.
+pub struct ClipDirectory;
+
+impl typemap::Key for ClipDirectory {
+    type Value = PathBuf;
+}
+
+/// Maps a `/videos/:query` parameter onto a clip file inside `clip_dir`.
+/// Accepts either the bare uuid or the `video_<uuid>.mp4` file name; anything else is rejected
+/// so a request cannot name a file outside the clip directory.
+pub fn clip_path(clip_dir: &Path, query: &str) -> Option<PathBuf> {
+    let query = query.trim_left_matches("video_").trim_right_matches(".mp4");
+    match Uuid::parse_str(query) {
+        Ok(uuid) => Some(clip_dir.join(format!("video_{}.mp4", uuid.simple()))),
+        Err(_) => None,
+    }
+}
+
+/// Maps a `/thumbnails/:query` parameter onto a thumbnail inside `clip_dir`, accepting the bare
+/// uuid or the `thumb_<uuid>.png` file name the same way `clip_path` does.
+pub fn thumbnail_path(clip_dir: &Path, query: &str) -> Option<PathBuf> {
+    let query = query.trim_left_matches("thumb_").trim_right_matches(".png");
+    match Uuid::parse_str(query) {
+        Ok(uuid) => Some(clip_dir.join(thumbnail_file_name(&uuid.simple().to_string()))),
+        Err(_) => None,
+    }
+}
+
+/// Maps an `/exports/:query` parameter onto a finished export inside `clip_dir`.
+pub fn export_path(clip_dir: &Path, query: &str) -> Option<PathBuf> {
+    match Uuid::parse_str(query) {
+        Ok(uuid) => Some(clip_dir.join(EXPORT_DIRECTORY).join(format!("export_{}.mp4", uuid.simple()))),
+        Err(_) => None,
+    }
+}
+
+/// Maps `/live/:feed/:file` onto a live feed file inside `clip_dir`.
+/// Only feed directory names and the playlist or segment names the hls muxer writes are accepted.
+pub fn live_path(clip_dir: &Path, feed: &str, file: &str) -> Option<PathBuf> {
+    let valid_feed = !feed.is_empty() && feed.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let valid_file = file == LIVE_PLAYLIST || {
+        let number = file.trim_left_matches("segment").trim_right_matches(".ts");
+        file.starts_with("segment") && file.ends_with(".ts") && !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+    };
+    if valid_feed && valid_file {
+        Some(clip_dir.join(LIVE_DIRECTORY).join(feed).join(file))
+    } else {
+        None
+    }
+}
+
 pub struct JsonOutputWriter {
     client_stream: WeakClientStream
 }
@@ -76,4 +135,44 @@ impl WriteBody for JsonOutputWriter {
         serde_json::to_writer(res, &url_vec)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &'static str = "936da01f-9abd-4d9d-80c7-02af85c822a8";
+    const SIMPLE_UUID: &'static str = "936da01f9abd4d9d80c702af85c822a8";
+
+    #[test]
+    fn finds_a_clip_by_uuid_or_file_name() {
+        let expected = Path::new("clips").join(format!("video_{}.mp4", SIMPLE_UUID));
+        assert_eq!(clip_path(Path::new("clips"), UUID), Some(expected.clone()));
+        assert_eq!(clip_path(Path::new("clips"), SIMPLE_UUID), Some(expected.clone()));
+        assert_eq!(clip_path(Path::new("clips"), &format!("video_{}.mp4", SIMPLE_UUID)), Some(expected));
+    }
+
+    #[test]
+    fn rejects_clip_names_outside_the_clip_directory() {
+        assert_eq!(clip_path(Path::new("clips"), "../x"), None);
+        assert_eq!(clip_path(Path::new("clips"), &format!("../video_{}.mp4", SIMPLE_UUID)), None);
+        assert_eq!(clip_path(Path::new("clips"), "video_clip.mp4"), None);
+        assert_eq!(clip_path(Path::new("clips"), ""), None);
+    }
+
+    #[test]
+    fn finds_live_playlists_and_segments() {
+        let feed = Path::new("clips").join(LIVE_DIRECTORY).join("camera-1_a");
+        assert_eq!(live_path(Path::new("clips"), "camera-1_a", LIVE_PLAYLIST), Some(feed.join(LIVE_PLAYLIST)));
+        assert_eq!(live_path(Path::new("clips"), "camera-1_a", "segment12.ts"), Some(feed.join("segment12.ts")));
+    }
+
+    #[test]
+    fn rejects_live_names_outside_the_feed() {
+        assert_eq!(live_path(Path::new("clips"), "", LIVE_PLAYLIST), None);
+        assert_eq!(live_path(Path::new("clips"), "..", LIVE_PLAYLIST), None);
+        assert_eq!(live_path(Path::new("clips"), "a/b", LIVE_PLAYLIST), None);
+        assert_eq!(live_path(Path::new("clips"), "camera", "segment.ts"), None);
+        assert_eq!(live_path(Path::new("clips"), "camera", "segment../1.ts"), None);
+        assert_eq!(live_path(Path::new("clips"), "camera", "../index.m3u8"), None);
+    }
+}
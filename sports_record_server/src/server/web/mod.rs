@@ -1,2 +1,3 @@
 pub mod body_writer;
-pub mod web_handler;
\ No newline at end of file
+pub mod web_handler;
+pub mod api_handler;
\ No newline at end of file
@@ -1,46 +1,196 @@
 
+use std::cmp;
+use std::fs::File;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
 use server::web::body_writer;
 use server::client_handling::WeakClientStream;
 
 use iron::prelude::*;
-use iron::headers::ContentType;
+use iron::headers::{ContentType, ContentLength, ContentRange, ContentRangeSpec, AcceptRanges, RangeUnit, Range, ByteRangeSpec, IfRange};
+use iron::headers::{ETag, EntityTag, LastModified, HttpDate, IfNoneMatch, IfModifiedSince, CacheControl, CacheDirective};
 use iron::status;
 use router::Router;
+use time;
 
 const index_bytes: &'static [u8] = include_bytes!("../../../html/server/index.html");
+// written by `npm run build` in html/server, see the README
 const javascript_package: &'static [u8] = include_bytes!("../../../html/server/dist/build.js");
 
 pub fn individual_video_handler(req: &mut Request) -> IronResult<Response> {
-    let ref query = req.extensions.get::<Router>();
-    match *query {
-        Some(q) => {
-            let uuid: &str = q.find("query").unwrap_or("bad_file");
-            let mut res: Response = Response::with((status::Ok, body_writer::VideoPageHttpWriter::new(uuid)));
-            res.headers.set(ContentType("video/mp4".parse().unwrap()));
-            Ok(res)
-        }
-        None => {
-            let res: Response = Response::with((status::NotFound, "Not Found!"));
-            Ok(res)
-        }
+    let clip_path = {
+        let clip_dir = req.extensions.get::<body_writer::ClipDirectory>().expect("failed to get clip directory");
+        req.extensions.get::<Router>().and_then(|q| q.find("query")).and_then(|q| body_writer::clip_path(clip_dir, q))
+    };
+    match clip_path {
+        Some(path) => serve_video(req, &path),
+        None => Ok(Response::with((status::NotFound, "Not Found!"))),
     }
 }
 
-pub fn control_panel_handler(req: &mut Request) -> IronResult<Response> {
-    let ref query = req.extensions.get::<Router>();
-    match *query {
-        Some(q) => {
-            let mut res = Response::with((status::NotFound, index_bytes));
-            res.headers.set(ContentType("text/html".parse().unwrap()));
-            Ok(res)
+pub fn export_download_handler(req: &mut Request) -> IronResult<Response> {
+    let export_path = {
+        let clip_dir = req.extensions.get::<body_writer::ClipDirectory>().expect("failed to get clip directory");
+        req.extensions.get::<Router>().and_then(|q| q.find("query")).and_then(|q| body_writer::export_path(clip_dir, q))
+    };
+    match export_path {
+        Some(path) => serve_video(req, &path),
+        None => Ok(Response::with((status::NotFound, "Not Found!"))),
+    }
+}
+
+/// Serves an MP4 with caching headers and single byte ranges, which players use to seek.
+fn serve_video(req: &Request, clip_path: &Path) -> IronResult<Response> {
+    let (file, length, etag, last_modified) = match file_validators(clip_path) {
+        Some(item) => item,
+        None => return Ok(Response::with((status::NotFound, "Not Found!"))),
+    };
+    if not_modified(req, &etag, &last_modified) {
+        return Ok(not_modified_response(etag, last_modified));
+    }
+
+    // a stale If-Range means the client's partial copy is out of date, so it gets the whole clip
+    let range = match req.headers.get::<IfRange>() {
+        Some(&IfRange::EntityTag(ref tag)) if !tag.strong_eq(&etag) => None,
+        Some(&IfRange::Date(ref date)) if date != &last_modified => None,
+        _ => req.headers.get::<Range>().cloned(),
+    };
+
+    let mut res = match range {
+        Some(Range::Bytes(ref specs)) if specs.len() == 1 => {
+            match satisfiable_range(&specs[0], length) {
+                Some((start, end)) => {
+                    let mut res = Response::with((status::PartialContent, body_writer::VideoPageHttpWriter::new(file, start, end - start + 1)));
+                    res.headers.set(ContentRange(ContentRangeSpec::Bytes { range: Some((start, end)), instance_length: Some(length) }));
+                    res.headers.set(ContentLength(end - start + 1));
+                    res
+                },
+                None => {
+                    let mut res = Response::with(status::RangeNotSatisfiable);
+                    res.headers.set(ContentRange(ContentRangeSpec::Bytes { range: None, instance_length: Some(length) }));
+                    return Ok(res);
+                },
+            }
         },
-        None => {
-            let res: Response = Response::with((status::NotFound, "Not Found!"));
-            Ok(res)
+        // multiple ranges would need a multipart body, which players never ask for
+        _ => {
+            let mut res = Response::with((status::Ok, body_writer::VideoPageHttpWriter::new(file, 0, length)));
+            res.headers.set(ContentLength(length));
+            res
+        },
+    };
+
+    res.headers.set(ContentType("video/mp4".parse().unwrap()));
+    res.headers.set(AcceptRanges(vec![RangeUnit::Bytes]));
+    res.headers.set(ETag(etag));
+    res.headers.set(LastModified(last_modified));
+    Ok(res)
+}
+
+pub fn thumbnail_handler(req: &mut Request) -> IronResult<Response> {
+    let thumbnail_path = {
+        let clip_dir = req.extensions.get::<body_writer::ClipDirectory>().expect("failed to get clip directory");
+        req.extensions.get::<Router>().and_then(|q| q.find("query")).and_then(|q| body_writer::thumbnail_path(clip_dir, q))
+    };
+    let thumbnail_path = match thumbnail_path {
+        Some(path) => path,
+        None => return Ok(Response::with((status::NotFound, "Not Found!"))),
+    };
+    let (file, length, etag, last_modified) = match file_validators(&thumbnail_path) {
+        Some(item) => item,
+        None => return Ok(Response::with((status::NotFound, "Not Found!"))),
+    };
+    if not_modified(req, &etag, &last_modified) {
+        return Ok(not_modified_response(etag, last_modified));
+    }
+
+    let mut res = Response::with((status::Ok, body_writer::VideoPageHttpWriter::new(file, 0, length)));
+    res.headers.set(ContentLength(length));
+    res.headers.set(ContentType::png());
+    res.headers.set(ETag(etag));
+    res.headers.set(LastModified(last_modified));
+    Ok(res)
+}
+
+pub fn live_stream_handler(req: &mut Request) -> IronResult<Response> {
+    let live_path = {
+        let clip_dir = req.extensions.get::<body_writer::ClipDirectory>().expect("failed to get clip directory");
+        let router = req.extensions.get::<Router>();
+        match (router.and_then(|q| q.find("feed")), router.and_then(|q| q.find("file"))) {
+            (Some(feed), Some(file)) => body_writer::live_path(clip_dir, feed, file),
+            _ => None,
+        }
+    };
+    let live_path = match live_path {
+        Some(path) => path,
+        None => return Ok(Response::with((status::NotFound, "Not Found!"))),
+    };
+    // segments disappear as the playlist rolls, so a miss here is normal
+    let (file, length) = match File::open(&live_path).and_then(|f| { let len = f.metadata()?.len(); Ok((f, len)) }) {
+        Ok(item) => item,
+        Err(_) => return Ok(Response::with((status::NotFound, "Not Found!"))),
+    };
+
+    let mut res = Response::with((status::Ok, body_writer::VideoPageHttpWriter::new(file, 0, length)));
+    res.headers.set(ContentLength(length));
+    if live_path.extension().map(|x| x == "m3u8").unwrap_or(false) {
+        res.headers.set(ContentType("application/vnd.apple.mpegurl".parse().unwrap()));
+        res.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+    } else {
+        res.headers.set(ContentType("video/mp2t".parse().unwrap()));
+    }
+    Ok(res)
+}
+
+/// Opens a file served with caching headers, along with its length and the validators derived from
+/// its length and modification time. `None` when it cannot be read.
+fn file_validators(path: &Path) -> Option<(File, u64, EntityTag, HttpDate)> {
+    let (file, meta) = match File::open(path).and_then(|f| { let meta = f.metadata()?; Ok((f, meta)) }) {
+        Ok(item) => item,
+        Err(_) => return None,
+    };
+    let length = meta.len();
+    let modified = meta.modified().ok().and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0);
+    let etag = EntityTag::strong(format!("{:x}-{:x}", length, modified));
+    let last_modified = HttpDate(time::at_utc(time::Timespec::new(modified as i64, 0)));
+    Some((file, length, etag, last_modified))
+}
+
+fn not_modified_response(etag: EntityTag, last_modified: HttpDate) -> Response {
+    let mut res = Response::with(status::NotModified);
+    res.headers.set(ETag(etag));
+    res.headers.set(LastModified(last_modified));
+    res
+}
+
+fn not_modified(req: &Request, etag: &EntityTag, last_modified: &HttpDate) -> bool {
+    match req.headers.get::<IfNoneMatch>() {
+        Some(&IfNoneMatch::Any) => true,
+        Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => match req.headers.get::<IfModifiedSince>() {
+            Some(&IfModifiedSince(ref since)) => last_modified.0.to_timespec() <= since.0.to_timespec(),
+            None => false,
         },
     }
 }
 
+/// Resolves a byte range against the clip length into inclusive `(start, end)` offsets.
+fn satisfiable_range(spec: &ByteRangeSpec, length: u64) -> Option<(u64, u64)> {
+    match *spec {
+        ByteRangeSpec::FromTo(from, to) if from < length && from <= to => Some((from, cmp::min(to, length - 1))),
+        ByteRangeSpec::AllFrom(from) if from < length => Some((from, length - 1)),
+        ByteRangeSpec::Last(count) if count > 0 && length > 0 => Some((length - cmp::min(count, length), length - 1)),
+        _ => None,
+    }
+}
+
+pub fn control_panel_handler(_: &mut Request) -> IronResult<Response> {
+    let mut res = Response::with((status::Ok, index_bytes));
+    res.headers.set(ContentType("text/html".parse().unwrap()));
+    Ok(res)
+}
+
 pub fn asset_handler(req: &mut Request) -> IronResult<Response> {
     let ref query = req.extensions.get::<Router>();
     match *query {
@@ -66,4 +216,32 @@ pub fn asset_handler(req: &mut Request) -> IronResult<Response> {
             Ok(res)
         },
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENGTH: u64 = 1000;
+
+    #[test]
+    fn serves_ranges_inside_the_clip() {
+        assert_eq!(satisfiable_range(&ByteRangeSpec::FromTo(0, 499), LENGTH), Some((0, 499)));
+        assert_eq!(satisfiable_range(&ByteRangeSpec::AllFrom(500), LENGTH), Some((500, 999)));
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(100), LENGTH), Some((900, 999)));
+    }
+
+    #[test]
+    fn clamps_ranges_that_run_past_the_end() {
+        assert_eq!(satisfiable_range(&ByteRangeSpec::FromTo(900, 1999), LENGTH), Some((900, 999)));
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(LENGTH + 1), LENGTH), Some((0, 999)));
+    }
+
+    #[test]
+    fn rejects_ranges_starting_past_the_end() {
+        assert_eq!(satisfiable_range(&ByteRangeSpec::FromTo(LENGTH, LENGTH + 10), LENGTH), None);
+        assert_eq!(satisfiable_range(&ByteRangeSpec::AllFrom(LENGTH), LENGTH), None);
+        assert_eq!(satisfiable_range(&ByteRangeSpec::FromTo(500, 499), LENGTH), None);
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(0), LENGTH), None);
+        assert_eq!(satisfiable_range(&ByteRangeSpec::Last(100), 0), None);
+    }
+}
@@ -0,0 +1,13 @@
+-- A database written before schema versioning existed: user_version is 0,
+-- a game row was inserted on every boot and foreign keys were never enforced.
+CREATE TABLE IF NOT EXISTS games (id INTEGER PRIMARY KEY ASC, date TEXT);
+CREATE TABLE IF NOT EXISTS plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE IF NOT EXISTS clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date) VALUES (1, '2017-09-01');
+INSERT INTO games (id, date) VALUES (2, '2017-09-01');
+INSERT INTO plays (id, game_id) VALUES (1, 2);
+INSERT INTO plays (id, game_id) VALUES (2, 2);
+INSERT INTO clips (id, uuid, play_id) VALUES (1, '3f1c2e1a-2d1b-4c55-9d8e-6a2b7f0c9e11', 1);
+INSERT INTO clips (id, uuid, play_id) VALUES (2, '8b0f4d62-5e7a-4f3b-a1c9-0d2e6f7a8b93', 1);
+INSERT INTO clips (id, uuid, play_id) VALUES (3, 'c4e5f6a7-b8c9-4d0e-9f1a-2b3c4d5e6f70', 2);
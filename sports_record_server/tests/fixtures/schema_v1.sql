@@ -0,0 +1,10 @@
+-- Schema version 1: the original games, plays and clips tables.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date) VALUES (1, '2017-09-08');
+INSERT INTO plays (id, game_id) VALUES (1, 1);
+INSERT INTO clips (id, uuid, play_id) VALUES (1, '0a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d', 1);
+
+PRAGMA user_version = 1;
@@ -0,0 +1,10 @@
+-- Schema version 2: games gain a name, opponent and closed flag; plays gain down and distance metadata.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-09-15', 'Week 3', 'Central', 1);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes) VALUES (1, 1, 3, 7, 2, 'Screen left');
+INSERT INTO clips (id, uuid, play_id) VALUES (1, '5d6e7f80-9a1b-4c2d-8e3f-4a5b6c7d8e9f', 1);
+
+PRAGMA user_version = 2;
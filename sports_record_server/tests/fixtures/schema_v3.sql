@@ -0,0 +1,14 @@
+-- Schema version 3: plays record when they started and clips where they start within their play.
+-- Clips recorded before version 3 keep NULL offsets.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, started_at INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, start_offset INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-09-22', 'Week 4', 'Westfield', 0);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (1, 1, 1, 10, 1, NULL, NULL);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (2, 1, 2, 4, 1, 'Dive right', 1506117600000000);
+INSERT INTO clips (id, uuid, play_id, start_offset) VALUES (1, '1e2d3c4b-5a69-4788-97a6-b5c4d3e2f1a0', 1, NULL);
+INSERT INTO clips (id, uuid, play_id, start_offset) VALUES (2, '6f5e4d3c-2b1a-4098-8f7e-6d5c4b3a2918', 2, 0);
+INSERT INTO clips (id, uuid, play_id, start_offset) VALUES (3, 'a9b8c7d6-e5f4-4a3b-92c1-d0e9f8a7b6c5', 2, 41667);
+
+PRAGMA user_version = 3;
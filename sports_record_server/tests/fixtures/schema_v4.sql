@@ -0,0 +1,12 @@
+-- Schema version 4: clips record which client recorded them, so a replayed play replaces only that client's clips.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, started_at INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, start_offset INTEGER, client_id TEXT, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-09-29', 'Week 5', 'Lakeview', 0);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (1, 1, 1, 10, 1, 'Sweep left', 1506722400000000);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id) VALUES (1, 'b1c2d3e4-f5a6-4b7c-8d9e-0f1a2b3c4d5e', 1, NULL, NULL);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id) VALUES (2, 'c2d3e4f5-a6b7-4c8d-9e0f-1a2b3c4d5e6f', 1, 12500, 'endzone');
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id) VALUES (3, 'd3e4f5a6-b7c8-4d9e-8f1a-2b3c4d5e6f70', 1, 0, 'sideline');
+
+PRAGMA user_version = 4;
@@ -0,0 +1,11 @@
+-- Schema version 5: clips name the thumbnail written next to them in the output directory.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, started_at INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, start_offset INTEGER, client_id TEXT, thumbnail TEXT, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-10-06', 'Week 6', 'Northridge', 0);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (1, 1, 3, 2, 3, 'QB sneak', 1507327200000000);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail) VALUES (1, 'e4f5a6b7-c8d9-4e0f-9a1b-2c3d4e5f6a7b', 1, 0, 'endzone', 'thumb_e4f5a6b7-c8d9-4e0f-9a1b-2c3d4e5f6a7b.png');
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail) VALUES (2, 'f5a6b7c8-d9e0-4f1a-8b2c-3d4e5f6a7b8c', 1, 33333, 'sideline', NULL);
+
+PRAGMA user_version = 5;
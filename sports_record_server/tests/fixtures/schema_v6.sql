@@ -0,0 +1,11 @@
+-- Schema version 6: clips record how long they run in microseconds.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, started_at INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, start_offset INTEGER, client_id TEXT, thumbnail TEXT, duration INTEGER, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-10-13', 'Week 7', 'Riverside', 0);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (1, 1, 1, 10, 2, 'Play action', 1507932000000000);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail, duration) VALUES (1, '0b1c2d3e-4f5a-4b6c-9d7e-8f9a0b1c2d3e', 1, 0, 'endzone', 'thumb_0b1c2d3e-4f5a-4b6c-9d7e-8f9a0b1c2d3e.png', 8433333);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail, duration) VALUES (2, '1c2d3e4f-5a6b-4c7d-8e9f-0a1b2c3d4e5f', 1, 20833, 'sideline', NULL, NULL);
+
+PRAGMA user_version = 6;
@@ -0,0 +1,12 @@
+-- Schema version 7: clips the server rendered from a play's other clips are marked as derived.
+CREATE TABLE games (id INTEGER PRIMARY KEY ASC, date TEXT, name TEXT, opponent TEXT, closed INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE plays (id INTEGER PRIMARY KEY ASC, game_id INTEGER, down INTEGER, distance INTEGER, quarter INTEGER, notes TEXT, started_at INTEGER, FOREIGN KEY(game_id) REFERENCES games(id));
+CREATE TABLE clips (id INTEGER PRIMARY KEY ASC, uuid TEXT, play_id INTEGER, start_offset INTEGER, client_id TEXT, thumbnail TEXT, duration INTEGER, derived INTEGER NOT NULL DEFAULT 0, FOREIGN KEY(play_id) REFERENCES plays(id));
+
+INSERT INTO games (id, date, name, opponent, closed) VALUES (1, '2017-10-20', 'Week 8', 'Eastwood', 0);
+INSERT INTO plays (id, game_id, down, distance, quarter, notes, started_at) VALUES (1, 1, 2, 6, 4, 'Slant right', 1508536800000000);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail, duration, derived) VALUES (1, '2d3e4f5a-6b7c-4d8e-9f0a-1b2c3d4e5f6a', 1, 0, 'endzone', 'thumb_2d3e4f5a-6b7c-4d8e-9f0a-1b2c3d4e5f6a.png', 6100000, 0);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail, duration, derived) VALUES (2, '3e4f5a6b-7c8d-4e9f-8a1b-2c3d4e5f6a7b', 1, 16667, 'sideline', 'thumb_3e4f5a6b-7c8d-4e9f-8a1b-2c3d4e5f6a7b.png', 6066667, 0);
+INSERT INTO clips (id, uuid, play_id, start_offset, client_id, thumbnail, duration, derived) VALUES (3, '4f5a6b7c-8d9e-4f0a-9b2c-3d4e5f6a7b8c', 1, 0, NULL, 'thumb_4f5a6b7c-8d9e-4f0a-9b2c-3d4e5f6a7b8c.png', 6133333, 1);
+
+PRAGMA user_version = 7;